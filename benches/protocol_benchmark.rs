@@ -0,0 +1,58 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rust_live_server::{
+    api::encoding::Encoding,
+    ot::operations::{DeleteOperation, InsertOperation, Operation},
+};
+
+fn cases() -> [(String, Operation); 3] {
+    [
+        (
+            String::from("Insert(2, x)"),
+            Box::new(InsertOperation::new(2, 10, String::from("x"))),
+        ),
+        (
+            String::from("Insert(6890, <long>)"),
+            Box::new(InsertOperation::new(6890, 1000, "0123456789".repeat(100))),
+        ),
+        (
+            String::from("Delete(10, 100)"),
+            Box::new(DeleteOperation::new(10, 1000, 100)),
+        ),
+    ]
+}
+
+fn benchmark_encode(c: &mut Criterion) {
+    for (test_name, operation) in cases() {
+        // the throughput shows the size of the frame in each encoding
+        let mut group = c.benchmark_group(format!("Encode {test_name}"));
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let frame = encoding.encode_operation(&operation);
+            group.throughput(Throughput::Bytes(frame.as_bytes().len() as u64));
+            group.bench_function(encoding.protocol(), |b| {
+                b.iter(|| {
+                    black_box(encoding.encode_operation(black_box(&operation)));
+                });
+            });
+        }
+        group.finish();
+    }
+}
+
+fn benchmark_decode(c: &mut Criterion) {
+    for (test_name, operation) in cases() {
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let frame = encoding.encode_operation(&operation);
+
+            let test_name = format!("Decode {test_name} with {}", encoding.protocol());
+            c.bench_function(&test_name, |b| {
+                b.iter(|| {
+                    black_box(encoding.decode_operation(black_box(frame.as_bytes())));
+                });
+            });
+        }
+    }
+}
+
+criterion_group!(encode, benchmark_encode);
+criterion_group!(decode, benchmark_decode);
+criterion_main!(encode, decode);
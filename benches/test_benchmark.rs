@@ -97,7 +97,7 @@ fn benchmark_insert(c: &mut Criterion) {
     ];
     for (idx, (test_name, position, insert_text, mut apply_text)) in cases.into_iter().enumerate() {
         let op = InsertOperation::new(position, 0, insert_text);
-        let test_name = String::from(idx.to_string()) + " case. " + &test_name;
+        let test_name = idx.to_string() + " case. " + &test_name;
         c.bench_function(&test_name, |b| {
             b.iter(|| {
                 black_box(op.apply_return(black_box(&mut apply_text)));
@@ -172,7 +172,7 @@ fn benchmark_delete(c: &mut Criterion) {
     ];
     for (idx, (test_name, position, len, mut apply_text)) in cases.into_iter().enumerate() {
         let op = DeleteOperation::new(position, 0, len);
-        let test_name = String::from(idx.to_string()) + " case. " + &test_name;
+        let test_name = idx.to_string() + " case. " + &test_name;
         c.bench_function(&test_name, |b| {
             b.iter(|| {
                 black_box(op.apply_return(black_box(&mut apply_text)));
@@ -0,0 +1,36 @@
+#![no_main]
+
+mod operation;
+
+use libfuzzer_sys::fuzz_target;
+use operation::FuzzOperation;
+use rust_live_server::ot::document::{DocumentMem, DocumentTrait};
+
+fuzz_target!(|input: (String, Vec<FuzzOperation>)| {
+    let (mut text, operations) = input;
+    let mut document = DocumentMem::new();
+
+    for operation in operations {
+        let operation = operation.into_operation();
+
+        // operations which fit the text are applied the same way by both methods
+        if operation.can_apply(&text) {
+            let returned = operation.apply_return(&mut text);
+            operation.apply(&mut text);
+            assert_eq!(returned, text);
+        }
+
+        // the document rejects the operations which don't fit it instead of panicking
+        let revision = document.revision();
+        match document.apply(operation) {
+            Some(applied) => assert_eq!(applied.revision(), revision),
+            None => assert_eq!(document.revision(), revision),
+        }
+    }
+
+    let mut replayed = String::new();
+    for operation in document.operations(0) {
+        operation.apply(&mut replayed);
+    }
+    assert_eq!(replayed, document.text());
+});
@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_live_server::api::fuzzing::decode_payload;
+
+fuzz_target!(|payload: &[u8]| decode_payload(payload));
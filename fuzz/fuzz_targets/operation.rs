@@ -0,0 +1,115 @@
+use arbitrary::Arbitrary;
+use rust_live_server::ot::{
+    operations::{
+        DeleteOperation, InsertOperation, MoveOperation, Operation, ReplaceOperation,
+        TransactionOperation,
+    },
+    rich_text::{Attributes, FormatOperation},
+};
+
+/// Operation with small positions, so that most of them hit the text
+#[derive(Arbitrary, Debug)]
+pub enum FuzzOperation {
+    Insert {
+        position: u8,
+        revision: u8,
+        text: String,
+    },
+    Delete {
+        position: u8,
+        revision: u8,
+        len: u8,
+    },
+    Move {
+        position: u8,
+        revision: u8,
+        len: u8,
+        to: u8,
+    },
+    Replace {
+        position: u8,
+        revision: u8,
+        len: u8,
+        text: String,
+    },
+    /// Each operation is based on the text left by the previous ones
+    Transaction {
+        revision: u8,
+        operations: Vec<FuzzOperation>,
+    },
+    Format {
+        position: u8,
+        revision: u8,
+        len: u8,
+        /// `None` removes the attribute
+        bold: Option<bool>,
+    },
+}
+
+impl FuzzOperation {
+    pub fn into_operation(self) -> Operation {
+        match self {
+            FuzzOperation::Insert {
+                position,
+                revision,
+                text,
+            } => Box::new(InsertOperation::new(position.into(), revision.into(), text)),
+            FuzzOperation::Delete {
+                position,
+                revision,
+                len,
+            } => Box::new(DeleteOperation::new(
+                position.into(),
+                revision.into(),
+                len.into(),
+            )),
+            FuzzOperation::Move {
+                position,
+                revision,
+                len,
+                to,
+            } => Box::new(MoveOperation::new(
+                position.into(),
+                revision.into(),
+                len.into(),
+                to.into(),
+            )),
+            FuzzOperation::Replace {
+                position,
+                revision,
+                len,
+                text,
+            } => Box::new(ReplaceOperation::new(
+                position.into(),
+                revision.into(),
+                len.into(),
+                text,
+            )),
+            FuzzOperation::Transaction {
+                revision,
+                operations,
+            } => Box::new(TransactionOperation::new(
+                revision.into(),
+                operations
+                    .into_iter()
+                    .map(FuzzOperation::into_operation)
+                    .collect(),
+            )),
+            FuzzOperation::Format {
+                position,
+                revision,
+                len,
+                bold,
+            } => {
+                let bold = bold.map_or(serde_json::Value::Null, serde_json::Value::Bool);
+                let attributes = Attributes::from([(String::from("bold"), bold)]);
+                Box::new(FormatOperation::new(
+                    position.into(),
+                    revision.into(),
+                    len.into(),
+                    attributes,
+                ))
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+#![no_main]
+
+mod operation;
+
+use libfuzzer_sys::fuzz_target;
+use operation::FuzzOperation;
+
+// Two concurrent operations on the same text converge in both orders (TP1)
+fuzz_target!(|input: (String, FuzzOperation, FuzzOperation)| {
+    let (text, first, second) = input;
+    let (first, second) = (first.into_operation(), second.into_operation());
+    if !first.can_apply(&text) || !second.can_apply(&text) {
+        return;
+    }
+
+    // the server has applied `first`, then `second` comes
+    let mut second_transformed = second.boxed_clone();
+    second_transformed.transform_relative_to(first.as_ref());
+    // the author of `second` receives `first`
+    let mut first_transformed = first.boxed_clone();
+    first_transformed.transform_relative_to_later(second.as_ref());
+
+    let mut server_text = text.clone();
+    first.apply(&mut server_text);
+    assert!(second_transformed.can_apply(&server_text));
+    second_transformed.apply(&mut server_text);
+
+    let mut client_text = text;
+    second.apply(&mut client_text);
+    assert!(first_transformed.can_apply(&client_text));
+    first_transformed.apply(&mut client_text);
+
+    assert_eq!(server_text, client_text);
+});
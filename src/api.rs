@@ -1,64 +1,266 @@
-use actix_web::{dev::Server, get, rt, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use actix_web::{
+    dev::Server,
+    get,
+    http::header::{HeaderValue, SEC_WEBSOCKET_PROTOCOL},
+    put, rt, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+};
 
-use crate::collaboration::manager::Manager;
+use actix_ws::{CloseCode, CloseReason};
+use std::{
+    net::TcpListener,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tracing::Instrument;
 
-use self::contracts::WsConnectionQuery;
+use crate::{
+    collaboration::{access::AccessError, manager::Manager},
+    metrics,
+};
 
-mod contracts;
+use self::{
+    contracts::{ClientQuery, DocumentCreationContract, RoleChangeContract, WsConnectionQuery},
+    encoding::Encoding,
+    lifecycle::Lifecycle,
+    subscriptions::Subscriptions,
+};
+
+pub use self::lifecycle::ShutdownHandle;
+
+mod audit;
+mod branches;
+mod checkpoints;
+mod content;
+pub(crate) mod contracts;
+pub mod encoding;
+mod export;
+mod fallback;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
 mod handlers;
+mod lifecycle;
+mod playback;
+mod protocol;
+mod subscriptions;
 
 #[get("/")]
 async fn hello() -> impl Responder {
     HttpResponse::Ok().body("Hello world!")
 }
 
+/// Number of the next `/ws` connection, it tells apart the connections of one client,
+/// e.g. only the connection which submitted an operation receives its `ack`
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
 #[get("/ws")]
 async fn echo_ws(
     req: HttpRequest,
     stream: web::Payload,
     query: web::Query<WsConnectionQuery>, // more detailed analysis in function body maybe needed
     session_manager: web::Data<Manager>,
+    lifecycle: web::Data<Lifecycle>,
 ) -> impl Responder {
-    let (res, session, msg_stream) = actix_ws::handle(&req, stream).unwrap();
+    let (mut res, session, mut msg_stream) = actix_ws::handle(&req, stream).unwrap();
+    let encoding = match req
+        .headers()
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|protocols| protocols.to_str().ok())
+        .and_then(Encoding::negotiate)
+    {
+        Some(encoding) => {
+            res.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(encoding.protocol()),
+            );
+            encoding
+        }
+        None => Encoding::Json,
+    };
     let q = query.0;
     let (client_name, document_id) = (q.client, q.document);
+    let connection_number = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+    let span = tracing::info_span!(
+        "connection",
+        client = %client_name,
+        connection = connection_number,
+    );
 
-    let (cur_client_name, cur_document_id) = (client_name.clone(), document_id.clone());
-    let cur_session_manager = session_manager.clone();
-    let (sender, receiver) = tokio::task::spawn_blocking(move || {
-        cur_session_manager
-            .as_mut()
-            .connect(cur_client_name, cur_document_id)
-    })
-    .await
-    .unwrap();
+    let connection = async move {
+        tracing::info!(document = document_id.as_deref(), "connected");
+        let Some(protocol) =
+            handlers::websocket_handshake(session.clone(), &mut msg_stream, encoding).await
+        else {
+            return;
+        };
 
-    {
-        let session = session.clone();
-        rt::spawn(async move {
-            rt::spawn(handlers::websocket_reader(session, msg_stream, sender))
-                .await
-                .unwrap();
-        });
-        session_manager
-            .as_mut()
-            .disconnect(client_name, document_id)
-    }
-    rt::spawn(handlers::websocket_writer(session, receiver)); // TODO maybe rt::spawn => tokio::spawn?
+        if document_id.is_none() && !protocol.multiplexed() {
+            let _ = session
+                .close(Some(CloseReason {
+                    code: CloseCode::Policy,
+                    description: Some(String::from(
+                        "document is required before protocol version 3",
+                    )),
+                }))
+                .await;
+            return;
+        }
+
+        let (mut subscriptions, events) =
+            Subscriptions::new(client_name, connection_number, session_manager);
+        if let Some(document_id) = &document_id {
+            subscriptions.subscribe(document_id.clone(), None).await;
+        }
+
+        let writer = rt::spawn(
+            handlers::websocket_writer(session.clone(), events, protocol.clone()).in_current_span(),
+        ); // TODO maybe rt::spawn => tokio::spawn?
+        let close = rt::spawn(
+            handlers::websocket_reader(
+                session.clone(),
+                msg_stream,
+                subscriptions,
+                document_id,
+                protocol,
+                lifecycle.get_ref().clone(),
+            )
+            .in_current_span(),
+        )
+        .await
+        .unwrap();
+        // the writer stops when the subscriptions of the reader are dropped.
+        // The close frame goes from the last handle of the session,
+        // actix-ws doesn't end the response if the handles are dropped after the frame is sent
+        let _ = writer.await;
+        if let Some(reason) = close {
+            let _ = session.close(Some(reason)).await;
+        }
+        tracing::info!("disconnected");
+    };
+    rt::spawn(connection.instrument(span));
 
     res
 }
 
+/// Creates an empty document of the type from the body, the client becomes its owner
+#[put("/documents/{document}")]
+async fn create_document(
+    path: web::Path<String>,
+    query: web::Query<ClientQuery>,
+    body: web::Json<DocumentCreationContract>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    match session_manager.create(path.into_inner(), query.into_inner().client, body.kind) {
+        Ok(()) => HttpResponse::Created().finish(),
+        Err(err) => access_error_response(err),
+    }
+}
+
+/// Changes the role of the client on behalf of the owner from the query
+#[put("/documents/{document}/roles/{client}")]
+async fn set_role(
+    path: web::Path<(String, String)>,
+    query: web::Query<ClientQuery>,
+    body: web::Json<RoleChangeContract>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let (document_id, client_name) = path.into_inner();
+    let actor = query.into_inner().client;
+    let role = body.into_inner().role;
+
+    match session_manager.set_role(&document_id, &actor, client_name, role) {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => access_error_response(err),
+    }
+}
+
+#[get("/metrics")]
+async fn get_metrics(session_manager: web::Data<Manager>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render(&session_manager))
+}
+
+fn access_error_response(err: AccessError) -> HttpResponse {
+    match err {
+        AccessError::DocumentNotFound | AccessError::CheckpointNotFound => {
+            HttpResponse::NotFound().body(err.to_string())
+        }
+        AccessError::NotOwner | AccessError::NoAccess | AccessError::ReadOnly => {
+            HttpResponse::Forbidden().body(err.to_string())
+        }
+        AccessError::LastOwner
+        | AccessError::DocumentExists
+        | AccessError::CheckpointExists
+        | AccessError::BranchMerged => HttpResponse::Conflict().body(err.to_string()),
+        AccessError::InvalidOperation | AccessError::RevisionNotFound | AccessError::NotBranch => {
+            HttpResponse::UnprocessableEntity().body(err.to_string())
+        }
+    }
+}
+
 pub fn get_server_future() -> Server {
+    get_server_future_on(TcpListener::bind(("127.0.0.1", 8080)).unwrap())
+}
+
+/// Runs the server on an already bound listener, e.g. on a random port in tests.
+///
+/// The server stops on SIGTERM or Ctrl+C.
+pub fn get_server_future_on(listener: TcpListener) -> Server {
+    run(listener, true).0
+}
+
+/// Runs the server on an already bound listener and returns the handle of its graceful shutdown.
+///
+/// The server doesn't handle the signals, the caller decides when to shut it down.
+pub fn start_on(listener: TcpListener) -> (Server, ShutdownHandle) {
+    run(listener, false)
+}
+
+/// Runs the server, actix handles the signals only if `signals` is set
+fn run(listener: TcpListener, signals: bool) -> (Server, ShutdownHandle) {
     let manager = web::Data::new(Manager::new());
+    let lifecycle = web::Data::new(Lifecycle::default());
 
-    HttpServer::new(move || {
+    let app_manager = web::Data::clone(&manager);
+    let app_lifecycle = web::Data::clone(&lifecycle);
+    let server = HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::clone(&manager))
+            .app_data(web::Data::clone(&app_manager))
+            .app_data(web::Data::clone(&app_lifecycle))
             .service(hello)
+            .service(lifecycle::healthz)
+            .service(lifecycle::readyz)
             .service(echo_ws)
-    })
-    .bind(("127.0.0.1", 8080))
-    .unwrap()
-    .run()
+            .service(create_document)
+            .service(set_role)
+            .service(get_metrics)
+            .service(audit::audit)
+            .service(audit::blame)
+            .service(branches::create_branch)
+            .service(branches::merge_branch)
+            .service(checkpoints::checkpoints)
+            .service(checkpoints::create_checkpoint)
+            .service(checkpoints::diff_checkpoint)
+            .service(checkpoints::restore_checkpoint)
+            .service(content::replace_content)
+            .service(export::export_document)
+            .service(export::import_document)
+            .service(playback::playback)
+            .service(playback::playback_ws)
+            .service(fallback::events)
+            .service(fallback::poll)
+            .service(fallback::submit)
+    });
+    let server = if signals {
+        server
+    } else {
+        server.disable_signals()
+    };
+    let server = server.listen(listener).unwrap().run();
+
+    let shutdown = ShutdownHandle {
+        lifecycle: lifecycle.get_ref().clone(),
+        server: server.handle(),
+        manager,
+    };
+    (server, shutdown)
 }
@@ -0,0 +1,31 @@
+//! Who changed a document and when
+
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::collaboration::manager::Manager;
+
+use super::contracts::{AuditQueryContract, AuditRecordJSONContract, BlameJSONContract};
+
+/// Returns the applied operations of the document filtered by author and time, oldest first
+#[get("/documents/{document}/audit")]
+async fn audit(
+    path: web::Path<String>,
+    query: web::Query<AuditQueryContract>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let Some(records) = session_manager.audit(&path, &query.into_inner().into_query()) else {
+        return HttpResponse::NotFound().body("document not found");
+    };
+    let records: Vec<_> = records.iter().map(AuditRecordJSONContract::new).collect();
+    HttpResponse::Ok().json(records)
+}
+
+/// Returns the byte ranges of the current text with the operations which inserted them
+#[get("/documents/{document}/blame")]
+async fn blame(path: web::Path<String>, session_manager: web::Data<Manager>) -> impl Responder {
+    let Some(ranges) = session_manager.blame(&path) else {
+        return HttpResponse::NotFound().body("document not found");
+    };
+    let ranges: Vec<_> = ranges.iter().map(BlameJSONContract::new).collect();
+    HttpResponse::Ok().json(ranges)
+}
@@ -0,0 +1,43 @@
+//! Drafts forked from documents and merged back
+
+use actix_web::{post, put, web, HttpResponse, Responder};
+
+use crate::collaboration::manager::Manager;
+
+use super::{
+    access_error_response,
+    contracts::{ClientQuery, MergeJSONContract, RevisionJSONContract, RevisionQuery},
+};
+
+/// Forks the document at the revision from the query, the current one by default.
+///
+/// The branch is a new document owned by the client, returns the fork point.
+#[put("/documents/{document}/branches/{branch}")]
+async fn create_branch(
+    path: web::Path<(String, String)>,
+    query: web::Query<RevisionQuery>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let (document_id, branch_id) = path.into_inner();
+    let RevisionQuery { client, revision } = query.into_inner();
+    match session_manager.branch(&document_id, branch_id.clone(), client, revision) {
+        Ok(revision) => {
+            HttpResponse::Created().json(RevisionJSONContract::new(&branch_id, revision))
+        }
+        Err(err) => access_error_response(err),
+    }
+}
+
+/// Applies the operations of the branch to its parent, returns them with the text
+/// which both documents deleted
+#[post("/documents/{document}/merge")]
+async fn merge_branch(
+    path: web::Path<String>,
+    query: web::Query<ClientQuery>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    match session_manager.merge(&path, &query.into_inner().client) {
+        Ok(merge) => HttpResponse::Ok().json(MergeJSONContract::new(&merge)),
+        Err(err) => access_error_response(err),
+    }
+}
@@ -0,0 +1,85 @@
+//! Named revisions of documents and restoring them
+
+use actix_web::{get, post, put, web, HttpResponse, Responder};
+
+use crate::collaboration::manager::Manager;
+
+use super::{
+    access_error_response,
+    contracts::{
+        CheckpointDiffQuery, CheckpointJSONContract, ClientQuery, OperationJSONFreeCopy,
+        RevisionQuery,
+    },
+};
+
+/// Returns the checkpoints of the document ordered by revision
+#[get("/documents/{document}/checkpoints")]
+async fn checkpoints(
+    path: web::Path<String>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let Some(checkpoints) = session_manager.checkpoints(&path) else {
+        return HttpResponse::NotFound().body("document not found");
+    };
+    let checkpoints: Vec<_> = checkpoints
+        .iter()
+        .map(CheckpointJSONContract::new)
+        .collect();
+    HttpResponse::Ok().json(checkpoints)
+}
+
+/// Names the revision from the query, the current one by default
+#[put("/documents/{document}/checkpoints/{name}")]
+async fn create_checkpoint(
+    path: web::Path<(String, String)>,
+    query: web::Query<RevisionQuery>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let (document_id, name) = path.into_inner();
+    let RevisionQuery { client, revision } = query.into_inner();
+    match session_manager.create_checkpoint(&document_id, client, name, revision) {
+        Ok(checkpoint) => HttpResponse::Created().json(CheckpointJSONContract::new(&checkpoint)),
+        Err(err) => access_error_response(err),
+    }
+}
+
+/// Returns the insertions and deletions which turn the text at the checkpoint
+/// into the text at the checkpoint `to` or into the current text
+#[get("/documents/{document}/checkpoints/{name}/diff")]
+async fn diff_checkpoint(
+    path: web::Path<(String, String)>,
+    query: web::Query<CheckpointDiffQuery>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let (document_id, name) = path.into_inner();
+    let to = query.into_inner().to;
+    match session_manager.diff_checkpoints(&document_id, &name, to.as_deref()) {
+        Ok(operations) => {
+            let operations: Vec<_> = operations
+                .iter()
+                .map(OperationJSONFreeCopy::from_operation)
+                .collect();
+            HttpResponse::Ok().json(operations)
+        }
+        Err(err) => access_error_response(err),
+    }
+}
+
+/// Brings back the content at the checkpoint with a new operation of the client.
+///
+/// Returns the applied operation, nothing if the content is the same.
+#[post("/documents/{document}/checkpoints/{name}/restore")]
+async fn restore_checkpoint(
+    path: web::Path<(String, String)>,
+    query: web::Query<ClientQuery>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let (document_id, name) = path.into_inner();
+    match session_manager.restore(&document_id, query.into_inner().client, &name) {
+        Ok(Some(operation)) => {
+            HttpResponse::Ok().json(OperationJSONFreeCopy::from_operation(&operation))
+        }
+        Ok(None) => HttpResponse::NoContent().finish(),
+        Err(err) => access_error_response(err),
+    }
+}
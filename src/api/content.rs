@@ -0,0 +1,43 @@
+//! Whole texts of documents
+
+use actix_web::{put, web, HttpResponse, Responder};
+
+use crate::{
+    collaboration::{manager::Manager, sessions::Submission},
+    ot::{
+        diff::diff,
+        operations::{Operation, TransactionOperation},
+    },
+};
+
+use super::{
+    access_error_response,
+    contracts::{ClientQuery, OperationJSONFreeCopy},
+};
+
+/// Replaces the text of the document with the body.
+///
+/// The difference from the current text is submitted as one transaction, so it merges
+/// with the concurrent edits. Returns the applied transaction, nothing if the text is the same.
+#[put("/documents/{document}/content")]
+async fn replace_content(
+    path: web::Path<String>,
+    query: web::Query<ClientQuery>,
+    body: String,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let Some((text, revision)) = session_manager.content(&path) else {
+        return HttpResponse::NotFound().body("document not found");
+    };
+    let operations = diff(&text, &body, revision);
+    if operations.is_empty() {
+        return HttpResponse::NoContent().finish();
+    }
+
+    let operation: Operation = Box::new(TransactionOperation::new(revision, operations));
+    let submission = Submission::new(query.into_inner().client, operation);
+    match session_manager.submit(&path, submission) {
+        Ok(operation) => HttpResponse::Ok().json(OperationJSONFreeCopy::from_operation(&operation)),
+        Err(err) => access_error_response(err),
+    }
+}
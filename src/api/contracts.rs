@@ -1,104 +1,770 @@
-use crate::ot::operations::{self, DeleteOperation, InsertOperation, Operation, OperationTrait};
+use std::{
+    ops::Range,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    collaboration::{
+        access::Role,
+        audit::{AuditEntry, AuditQuery, AuditRecord, BlameRange},
+        checkpoints::Checkpoint,
+        sessions::Merge,
+    },
+    ot::{
+        document::DocumentKind,
+        json::{JsonAction, JsonOperation, PathSegment},
+        merge::Conflict,
+        operations::{
+            self, DeleteOperation, InsertOperation, MoveOperation, Operation, OperationTrait,
+            ReplaceOperation, TransactionOperation,
+        },
+        rich_text::{Attributes, FormatOperation},
+        sequence::{SequenceAction, SequenceOperation},
+    },
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Debug)]
 pub(super) struct WsConnectionQuery {
-    pub document: String,
+    /// Required before the third protocol version,
+    /// since then the documents are subscribed with messages
+    pub document: Option<String>,
+    pub client: String,
+}
+
+/// Identity of the client for the HTTP transports
+#[derive(Deserialize, Debug)]
+pub(super) struct ClientQuery {
+    pub client: String,
+}
+
+/// Body of the request which creates a document
+#[derive(Deserialize, Debug)]
+pub(super) struct DocumentCreationContract {
+    #[serde(rename = "type", default)]
+    pub kind: DocumentKind,
+}
+
+/// Client and the revision of the document it refers to, the current one by default
+#[derive(Deserialize, Debug)]
+pub(super) struct RevisionQuery {
+    pub client: String,
+    pub revision: Option<usize>,
+}
+
+/// The checkpoint to compare with, the current text by default
+#[derive(Deserialize, Debug)]
+pub(super) struct CheckpointDiffQuery {
+    pub to: Option<String>,
+}
+
+/// Client who watches a playback, its revisions, the end is exclusive,
+/// and how much faster it goes than it was edited
+#[derive(Deserialize, Debug)]
+pub(super) struct PlaybackQuery {
+    pub client: String,
+    #[serde(default)]
+    pub from: usize,
+    pub to: Option<usize>,
+    pub speed: Option<f64>,
+}
+
+/// Format of an exported document
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum ExportFormat {
+    /// The current text only
+    #[default]
+    Text,
+    /// JSON lines: a [`SnapshotHeaderContract`] and the operations with their stamps
+    Jsonl,
+    /// The same as the JSON lines in one MessagePack value
+    Archive,
+}
+
+/// Client who exports a document and the format of the response
+#[derive(Deserialize, Debug)]
+pub(super) struct ExportQuery {
+    pub client: String,
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// Client who imports a document and the format of the body
+#[derive(Deserialize, Debug)]
+pub(super) struct ImportQuery {
     pub client: String,
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct PollQuery {
+    /// The first revision the client doesn't know
+    pub since: usize,
+}
+
+/// Filter of the audit log, the times are milliseconds since the Unix epoch
+#[derive(Deserialize, Debug)]
+pub(super) struct AuditQueryContract {
+    pub author: Option<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
 }
 
+impl AuditQueryContract {
+    pub fn into_query(self) -> AuditQuery {
+        let time = |millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis);
+        AuditQuery {
+            author: self.author,
+            from: self.from.map(time),
+            to: self.to.map(time),
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize)]
 enum OperationType {
     INSERT,
     DELETE,
+    MOVE,
+    REPLACE,
+    TRANSACTION,
+    FORMAT,
+    JSON,
+    LIST,
+}
+
+/// Action of an operation on a JSON or a list document
+#[derive(Serialize)]
+#[serde(untagged)]
+pub(crate) enum ActionJSONContract<'a> {
+    Json(&'a JsonAction),
+    List(&'a SequenceAction<serde_json::Value>),
+}
+
+/// Part of the range of a format operation
+#[derive(Serialize, Deserialize)]
+pub(crate) struct FormatSpanJSONContract<A> {
+    length: usize,
+    attributes: A,
+}
+
+/// Position, length and ranges of a deletion or a move
+type RangesJSONContract<'a> = (Option<usize>, Option<usize>, Option<&'a [Range<usize>]>);
+
+/// A single range is sent as a position and a length, several ones as they are
+fn split_ranges(ranges: &[Range<usize>]) -> RangesJSONContract<'_> {
+    match ranges {
+        [range] => (Some(range.start), Some(range.len()), None),
+        _ => (None, None, Some(ranges)),
+    }
 }
 
 // IDK if this structure is needed
 #[derive(Serialize)]
-pub(super) struct OperationJSONFreeCopy<'a> {
+pub(crate) struct OperationJSONFreeCopy<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    document: Option<&'a str>,
+    /// Id which the author gave to the operation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
     kind: OperationType,
-    position: usize,
-    revision: usize,
+    /// JSON operations have a path instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<usize>,
+    /// Operations of a transaction have the revision of the transaction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revision: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
     content: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spans: Option<Vec<FormatSpanJSONContract<&'a Attributes>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a [PathSegment]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<ActionJSONContract<'a>>,
+    /// Deletions and moves of several ranges have them instead of a position and a length
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ranges: Option<&'a [Range<usize>]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operations: Option<Vec<OperationJSONFreeCopy<'a>>>,
 }
 
 impl<'a> OperationJSONFreeCopy<'a> {
     pub fn from_operation(operation: &'a Operation) -> Self {
         if let Some(insert_operation) = operation.downcast::<InsertOperation>() {
             OperationJSONFreeCopy {
+                document: None,
+                id: None,
                 kind: OperationType::INSERT,
-                position: insert_operation.position(),
-                revision: insert_operation.revision(),
+                position: Some(insert_operation.position()),
+                revision: Some(insert_operation.revision()),
                 content: Some(insert_operation.text()),
                 length: None,
+                spans: None,
+                path: None,
+                action: None,
+                ranges: None,
+                to: None,
+                operations: None,
             }
         } else if let Some(delete_operation) = operation.downcast::<DeleteOperation>() {
+            let (position, length, ranges) = split_ranges(delete_operation.ranges());
             OperationJSONFreeCopy {
+                document: None,
+                id: None,
                 kind: OperationType::DELETE,
-                position: delete_operation.position(),
-                revision: delete_operation.revision(),
+                position,
+                revision: Some(delete_operation.revision()),
+                content: None,
+                length,
+                spans: None,
+                path: None,
+                action: None,
+                ranges,
+                to: None,
+                operations: None,
+            }
+        } else if let Some(move_operation) = operation.downcast::<MoveOperation>() {
+            let (position, length, ranges) = split_ranges(move_operation.ranges());
+            OperationJSONFreeCopy {
+                document: None,
+                id: None,
+                kind: OperationType::MOVE,
+                position,
+                revision: Some(move_operation.revision()),
+                content: None,
+                length,
+                spans: None,
+                path: None,
+                action: None,
+                ranges,
+                to: Some(move_operation.to()),
+                operations: None,
+            }
+        } else if let Some(replace_operation) = operation.downcast::<ReplaceOperation>() {
+            let (delete, insert) = (replace_operation.delete(), replace_operation.insert());
+            let (length, ranges) = match delete.ranges() {
+                [range] if range.start == insert.position() => (Some(range.len()), None),
+                ranges => (None, Some(ranges)),
+            };
+            OperationJSONFreeCopy {
+                document: None,
+                id: None,
+                kind: OperationType::REPLACE,
+                position: Some(insert.position()),
+                revision: Some(replace_operation.revision()),
+                content: Some(insert.text()),
+                length,
+                spans: None,
+                path: None,
+                action: None,
+                ranges,
+                to: None,
+                operations: None,
+            }
+        } else if let Some(transaction) = operation.downcast::<TransactionOperation>() {
+            let operations = (transaction.operations().iter())
+                .map(|operation| OperationJSONFreeCopy {
+                    revision: None,
+                    ..OperationJSONFreeCopy::from_operation(operation)
+                })
+                .collect();
+            OperationJSONFreeCopy {
+                document: None,
+                id: None,
+                kind: OperationType::TRANSACTION,
+                position: None,
+                revision: Some(transaction.revision()),
+                content: None,
+                length: None,
+                spans: None,
+                path: None,
+                action: None,
+                ranges: None,
+                to: None,
+                operations: Some(operations),
+            }
+        } else if let Some(format_operation) = operation.downcast::<FormatOperation>() {
+            let spans = format_operation
+                .spans()
+                .iter()
+                .map(|(length, attributes)| FormatSpanJSONContract {
+                    length: *length,
+                    attributes,
+                })
+                .collect();
+            OperationJSONFreeCopy {
+                document: None,
+                id: None,
+                kind: OperationType::FORMAT,
+                position: Some(format_operation.position()),
+                revision: Some(format_operation.revision()),
+                content: None,
+                length: None,
+                spans: Some(spans),
+                path: None,
+                action: None,
+                ranges: None,
+                to: None,
+                operations: None,
+            }
+        } else if let Some(json_operation) = operation.downcast::<JsonOperation>() {
+            OperationJSONFreeCopy {
+                document: None,
+                id: None,
+                kind: OperationType::JSON,
+                position: None,
+                revision: Some(json_operation.revision()),
                 content: None,
-                length: Some(*delete_operation.len()),
+                length: None,
+                spans: None,
+                path: Some(json_operation.path()),
+                action: Some(ActionJSONContract::Json(json_operation.action())),
+                ranges: None,
+                to: None,
+                operations: None,
+            }
+        } else if let Some(list_operation) =
+            operation.downcast::<SequenceOperation<serde_json::Value>>()
+        {
+            OperationJSONFreeCopy {
+                document: None,
+                id: None,
+                kind: OperationType::LIST,
+                position: None,
+                revision: Some(list_operation.revision()),
+                content: None,
+                length: None,
+                spans: None,
+                path: None,
+                action: Some(ActionJSONContract::List(list_operation.action())),
+                ranges: None,
+                to: None,
+                operations: None,
             }
         } else {
             unreachable!();
         }
     }
+
+    pub fn with_document(mut self, document: Option<&'a str>) -> Self {
+        self.document = document;
+        self
+    }
+
+    pub fn with_id(mut self, id: Option<&'a str>) -> Self {
+        self.id = id;
+        self
+    }
 }
 
 #[derive(Deserialize)]
-pub(super) struct OperationJSONContract {
+pub(crate) struct OperationJSONContract {
+    pub document: Option<String>,
+    /// Id which the client gave to the operation, it is kept in the audit log
+    #[serde(default)]
+    pub id: Option<String>,
     kind: OperationType,
-    position: usize,
-    revision: usize,
+    position: Option<usize>,
+    /// Operations of a transaction don't need it
+    revision: Option<usize>,
     content: Option<String>,
     length: Option<usize>,
+    spans: Option<Vec<FormatSpanJSONContract<Attributes>>>,
+    path: Option<Vec<PathSegment>>,
+    /// Decoded by `kind`
+    action: Option<serde_json::Value>,
+    ranges: Option<Vec<Range<usize>>>,
+    to: Option<usize>,
+    operations: Option<Vec<OperationJSONContract>>,
 }
 
 impl OperationJSONContract {
-    #[allow(unused)]
-    pub fn from_operation(operation: &Operation) -> Self {
-        if let Some(insert_operation) = operation.downcast::<InsertOperation>() {
-            OperationJSONContract {
-                kind: OperationType::INSERT,
-                position: insert_operation.position(),
-                revision: insert_operation.revision(),
-                content: Some(insert_operation.text().clone()),
-                length: None,
-            }
-        } else if let Some(delete_operation) = operation.downcast::<DeleteOperation>() {
-            OperationJSONContract {
-                kind: OperationType::DELETE,
-                position: delete_operation.position(),
-                revision: delete_operation.revision(),
-                content: None,
-                length: Some(*delete_operation.len()),
-            }
-        } else {
-            unreachable!();
-        }
+    pub fn into_operation(self) -> Option<Box<dyn operations::OperationTrait>> {
+        let revision = self.revision?;
+        self.into_part(revision)
     }
-    pub fn as_operation(self) -> Option<Box<dyn operations::OperationTrait>> {
+
+    /// Operation with `revision`, the operations of a transaction have its revision
+    fn into_part(self, revision: usize) -> Option<Box<dyn operations::OperationTrait>> {
         match self.kind {
             OperationType::INSERT => Some(Box::new(operations::InsertOperation::new(
-                self.position,
-                self.revision,
-                match self.content {
-                    Some(content) => content,
-                    None => return None,
-                },
+                self.position?,
+                revision,
+                self.content?,
             ))),
-            OperationType::DELETE => Some(Box::new(operations::DeleteOperation::new(
-                self.position,
-                self.revision,
-                match self.length {
-                    Some(len) => len,
-                    None => return None,
-                },
+            OperationType::DELETE => match self.ranges {
+                Some(ranges) => Some(Box::new(DeleteOperation::with_ranges(revision, ranges))),
+                None => Some(Box::new(DeleteOperation::new(
+                    self.position?,
+                    revision,
+                    self.length?,
+                ))),
+            },
+            OperationType::MOVE => match self.ranges {
+                Some(ranges) => Some(Box::new(MoveOperation::with_ranges(
+                    revision, ranges, self.to?,
+                ))),
+                None => Some(Box::new(MoveOperation::new(
+                    self.position?,
+                    revision,
+                    self.length?,
+                    self.to?,
+                ))),
+            },
+            OperationType::REPLACE => {
+                let insert = InsertOperation::new(self.position?, revision, self.content?);
+                let delete = match self.ranges {
+                    Some(ranges) => DeleteOperation::with_ranges(revision, ranges),
+                    None => DeleteOperation::new(insert.position(), revision, self.length?),
+                };
+                Some(Box::new(ReplaceOperation::with_parts(delete, insert)))
+            }
+            OperationType::TRANSACTION => {
+                let operations = (self.operations?.into_iter())
+                    .map(|operation| operation.into_part(revision))
+                    .collect::<Option<_>>()?;
+                Some(Box::new(TransactionOperation::new(revision, operations)))
+            }
+            OperationType::FORMAT => {
+                let spans = self.spans?;
+                Some(Box::new(FormatOperation::with_spans(
+                    self.position?,
+                    revision,
+                    spans
+                        .into_iter()
+                        .map(|span| (span.length, span.attributes))
+                        .collect(),
+                )))
+            }
+            OperationType::JSON => Some(Box::new(JsonOperation::new(
+                self.path?,
+                revision,
+                serde_json::from_value(self.action?).ok()?,
             ))),
+            OperationType::LIST => Some(Box::new(SequenceOperation::<serde_json::Value>::new(
+                revision,
+                serde_json::from_value(self.action?).ok()?,
+            ))),
+        }
+    }
+}
+
+/// Sent to the client instead of an operation when its message was rejected
+#[derive(Serialize)]
+pub(super) struct ErrorJSONContract<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    document: Option<&'a str>,
+    error: &'a str,
+    /// Id of the rejected operation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+}
+
+impl<'a> ErrorJSONContract<'a> {
+    pub fn new(document: Option<&'a str>, error: &'a str) -> Self {
+        ErrorJSONContract {
+            document,
+            error,
+            id: None,
+        }
+    }
+
+    pub fn with_id(mut self, id: Option<&'a str>) -> Self {
+        self.id = id;
+        self
+    }
+}
+
+/// Sent to the client when its role in the document was changed
+#[derive(Serialize)]
+pub(super) struct RoleJSONContract<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    document: Option<&'a str>,
+    role: Role,
+}
+
+impl<'a> RoleJSONContract<'a> {
+    pub fn new(document: Option<&'a str>, role: Role) -> Self {
+        RoleJSONContract { document, role }
+    }
+}
+
+/// Revision of a document, sent in `ack` and `subscribed` messages
+#[derive(Serialize)]
+pub(super) struct RevisionJSONContract<'a> {
+    document: &'a str,
+    revision: usize,
+}
+
+impl<'a> RevisionJSONContract<'a> {
+    pub fn new(document: &'a str, revision: usize) -> Self {
+        RevisionJSONContract { document, revision }
+    }
+}
+
+/// Sent to the other clients when a client moves its cursor or leaves the document
+#[derive(Serialize)]
+pub(super) struct PresenceJSONContract<'a> {
+    document: &'a str,
+    client: &'a str,
+    cursor: Option<usize>,
+}
+
+impl<'a> PresenceJSONContract<'a> {
+    pub fn new(document: &'a str, client: &'a str, cursor: Option<usize>) -> Self {
+        PresenceJSONContract {
+            document,
+            client,
+            cursor,
+        }
+    }
+}
+
+/// Cursor of the client in a document
+#[derive(Deserialize, Debug)]
+pub(super) struct PresenceChangeContract {
+    pub document: String,
+    pub cursor: Option<usize>,
+}
+
+/// New role of a client, the owner who changes it is the client of the query
+#[derive(Deserialize, Debug)]
+pub(super) struct RoleChangeContract {
+    pub role: Role,
+}
+
+/// Optional features of the protocol
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Capability {
+    Binary,
+    Presence,
+    Compose,
+}
+
+/// The first message of both sides, it has the same shape in all protocol versions
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct HelloJSONContract {
+    pub version: u32,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+}
+
+/// Messages of the server, tagged by `type` since the second protocol version
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(super) enum ServerMessage<'a> {
+    Hello(HelloJSONContract),
+    Operation(OperationJSONFreeCopy<'a>),
+    Error(ErrorJSONContract<'a>),
+    Role(RoleJSONContract<'a>),
+    /// The operation of the client got the revision
+    Ack(RevisionJSONContract<'a>),
+    /// The missed operations are sent, the revision is the next one
+    Subscribed(RevisionJSONContract<'a>),
+    Presence(PresenceJSONContract<'a>),
+}
+
+/// Messages of the client, tagged by `type` since the second protocol version
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(super) enum ClientMessage {
+    Hello(HelloJSONContract),
+    Operation(Box<OperationJSONContract>),
+    Subscribe(SubscriptionJSONContract),
+    Unsubscribe(SubscriptionJSONContract),
+    Presence(PresenceChangeContract),
+}
+
+#[derive(Deserialize)]
+pub(super) struct SubscriptionJSONContract {
+    pub document: String,
+    /// The first revision the client doesn't know, the server sends the missed operations
+    pub since: Option<usize>,
+}
+
+/// Author, time and id of an operation, the time is in milliseconds since the Unix epoch
+#[derive(Serialize)]
+pub(super) struct AuditEntryJSONContract<'a> {
+    author: &'a str,
+    timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+}
+
+impl<'a> AuditEntryJSONContract<'a> {
+    pub fn new(entry: &'a AuditEntry) -> Self {
+        AuditEntryJSONContract {
+            author: &entry.author,
+            timestamp: unix_millis(entry.timestamp),
+            id: entry.operation_id.as_deref(),
         }
     }
 }
+
+fn unix_millis(time: SystemTime) -> u64 {
+    let time = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    time.as_millis() as u64
+}
+
+#[derive(Serialize)]
+pub(super) struct AuditRecordJSONContract<'a> {
+    revision: usize,
+    #[serde(flatten)]
+    entry: AuditEntryJSONContract<'a>,
+    operation: OperationJSONFreeCopy<'a>,
+    /// Text removed by a delete operation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted: Option<&'a str>,
+}
+
+impl<'a> AuditRecordJSONContract<'a> {
+    pub fn new(record: &'a AuditRecord) -> Self {
+        AuditRecordJSONContract {
+            revision: record.revision,
+            entry: AuditEntryJSONContract::new(&record.entry),
+            operation: OperationJSONFreeCopy::from_operation(&record.operation),
+            deleted: record.deleted.as_deref(),
+        }
+    }
+}
+
+/// Byte range of the text and the operation which inserted it
+#[derive(Serialize)]
+pub(super) struct BlameJSONContract<'a> {
+    start: usize,
+    end: usize,
+    revision: usize,
+    #[serde(flatten)]
+    entry: AuditEntryJSONContract<'a>,
+}
+
+impl<'a> BlameJSONContract<'a> {
+    pub fn new(range: &'a BlameRange) -> Self {
+        BlameJSONContract {
+            start: range.start,
+            end: range.end,
+            revision: range.revision,
+            entry: AuditEntryJSONContract::new(&range.entry),
+        }
+    }
+}
+
+/// Named revision of a document, the time is in milliseconds since the Unix epoch
+#[derive(Serialize)]
+pub(super) struct CheckpointJSONContract<'a> {
+    name: &'a str,
+    revision: usize,
+    author: &'a str,
+    timestamp: u64,
+}
+
+impl<'a> CheckpointJSONContract<'a> {
+    pub fn new(checkpoint: &'a Checkpoint) -> Self {
+        CheckpointJSONContract {
+            name: &checkpoint.name,
+            revision: checkpoint.revision,
+            author: &checkpoint.author,
+            timestamp: unix_millis(checkpoint.timestamp),
+        }
+    }
+}
+
+/// Text deleted both in a branch and in its parent
+#[derive(Serialize)]
+pub(super) struct ConflictJSONContract {
+    revision: usize,
+    branch_revision: usize,
+    length: usize,
+}
+
+impl ConflictJSONContract {
+    pub fn new(conflict: &Conflict) -> Self {
+        ConflictJSONContract {
+            revision: conflict.revision,
+            branch_revision: conflict.branch_revision,
+            length: conflict.length,
+        }
+    }
+}
+
+/// Operations of a branch applied to its parent and the conflicts of the merge
+#[derive(Serialize)]
+pub(super) struct MergeJSONContract<'a> {
+    operations: Vec<OperationJSONFreeCopy<'a>>,
+    conflicts: Vec<ConflictJSONContract>,
+}
+
+impl<'a> MergeJSONContract<'a> {
+    pub fn new(merge: &'a Merge) -> Self {
+        MergeJSONContract {
+            operations: (merge.operations.iter())
+                .map(|operation| OperationJSONFreeCopy::from_operation(operation))
+                .collect(),
+            conflicts: merge
+                .conflicts
+                .iter()
+                .map(ConflictJSONContract::new)
+                .collect(),
+        }
+    }
+}
+
+/// The first line of an exported history
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub(super) struct SnapshotHeaderContract {
+    #[serde(rename = "type")]
+    pub kind: DocumentKind,
+    /// Number of the operations
+    pub revision: usize,
+}
+
+/// Operation of an exported history with its stamp, see [`AuditRecordJSONContract`]
+#[derive(Deserialize)]
+pub(super) struct ImportRecordContract {
+    revision: usize,
+    author: String,
+    timestamp: u64,
+    id: Option<String>,
+    operation: OperationJSONContract,
+}
+
+impl ImportRecordContract {
+    /// `None` if the operation is malformed or has another revision than its record
+    pub fn into_record(self) -> Option<(Operation, AuditEntry)> {
+        let operation = self.operation.into_operation()?;
+        if operation.revision() != self.revision {
+            return None;
+        }
+        let entry = AuditEntry {
+            author: self.author,
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(self.timestamp),
+            operation_id: self.id,
+            connection: None,
+        };
+        Some((operation, entry))
+    }
+}
+
+/// Exported history in one value
+#[derive(Serialize)]
+pub(super) struct ArchiveJSONContract<'a> {
+    pub header: SnapshotHeaderContract,
+    pub records: Vec<AuditRecordJSONContract<'a>>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct ArchiveContract {
+    pub header: SnapshotHeaderContract,
+    pub records: Vec<ImportRecordContract>,
+}
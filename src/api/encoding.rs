@@ -0,0 +1,163 @@
+use actix_ws::Closed;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{metrics::METRICS, ot::operations::Operation};
+
+use super::contracts::{OperationJSONContract, OperationJSONFreeCopy};
+
+/// Encoding of the `/ws` messages.
+///
+/// Both encodings carry the same messages, the client chooses one
+/// through the `Sec-WebSocket-Protocol` header. Text frames are always decoded as JSON
+/// and binary frames as MessagePack, so a client can send JSON while debugging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+}
+
+/// Encoded message ready to be sent
+#[derive(Debug)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Frame {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Frame::Text(text) => text.as_bytes(),
+            Frame::Binary(bytes) => bytes,
+        }
+    }
+
+    pub async fn send(self, session: &mut actix_ws::Session) -> Result<(), Closed> {
+        METRICS.sent_size.observe(self.as_bytes().len() as u64);
+        match self {
+            Frame::Text(text) => session.text(text).await,
+            Frame::Binary(bytes) => session.binary(bytes).await,
+        }
+    }
+}
+
+impl Encoding {
+    pub const JSON_PROTOCOL: &'static str = "ot.json";
+    pub const MESSAGE_PACK_PROTOCOL: &'static str = "ot.msgpack";
+
+    /// Name of the subprotocol for the `Sec-WebSocket-Protocol` header
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            Encoding::Json => Self::JSON_PROTOCOL,
+            Encoding::MessagePack => Self::MESSAGE_PACK_PROTOCOL,
+        }
+    }
+
+    /// Chooses the first supported subprotocol from the value of `Sec-WebSocket-Protocol` header.
+    ///
+    /// Returns `None` if the client doesn't support any of them.
+    pub fn negotiate(protocols: &str) -> Option<Self> {
+        protocols
+            .split(',')
+            .find_map(|protocol| match protocol.trim() {
+                Self::JSON_PROTOCOL => Some(Encoding::Json),
+                Self::MESSAGE_PACK_PROTOCOL => Some(Encoding::MessagePack),
+                _ => None,
+            })
+    }
+
+    pub fn encode_operation(&self, operation: &Operation) -> Frame {
+        self.encode(&OperationJSONFreeCopy::from_operation(operation))
+    }
+
+    pub fn decode_operation(&self, payload: &[u8]) -> Option<Operation> {
+        self.decode::<OperationJSONContract>(payload)?
+            .into_operation()
+    }
+
+    pub(super) fn encode<T: Serialize>(&self, message: &T) -> Frame {
+        match self {
+            Encoding::Json => Frame::Text(serde_json::to_string(message).unwrap()),
+            // fields are written by name, because optional fields of the contracts are skipped
+            Encoding::MessagePack => Frame::Binary(rmp_serde::to_vec_named(message).unwrap()),
+        }
+    }
+
+    pub(super) fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Option<T> {
+        match self {
+            Encoding::Json => serde_json::from_slice(payload).ok(),
+            Encoding::MessagePack => rmp_serde::from_slice(payload).ok(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Encoding;
+    use crate::ot::operations::{DeleteOperation, InsertOperation, Operation};
+
+    #[test]
+    fn negotiate() {
+        let cases = [
+            ("ot.json", Some(Encoding::Json)),
+            ("ot.msgpack", Some(Encoding::MessagePack)),
+            ("chat, ot.msgpack, ot.json", Some(Encoding::MessagePack)),
+            ("ot.json,ot.msgpack", Some(Encoding::Json)),
+            ("chat", None),
+            ("", None),
+        ];
+
+        for (header, ans) in cases {
+            assert_eq!(Encoding::negotiate(header), ans, "header: {header:?}");
+        }
+    }
+
+    #[test]
+    fn operation_round_trip() {
+        let operations: [Operation; 2] = [
+            Box::new(InsertOperation::new(3, 7, String::from("text"))),
+            Box::new(DeleteOperation::new(1, 2, 5)),
+        ];
+
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            for operation in &operations {
+                let frame = encoding.encode_operation(operation);
+                let decoded = encoding.decode_operation(frame.as_bytes()).unwrap();
+                assert_eq!(
+                    format!("{decoded:?}"),
+                    format!("{operation:?}"),
+                    "{encoding:?}"
+                );
+            }
+        }
+    }
+
+    /// The sizes are in the table of the README, see `benches/protocol_benchmark.rs`
+    #[test]
+    fn message_pack_is_smaller() {
+        let cases: [(Operation, usize, usize); 3] = [
+            (
+                Box::new(InsertOperation::new(2, 10, String::from("x"))),
+                58,
+                43,
+            ),
+            (
+                Box::new(InsertOperation::new(6890, 1000, "0123456789".repeat(100))),
+                1062,
+                1048,
+            ),
+            (Box::new(DeleteOperation::new(10, 1000, 100)), 60, 43),
+        ];
+
+        for (operation, json, message_pack) in cases {
+            assert!(message_pack < json);
+            let json_frame = Encoding::Json.encode_operation(&operation);
+            let message_pack_frame = Encoding::MessagePack.encode_operation(&operation);
+            assert_eq!(json_frame.as_bytes().len(), json, "{operation:?}");
+            assert_eq!(
+                message_pack_frame.as_bytes().len(),
+                message_pack,
+                "{operation:?}"
+            );
+        }
+    }
+}
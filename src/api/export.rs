@@ -0,0 +1,341 @@
+//! Moving documents between servers and backing them up.
+//!
+//! A document is exported as its text or with the whole history: JSON lines with
+//! a [`SnapshotHeaderContract`] and then an [`AuditRecordJSONContract`] per operation,
+//! or the same in one MessagePack archive. An imported history is replayed,
+//! so the operations get the same revisions and keep their authors and times.
+
+use std::time::SystemTime;
+
+use actix_web::{get, put, web, HttpResponse, Responder};
+use futures_util::StreamExt;
+
+use crate::{
+    collaboration::{
+        audit::{AuditEntry, AuditRecord},
+        manager::Manager,
+    },
+    ot::{
+        document::DocumentKind,
+        operations::{InsertOperation, Operation},
+    },
+};
+
+use super::{
+    access_error_response,
+    contracts::{
+        ArchiveContract, ArchiveJSONContract, AuditRecordJSONContract, ExportFormat, ExportQuery,
+        ImportQuery, ImportRecordContract, SnapshotHeaderContract,
+    },
+};
+
+/// Imported bodies are larger than the other requests, so they have their own limit
+const MAX_IMPORT_SIZE: usize = 64 << 20;
+
+/// History of a document ready to be imported
+type History = (DocumentKind, Vec<(Operation, AuditEntry)>);
+
+fn encode_jsonl(kind: DocumentKind, records: &[AuditRecord]) -> Vec<u8> {
+    let header = SnapshotHeaderContract {
+        kind,
+        revision: records.len(),
+    };
+    let mut body = serde_json::to_vec(&header).unwrap();
+    for record in records {
+        body.push(b'\n');
+        serde_json::to_writer(&mut body, &AuditRecordJSONContract::new(record)).unwrap();
+    }
+    body.push(b'\n');
+    body
+}
+
+fn encode_archive(kind: DocumentKind, records: &[AuditRecord]) -> Vec<u8> {
+    let archive = ArchiveJSONContract {
+        header: SnapshotHeaderContract {
+            kind,
+            revision: records.len(),
+        },
+        records: records.iter().map(AuditRecordJSONContract::new).collect(),
+    };
+    // fields are written by name, because optional fields of the contracts are skipped
+    rmp_serde::to_vec_named(&archive).unwrap()
+}
+
+/// Checks that the history has all the operations of the header
+fn into_history(
+    header: SnapshotHeaderContract,
+    records: impl Iterator<Item = Option<(Operation, AuditEntry)>>,
+) -> Option<History> {
+    let history: Vec<_> = records.collect::<Option<_>>()?;
+    (history.len() == header.revision).then_some((header.kind, history))
+}
+
+fn decode_jsonl(body: &[u8]) -> Option<History> {
+    let mut lines = (body.split(|byte| *byte == b'\n')).filter(|line| !line.is_empty());
+    let header = serde_json::from_slice(lines.next()?).ok()?;
+    let records = lines.map(|line| {
+        serde_json::from_slice::<ImportRecordContract>(line)
+            .ok()?
+            .into_record()
+    });
+    into_history(header, records)
+}
+
+fn decode_archive(body: &[u8]) -> Option<History> {
+    let archive: ArchiveContract = rmp_serde::from_slice(body).ok()?;
+    let records = archive
+        .records
+        .into_iter()
+        .map(|record| record.into_record());
+    into_history(archive.header, records)
+}
+
+/// Text document whose first operation inserts the text
+fn text_history(text: &[u8], author: &str) -> Option<History> {
+    let text = String::from_utf8(text.to_vec()).ok()?;
+    let history = match text.is_empty() {
+        true => vec![],
+        false => {
+            let operation: Operation = Box::new(InsertOperation::new(0, 0, text));
+            let entry = AuditEntry {
+                author: author.to_string(),
+                timestamp: SystemTime::now(),
+                operation_id: None,
+                connection: None,
+            };
+            vec![(operation, entry)]
+        }
+    };
+    Some((DocumentKind::Text, history))
+}
+
+/// Returns the document in the format from the query, its text by default.
+/// The client has to be a viewer at least.
+#[get("/documents/{document}/export")]
+async fn export_document(
+    path: web::Path<String>,
+    query: web::Query<ExportQuery>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let ExportQuery { client, format } = query.into_inner();
+    if let Err(err) = session_manager.check_viewer(&path, &client) {
+        return access_error_response(err);
+    }
+    if format == ExportFormat::Text {
+        return match session_manager.text(&path) {
+            Some(text) => HttpResponse::Ok()
+                .content_type("text/plain; charset=utf-8")
+                .body(text),
+            None => HttpResponse::NotFound().body("document not found"),
+        };
+    }
+
+    let Some((kind, records)) = session_manager.export(&path) else {
+        return HttpResponse::NotFound().body("document not found");
+    };
+    match format {
+        ExportFormat::Jsonl => HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .body(encode_jsonl(kind, &records)),
+        _ => HttpResponse::Ok()
+            .content_type("application/msgpack")
+            .body(encode_archive(kind, &records)),
+    }
+}
+
+/// Creates the document from the body in the format from the query, the client becomes its owner.
+///
+/// The operations of an imported history get the same revisions as in the exported one.
+#[put("/documents/{document}/import")]
+async fn import_document(
+    path: web::Path<String>,
+    query: web::Query<ImportQuery>,
+    mut payload: web::Payload,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let Ok(chunk) = chunk else {
+            return HttpResponse::BadRequest().finish();
+        };
+        if body.len() + chunk.len() > MAX_IMPORT_SIZE {
+            return HttpResponse::PayloadTooLarge().body("document is too large");
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let ImportQuery { client, format } = query.into_inner();
+    let history = match format {
+        ExportFormat::Text => text_history(&body, &client),
+        ExportFormat::Jsonl => decode_jsonl(&body),
+        ExportFormat::Archive => decode_archive(&body),
+    };
+    let Some((kind, history)) = history else {
+        return HttpResponse::BadRequest().body("malformed document");
+    };
+    match session_manager.import(path.into_inner(), client, kind, history) {
+        Ok(()) => HttpResponse::Created().finish(),
+        Err(err) => access_error_response(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{decode_archive, decode_jsonl, encode_archive, encode_jsonl, History};
+    use crate::{
+        api::contracts::OperationJSONContract,
+        collaboration::{audit::AuditRecord, manager::Manager, sessions::Submission},
+        ot::{document::DocumentKind, operations::Operation},
+    };
+
+    fn submit(manager: &Manager, document_id: &str, author: &str, operation: serde_json::Value) {
+        let operation = serde_json::from_value::<OperationJSONContract>(operation)
+            .unwrap()
+            .into_operation()
+            .unwrap();
+        let submission = Submission::new(author.to_string(), operation)
+            .with_operation_id(Some(format!("{author}-1")));
+        manager.submit(document_id, submission).unwrap();
+    }
+
+    fn exported(manager: &Manager, document_id: &str) -> String {
+        let (kind, records) = manager.export(document_id).unwrap();
+        String::from_utf8(encode_jsonl(kind, &records)).unwrap()
+    }
+
+    /// Imports the exported document as `copy` and compares them
+    fn round_trip(
+        manager: &Manager,
+        document_id: &str,
+        copy: &str,
+        encode: fn(DocumentKind, &[AuditRecord]) -> Vec<u8>,
+        decode: fn(&[u8]) -> Option<History>,
+    ) {
+        let (kind, records) = manager.export(document_id).unwrap();
+        let (kind, history) = decode(&encode(kind, &records)).unwrap();
+        manager
+            .import(copy.to_string(), String::from("carol"), kind, history)
+            .unwrap();
+
+        assert_eq!(manager.text(copy), manager.text(document_id));
+        // the times are exported in milliseconds
+        let (_, copied) = manager.export(copy).unwrap();
+        for (copied, record) in copied.iter().zip(&records) {
+            let millis = |record: &AuditRecord| {
+                let time = record.entry.timestamp.duration_since(std::time::UNIX_EPOCH);
+                time.unwrap().as_millis()
+            };
+            assert_eq!(millis(copied), millis(record));
+        }
+        assert_eq!(exported(manager, copy), exported(manager, document_id));
+        assert!(manager.role(copy, "carol").unwrap().can_manage());
+    }
+
+    #[test]
+    fn text_history_is_kept() {
+        let manager = Manager::new();
+        manager
+            .create(
+                String::from("doc"),
+                String::from("alice"),
+                DocumentKind::Text,
+            )
+            .unwrap();
+        submit(
+            &manager,
+            "doc",
+            "alice",
+            json!({"kind": "INSERT", "position": 0, "revision": 0, "content": "hello world"}),
+        );
+        submit(
+            &manager,
+            "doc",
+            "bob",
+            json!({"kind": "FORMAT", "position": 0, "revision": 1,
+                "spans": [{"length": 5, "attributes": {"bold": true}}]}),
+        );
+        // based on the first revision, it is transformed on the server
+        submit(
+            &manager,
+            "doc",
+            "bob",
+            json!({"kind": "TRANSACTION", "revision": 1, "operations": [
+                {"kind": "REPLACE", "position": 0, "length": 1, "content": "H"},
+                {"kind": "MOVE", "position": 6, "length": 5, "to": 0},
+            ]}),
+        );
+
+        round_trip(&manager, "doc", "doc-jsonl", encode_jsonl, decode_jsonl);
+        round_trip(
+            &manager,
+            "doc",
+            "doc-archive",
+            encode_archive,
+            decode_archive,
+        );
+        let lines: Vec<_> = exported(&manager, "doc")
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(lines[0], r#"{"type":"text","revision":3}"#);
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn json_history_is_kept() {
+        let manager = Manager::new();
+        manager
+            .create(
+                String::from("form"),
+                String::from("alice"),
+                DocumentKind::Json,
+            )
+            .unwrap();
+        submit(
+            &manager,
+            "form",
+            "alice",
+            json!({"kind": "JSON", "path": ["fields"], "revision": 0,
+                "action": {"type": "set", "value": [1.5, "a", null]}}),
+        );
+        submit(
+            &manager,
+            "form",
+            "alice",
+            json!({"kind": "JSON", "path": ["fields", 0], "revision": 1,
+                "action": {"type": "add", "amount": 2}}),
+        );
+
+        round_trip(&manager, "form", "form-jsonl", encode_jsonl, decode_jsonl);
+        round_trip(
+            &manager,
+            "form",
+            "form-archive",
+            encode_archive,
+            decode_archive,
+        );
+        assert_eq!(
+            manager.text("form-archive").unwrap(),
+            r#"{"fields":[3.5,"a",null]}"#
+        );
+    }
+
+    #[test]
+    fn incomplete_history_is_rejected() {
+        let body = concat!(
+            r#"{"type":"text","revision":2}"#,
+            "\n",
+            r#"{"revision":0,"author":"a","timestamp":0,"operation":{"kind":"INSERT","position":0,"revision":0,"content":"x"}}"#,
+        );
+        assert!(decode_jsonl(body.as_bytes()).is_none());
+        let shifted = body
+            .replace(r#""revision":2"#, r#""revision":1"#)
+            .replace(r#""revision":0,"content""#, r#""revision":1,"content""#);
+        assert!(decode_jsonl(shifted.as_bytes()).is_none());
+        let body = body.replace(r#""revision":2"#, r#""revision":1"#);
+        let (_, history): (_, Vec<(Operation, _)>) = decode_jsonl(body.as_bytes()).unwrap();
+        assert_eq!(history.len(), 1);
+    }
+}
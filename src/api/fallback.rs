@@ -0,0 +1,219 @@
+//! Transports for clients which can't use WebSockets.
+//!
+//! Operations are received through Server-Sent Events or long polling
+//! and submitted with POST requests.
+
+use std::{collections::VecDeque, time::Duration};
+
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    collaboration::{manager::Manager, sessions::Submission},
+    metrics::METRICS,
+    ot::operations::ArcOperation,
+};
+
+use super::{
+    access_error_response,
+    contracts::{ClientQuery, OperationJSONContract, OperationJSONFreeCopy, PollQuery},
+    lifecycle::Lifecycle,
+};
+
+/// How long the poll request waits for new operations
+const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// Connection of an event stream, disconnects the client when the stream is dropped
+struct EventStreamConnection {
+    manager: web::Data<Manager>,
+    client_name: String,
+    document_id: String,
+}
+
+impl Drop for EventStreamConnection {
+    fn drop(&mut self) {
+        self.manager
+            .disconnect(self.client_name.clone(), self.document_id.clone());
+    }
+}
+
+struct EventStream {
+    _connection: EventStreamConnection,
+    history: VecDeque<ArcOperation>,
+    receiver: broadcast::Receiver<ArcOperation>,
+    /// Revision of the next operation, older ones were already sent from the history
+    next_revision: usize,
+    lifecycle: Lifecycle,
+}
+
+impl EventStream {
+    async fn next(&mut self) -> Option<ArcOperation> {
+        if let Some(operation) = self.history.pop_front() {
+            return Some(operation);
+        }
+        loop {
+            // the stream ends when the client lags behind,
+            // it reconnects with `Last-Event-ID` and gets the missed operations from the history
+            let operation = tokio::select! {
+                operation = self.receiver.recv() => operation,
+                _ = self.lifecycle.closing() => return None,
+            };
+            let operation = match operation {
+                Ok(operation) => operation,
+                Err(RecvError::Lagged(missed)) => {
+                    METRICS.broadcast_lagged.add(missed);
+                    tracing::warn!(missed, "the subscriber lagged behind the broadcast");
+                    return None;
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            if operation.revision() >= self.next_revision {
+                return Some(operation);
+            }
+        }
+    }
+}
+
+fn event(operation: &ArcOperation) -> web::Bytes {
+    let data = serde_json::to_string(&OperationJSONFreeCopy::from_operation(operation)).unwrap();
+    web::Bytes::from(format!(
+        "id: {}\nevent: operation\ndata: {data}\n\n",
+        operation.revision()
+    ))
+}
+
+/// Streams the operations of the document as Server-Sent Events.
+///
+/// The id of an event is the revision of its operation,
+/// so a reconnected `EventSource` continues from the last received operation.
+/// Returns 404 if the document isn't open, like `poll`.
+#[get("/documents/{document}/events")]
+async fn events(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<ClientQuery>,
+    session_manager: web::Data<Manager>,
+    lifecycle: web::Data<Lifecycle>,
+) -> impl Responder {
+    let document_id = path.into_inner();
+    let client_name = query.into_inner().client;
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse::<usize>().ok());
+    // the stream only connects to open documents, like `poll`
+    if let Err(err) = session_manager.check_viewer(&document_id, &client_name) {
+        return access_error_response(err);
+    }
+
+    let (cur_client_name, cur_document_id) = (client_name.clone(), document_id.clone());
+    let cur_session_manager = session_manager.clone();
+    let (_input, receiver, _role) = tokio::task::spawn_blocking(move || {
+        cur_session_manager.connect(cur_client_name, cur_document_id)
+    })
+    .await
+    .unwrap();
+
+    let (history, next_revision) = match last_event_id {
+        Some(id) => {
+            let history = session_manager
+                .operations_since(&document_id, id + 1)
+                .map(|(history, _)| history)
+                .unwrap_or_default();
+            let next_revision = id + 1 + history.len();
+            (history, next_revision)
+        }
+        None => (vec![], 0),
+    };
+
+    let stream = EventStream {
+        _connection: EventStreamConnection {
+            manager: session_manager,
+            client_name,
+            document_id,
+        },
+        history: history.into(),
+        receiver,
+        next_revision,
+        lifecycle: lifecycle.get_ref().clone(),
+    };
+    let stream = futures_util::stream::unfold(stream, |mut stream| async move {
+        let operation = stream.next().await?;
+        stream.next_revision = operation.revision() + 1;
+        Some((Ok::<_, actix_web::Error>(event(&operation)), stream))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+}
+
+/// Returns the operations starting from revision `since`.
+///
+/// If there are none yet, waits for the next operation, but not longer than [`POLL_TIMEOUT`]
+/// and not after the server has started closing the connections.
+#[get("/documents/{document}/poll")]
+async fn poll(
+    path: web::Path<String>,
+    query: web::Query<PollQuery>,
+    session_manager: web::Data<Manager>,
+    lifecycle: web::Data<Lifecycle>,
+) -> impl Responder {
+    let since = query.since;
+    let Some((mut operations, mut receiver)) = session_manager.operations_since(&path, since)
+    else {
+        return HttpResponse::NotFound().body("document not found");
+    };
+
+    if operations.is_empty() {
+        let wait = async {
+            loop {
+                match receiver.recv().await {
+                    Ok(operation) if operation.revision() >= since => return vec![operation],
+                    Ok(_) => continue,
+                    // the client asks again and gets the missed operations from the history
+                    Err(RecvError::Lagged(missed)) => {
+                        METRICS.broadcast_lagged.add(missed);
+                        tracing::warn!(missed, "the subscriber lagged behind the broadcast");
+                        return vec![];
+                    }
+                    Err(RecvError::Closed) => return vec![],
+                }
+            }
+        };
+        operations = tokio::select! {
+            operations = tokio::time::timeout(POLL_TIMEOUT, wait) => operations.unwrap_or_default(),
+            _ = lifecycle.closing() => vec![],
+        };
+    }
+
+    let operations: Vec<OperationJSONFreeCopy> = operations
+        .iter()
+        .map(|operation| OperationJSONFreeCopy::from_operation(operation))
+        .collect();
+    HttpResponse::Ok().json(operations)
+}
+
+/// Applies the operation and returns it transformed to the current revision
+#[post("/documents/{document}/operations")]
+async fn submit(
+    path: web::Path<String>,
+    query: web::Query<ClientQuery>,
+    body: web::Json<OperationJSONContract>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let mut body = body.into_inner();
+    let operation_id = body.id.take();
+    let Some(operation) = body.into_operation() else {
+        return HttpResponse::BadRequest().body("operation is malformed");
+    };
+
+    let submission =
+        Submission::new(query.into_inner().client, operation).with_operation_id(operation_id);
+    match session_manager.submit(&path, submission) {
+        Ok(operation) => HttpResponse::Ok().json(OperationJSONFreeCopy::from_operation(&operation)),
+        Err(err) => access_error_response(err),
+    }
+}
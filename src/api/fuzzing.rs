@@ -0,0 +1,28 @@
+use super::{
+    contracts::{Capability, HelloJSONContract},
+    encoding::Encoding,
+    protocol::{self, Protocol, Request, SUPPORTED_VERSIONS},
+};
+
+/// Decodes `payload` the way `/ws` does: as a hello and as a message
+/// of every protocol version, in both encodings.
+///
+/// Decoded operations must survive the round trip through the encoding.
+pub fn decode_payload(payload: &[u8]) {
+    for encoding in [Encoding::Json, Encoding::MessagePack] {
+        let _ = protocol::decode_hello(encoding, payload);
+
+        for version in SUPPORTED_VERSIONS {
+            let hello = HelloJSONContract {
+                version,
+                capabilities: vec![Capability::Binary],
+            };
+            let protocol = Protocol::accept(hello, encoding).unwrap();
+            if let Some(Request::Operation(_, operation, _)) = protocol.decode(encoding, payload) {
+                let frame = encoding.encode_operation(&operation);
+                let decoded = encoding.decode_operation(frame.as_bytes()).unwrap();
+                assert_eq!(format!("{decoded:?}"), format!("{operation:?}"));
+            }
+        }
+    }
+}
@@ -1,55 +1,266 @@
-use actix_ws::{self, Message};
+use std::time::{Duration, Instant};
+
+use actix_ws::{self, CloseCode, CloseReason, Message};
 use futures_util::StreamExt;
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::mpsc;
+
+use crate::{collaboration::sessions::Submission, metrics::METRICS};
 
-use crate::{
-    api::contracts::OperationJSONContract,
-    ot::operations::{ArcOperation, Operation},
+use super::{
+    contracts::Capability,
+    encoding::Encoding,
+    lifecycle::Lifecycle,
+    protocol::{self, Protocol, Request},
+    subscriptions::{Event, Subscriptions},
 };
 
-use super::contracts::OperationJSONFreeCopy;
+/// Waits for the hello of the client and answers with the hello of the server.
+///
+/// Returns `None` if the connection was closed, in that case the close frame is already sent.
+pub async fn websocket_handshake(
+    mut session: actix_ws::Session,
+    msg_stream: &mut actix_ws::MessageStream,
+    encoding: Encoding,
+) -> Option<Protocol> {
+    let reason = loop {
+        let (payload_encoding, payload) = match msg_stream.next().await {
+            Some(Ok(msg)) => match msg {
+                Message::Ping(bytes) => {
+                    session.pong(&bytes).await.ok()?;
+                    continue;
+                }
+                Message::Text(text) => (Encoding::Json, text.into_bytes()),
+                Message::Binary(bytes) => (Encoding::MessagePack, bytes),
+                Message::Close(_reason) => return None,
+
+                _ => continue,
+            },
+            Some(Err(_)) | None => return None,
+        };
+
+        let Some(hello) = protocol::decode_hello(payload_encoding, &payload) else {
+            break String::from("the first message must be hello");
+        };
+        match Protocol::accept(hello, encoding) {
+            Ok(protocol) => {
+                protocol.hello().send(&mut session).await.ok()?;
+                tracing::debug!(?protocol, "handshake completed");
+                return Some(protocol);
+            }
+            Err(reason) => break reason,
+        }
+    };
+    tracing::info!(%reason, "handshake failed");
+
+    let _ = session
+        .close(Some(CloseReason {
+            code: CloseCode::Protocol,
+            description: Some(reason),
+        }))
+        .await;
+    None
+}
 
 pub async fn websocket_writer(
     mut session: actix_ws::Session,
-    mut operation_receiver: broadcast::Receiver<ArcOperation>,
+    mut events: mpsc::Receiver<Event>,
+    protocol: Protocol,
 ) {
-    while let Ok(operation) = operation_receiver.recv().await {
-        session
-            .text(
-                serde_json::to_string(&OperationJSONFreeCopy::from_operation(&operation)).unwrap(),
-            )
-            .await
-            .unwrap();
+    while let Some(event) = events.recv().await {
+        let frame = match event {
+            Event::Operation(document_id, operation, operation_id, own) => {
+                tracing::debug!(
+                    document = %document_id,
+                    revision = operation.revision(),
+                    own,
+                    "operation sent"
+                );
+                match own && protocol.acknowledges() {
+                    true => protocol.ack(&document_id, operation.revision()),
+                    false => protocol.operation(&document_id, &operation, operation_id.as_deref()),
+                }
+            }
+            Event::Rejected(document_id, operation_id) => {
+                protocol.rejected(&document_id, operation_id.as_deref())
+            }
+            Event::Role(document_id, role) => protocol.role(&document_id, role),
+            Event::Subscribed(document_id, revision) => match protocol.acknowledges() {
+                true => protocol.subscribed(&document_id, revision),
+                false => continue,
+            },
+            Event::Presence(document_id, presence) => {
+                match protocol.supports(Capability::Presence) {
+                    true => protocol.presence(&document_id, &presence.client, presence.cursor),
+                    false => continue,
+                }
+            }
+        };
+        if frame.send(&mut session).await.is_err() {
+            break;
+        }
     }
 }
 
+/// How often the server pings the client
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The connection is closed if the client sends nothing for this long,
+/// a dropped TCP connection isn't noticed otherwise
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Reads the messages of the client until the connection is closed.
+///
+/// Returns the reason to close the connection with if the server closes it,
+/// the caller sends the close frame once the writer has stopped.
+///
+/// `document_id` is the document from the connection query, it is the only document
+/// of the connection before the third protocol version.
 pub async fn websocket_reader(
     mut session: actix_ws::Session,
     mut msg_stream: actix_ws::MessageStream,
-    operation_sender: mpsc::Sender<Operation>,
-) {
+    mut subscriptions: Subscriptions,
+    document_id: Option<String>,
+    protocol: Protocol,
+    lifecycle: Lifecycle,
+) -> Option<CloseReason> {
+    let _connection = METRICS.connections.track();
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_message = Instant::now();
     loop {
-        match msg_stream.next().await {
+        let message = tokio::select! {
+            message = msg_stream.next() => message,
+            _ = heartbeat.tick() => {
+                if last_message.elapsed() > CLIENT_TIMEOUT {
+                    tracing::info!("client timed out");
+                    break Some(CloseReason {
+                        code: CloseCode::Normal,
+                        description: Some(String::from("client timed out")),
+                    });
+                }
+                let _ = session.ping(b"").await;
+                continue;
+            }
+            // the operations which are already sent are applied before the connection is closed
+            _ = lifecycle.stopping() => {
+                lifecycle.closing().await;
+                break Some(restarting());
+            }
+        };
+        last_message = Instant::now();
+
+        let (payload_encoding, payload) = match message {
             Some(Ok(msg)) => match msg {
                 Message::Ping(bytes) => {
-                    session.pong(&bytes).await.unwrap();
+                    if session.pong(&bytes).await.is_err() {
+                        break None;
+                    }
+                    continue;
                 }
-                Message::Text(bytes) => {
-                    // or we will use binary?
-                    let input_operation =
-                        serde_json::from_str::<OperationJSONContract>(&bytes).unwrap(); // TODO need error handler
-                    let input_operation = input_operation.as_operation().unwrap();
-                    operation_sender.send(input_operation).await.unwrap();
-                }
-                Message::Close(_reason) => break,
+                Message::Text(text) => (Encoding::Json, text.into_bytes()),
+                Message::Binary(bytes) => (Encoding::MessagePack, bytes),
+                Message::Close(_reason) => break None,
 
-                _ => {}
+                _ => continue,
             },
             Some(Err(err)) => {
                 panic!("{}", err)
             }
 
-            None => break,
+            None => break None,
+        };
+        METRICS.received_size.observe(payload.len() as u64);
+
+        let Some(request) = protocol.decode(payload_encoding, &payload) else {
+            tracing::debug!(size = payload.len(), "malformed message");
+            let frame = protocol.error(None, "malformed message");
+            if frame.send(&mut session).await.is_err() {
+                break None;
+            }
+            continue;
+        };
+        let (operation_document_id, input_operation, operation_id) = match request {
+            Request::Subscribe(document_id, since) => {
+                subscriptions.subscribe(document_id, since).await;
+                continue;
+            }
+            Request::Presence(document_id, cursor) => {
+                if !subscriptions.set_presence(&document_id, cursor) {
+                    let frame =
+                        protocol.error(Some(&document_id), "not subscribed to the document");
+                    if frame.send(&mut session).await.is_err() {
+                        break None;
+                    }
+                }
+                continue;
+            }
+            Request::Unsubscribe(document_id) => {
+                if !subscriptions.unsubscribe(&document_id) {
+                    let frame =
+                        protocol.error(Some(&document_id), "not subscribed to the document");
+                    if frame.send(&mut session).await.is_err() {
+                        break None;
+                    }
+                }
+                continue;
+            }
+            Request::Operation(operation_document_id, input_operation, operation_id) => (
+                operation_document_id.or_else(|| document_id.clone()),
+                input_operation,
+                operation_id,
+            ),
+        };
+
+        let Some((operation_sender, role)) = operation_document_id
+            .as_deref()
+            .and_then(|document_id| subscriptions.get(document_id))
+        else {
+            let frame = protocol.operation_error(
+                operation_document_id.as_deref(),
+                operation_id.as_deref(),
+                "not subscribed to the document",
+            );
+            if frame.send(&mut session).await.is_err() {
+                break None;
+            }
+            continue;
+        };
+        // the role can be changed at any moment, so it is checked for each operation
+        if !role.borrow().can_edit() {
+            let frame = protocol.operation_error(
+                operation_document_id.as_deref(),
+                operation_id.as_deref(),
+                "viewers can't submit operations",
+            );
+            if frame.send(&mut session).await.is_err() {
+                break None;
+            }
+            continue;
+        }
+        let span = tracing::debug_span!(
+            "operation",
+            document = operation_document_id.as_deref(),
+            kind = input_operation.kind(),
+            size = input_operation.size(),
+            base_revision = input_operation.revision(),
+        );
+        span.in_scope(|| tracing::debug!("operation received"));
+        let author = subscriptions.client_name().to_string();
+        let submission = span.in_scope(|| {
+            Submission::new(author, input_operation)
+                .with_operation_id(operation_id)
+                .with_connection(subscriptions.connection())
+        });
+        if operation_sender.send(submission).await.is_err() {
+            // the session doesn't take operations anymore, e.g. it was drained during the shutdown
+            break Some(restarting());
         }
     }
 }
+
+/// Tells the client to reconnect later
+fn restarting() -> CloseReason {
+    CloseReason {
+        code: CloseCode::Restart,
+        description: Some(String::from("server restarting")),
+    }
+}
@@ -0,0 +1,89 @@
+//! Health checks and the graceful shutdown of the server.
+//!
+//! The shutdown goes in steps, so the operations which the clients have already sent
+//! are applied and acknowledged before the connections are closed:
+//! 1. `/readyz` starts failing and new connections aren't accepted;
+//! 2. the connections stop reading;
+//! 3. the input queues of the sessions are drained;
+//! 4. the connections are closed with the "service restart" code,
+//!    the event streams and the long polls end;
+//! 5. the HTTP server stops.
+//!
+//! The documents are kept only in memory, so there is nothing to flush between 3 and 4 yet.
+
+use std::time::Duration;
+
+use actix_web::{dev::ServerHandle, get, web, HttpResponse, Responder};
+use tokio_util::sync::CancellationToken;
+
+use crate::collaboration::manager::Manager;
+
+/// Stage of the shutdown shared by the handlers
+#[derive(Clone, Default)]
+pub(super) struct Lifecycle {
+    stopping: CancellationToken,
+    closing: CancellationToken,
+}
+
+impl Lifecycle {
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.is_cancelled()
+    }
+
+    /// Resolves when the clients have to stop sending
+    pub async fn stopping(&self) {
+        self.stopping.cancelled().await
+    }
+
+    /// Resolves when the connections have to be closed
+    pub async fn closing(&self) {
+        self.closing.cancelled().await
+    }
+}
+
+/// Stops the server gracefully, see the module documentation
+pub struct ShutdownHandle {
+    pub(super) lifecycle: Lifecycle,
+    pub(super) server: ServerHandle,
+    pub(super) manager: web::Data<Manager>,
+}
+
+impl ShutdownHandle {
+    /// Runs the shutdown sequence, the server is stopped forcibly when `deadline` passes
+    pub async fn shutdown(self, deadline: Duration) {
+        let deadline = tokio::time::Instant::now() + deadline;
+
+        self.server.pause().await;
+        self.lifecycle.stopping.cancel();
+        if tokio::time::timeout_at(deadline, self.manager.drain())
+            .await
+            .is_err()
+        {
+            tracing::warn!("the input queues aren't drained before the deadline");
+        }
+
+        self.lifecycle.closing.cancel();
+        if tokio::time::timeout_at(deadline, self.server.stop(true))
+            .await
+            .is_err()
+        {
+            tracing::warn!("the connections aren't closed before the deadline");
+            self.server.stop(false).await;
+        }
+    }
+}
+
+/// Liveness, the process serves requests
+#[get("/healthz")]
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok().body("ok")
+}
+
+/// Readiness, the server accepts new clients
+#[get("/readyz")]
+async fn readyz(lifecycle: web::Data<Lifecycle>) -> impl Responder {
+    match lifecycle.is_stopping() {
+        true => HttpResponse::ServiceUnavailable().body("shutting down"),
+        false => HttpResponse::Ok().body("ready"),
+    }
+}
@@ -0,0 +1,164 @@
+//! Replaying how a document was edited, for reviews and demos.
+//!
+//! The operations are sent with their authors and the times when the server applied them.
+//! Over a WebSocket they are sent with the same gaps, divided by the speed of the playback.
+
+use std::time::{Duration, SystemTime};
+
+use actix_web::{get, rt, web, HttpRequest, HttpResponse, Responder};
+use actix_ws::{CloseCode, CloseReason, Message};
+use futures_util::StreamExt;
+
+use crate::collaboration::{audit::AuditRecord, manager::Manager};
+
+use super::{
+    access_error_response,
+    contracts::{AuditRecordJSONContract, PlaybackQuery},
+    lifecycle::Lifecycle,
+};
+
+/// Slowest playback, the gaps get at most 100 times longer
+const MIN_SPEED: f64 = 0.01;
+
+fn line(record: &AuditRecord) -> web::Bytes {
+    let mut line = serde_json::to_vec(&AuditRecordJSONContract::new(record)).unwrap();
+    line.push(b'\n');
+    web::Bytes::from(line)
+}
+
+/// Returns the operations of the document in the revision range as JSON lines,
+/// the time of each one is when the server applied it. The client has to be a viewer at least.
+#[get("/documents/{document}/playback")]
+async fn playback(
+    path: web::Path<String>,
+    query: web::Query<PlaybackQuery>,
+    session_manager: web::Data<Manager>,
+) -> impl Responder {
+    let query = query.into_inner();
+    if let Err(err) = session_manager.check_viewer(&path, &query.client) {
+        return access_error_response(err);
+    }
+    let records = match session_manager.playback(&path, query.from, query.to) {
+        Ok(records) => records,
+        Err(err) => return access_error_response(err),
+    };
+    let lines = records.iter().map(line).map(Ok::<_, actix_web::Error>);
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(futures_util::stream::iter(lines.collect::<Vec<_>>()))
+}
+
+/// Sends the operations of the document in the revision range as text frames
+/// at the speed from the query, then closes the connection
+#[get("/documents/{document}/playback/ws")]
+async fn playback_ws(
+    req: HttpRequest,
+    stream: web::Payload,
+    path: web::Path<String>,
+    query: web::Query<PlaybackQuery>,
+    session_manager: web::Data<Manager>,
+    lifecycle: web::Data<Lifecycle>,
+) -> impl Responder {
+    let query = query.into_inner();
+    if let Err(err) = session_manager.check_viewer(&path, &query.client) {
+        return access_error_response(err);
+    }
+    let speed = query.speed.unwrap_or(1.0);
+    if !(speed.is_finite() && speed >= MIN_SPEED) {
+        return HttpResponse::BadRequest().body(format!("speed must be at least {MIN_SPEED}"));
+    }
+    let records = match session_manager.playback(&path, query.from, query.to) {
+        Ok(records) => records,
+        Err(err) => return access_error_response(err),
+    };
+    let (res, session, msg_stream) = match actix_ws::handle(&req, stream) {
+        Ok(handle) => handle,
+        Err(err) => return HttpResponse::from_error(err),
+    };
+    tracing::info!(document = %path, revisions = records.len(), speed, "playback started");
+    rt::spawn(play(
+        session,
+        msg_stream,
+        records,
+        speed,
+        lifecycle.get_ref().clone(),
+    ));
+    res
+}
+
+/// Time to wait before the next operation of a playback at `speed`,
+/// the longest one if it doesn't fit into a [`Duration`]
+fn delay(previous: SystemTime, next: SystemTime, speed: f64) -> Duration {
+    let gap = next.duration_since(previous).unwrap_or_default();
+    Duration::try_from_secs_f64(gap.as_secs_f64() / speed).unwrap_or(Duration::MAX)
+}
+
+async fn play(
+    mut session: actix_ws::Session,
+    mut msg_stream: actix_ws::MessageStream,
+    records: Vec<AuditRecord>,
+    speed: f64,
+    lifecycle: Lifecycle,
+) {
+    let mut previous = None;
+    for record in &records {
+        let timestamp = record.entry.timestamp;
+        let sleep = tokio::time::sleep(
+            previous.map_or(Duration::ZERO, |previous| delay(previous, timestamp, speed)),
+        );
+        previous = Some(timestamp);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => break,
+                message = msg_stream.next() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                },
+                _ = lifecycle.closing() => {
+                    let _ = session
+                        .close(Some(CloseReason {
+                            code: CloseCode::Restart,
+                            description: Some(String::from("server restarting")),
+                        }))
+                        .await;
+                    return;
+                }
+            }
+        }
+        let frame = serde_json::to_string(&AuditRecordJSONContract::new(record)).unwrap();
+        if session.text(frame).await.is_err() {
+            return;
+        }
+    }
+    let _ = session
+        .close(Some(CloseReason {
+            code: CloseCode::Normal,
+            description: Some(String::from("playback finished")),
+        }))
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::delay;
+
+    #[test]
+    fn gaps_are_scaled() {
+        let start = SystemTime::UNIX_EPOCH;
+        let later = start + Duration::from_secs(3);
+        assert_eq!(delay(start, later, 1.0), Duration::from_secs(3));
+        assert_eq!(delay(start, later, 2.0), Duration::from_millis(1500));
+        assert_eq!(delay(start, later, 0.5), Duration::from_secs(6));
+        // the clock of the server went back
+        assert_eq!(delay(later, start, 1.0), Duration::ZERO);
+        assert_eq!(delay(start, later, 1e-300), Duration::MAX);
+    }
+}
@@ -0,0 +1,501 @@
+use crate::ot::operations::{ArcOperation, Operation};
+
+use super::{
+    contracts::{
+        Capability, ClientMessage, ErrorJSONContract, HelloJSONContract, OperationJSONContract,
+        OperationJSONFreeCopy, PresenceJSONContract, RevisionJSONContract, RoleJSONContract,
+        ServerMessage,
+    },
+    encoding::{Encoding, Frame},
+};
+use crate::collaboration::access::Role;
+
+/// Protocol versions supported by the server, the last one is the newest
+pub(super) const SUPPORTED_VERSIONS: [u32; 4] = [1, 2, 3, 4];
+
+/// Capabilities supported by the server
+pub(super) const SERVER_CAPABILITIES: [Capability; 2] = [Capability::Binary, Capability::Presence];
+
+/// Agreed parameters of a `/ws` connection.
+///
+/// * version 1: messages are not tagged, clients only send operations
+///   and receive operations, errors and roles.
+/// * version 2: every message has a `type` field.
+/// * version 3: one connection serves several documents, the client subscribes to them
+///   with `subscribe` and `unsubscribe` messages and the messages about documents
+///   carry a `document` field.
+/// * version 4: the connection which submitted an operation receives `ack` with its revision
+///   instead of the operation, the other operations carry the `id` given by their authors.
+///   `subscribe` can carry `since`, then the server sends the missed operations
+///   and `subscribed` with the next revision.
+///
+/// With the `presence` capability a multiplexed connection sends `presence` with the cursor
+/// of the client and receives the cursors of the other clients, `null` when a client has left.
+///
+/// In every version an operation of the client can carry an `id`, it is kept in the audit log
+/// and returned in the `error` sent if the operation isn't applied. An operation is
+/// an `INSERT`, a `DELETE`, a `MOVE`, a `REPLACE` or a `FORMAT` of a range with attributes,
+/// or a `TRANSACTION` of operations applied atomically under one revision.
+#[derive(Debug, Clone)]
+pub(super) struct Protocol {
+    version: u32,
+    encoding: Encoding,
+    capabilities: Vec<Capability>,
+}
+
+impl Protocol {
+    /// Accepts the hello of the client.
+    ///
+    /// Returns the close reason if the version of the client isn't supported.
+    pub fn accept(hello: HelloJSONContract, encoding: Encoding) -> Result<Self, String> {
+        if !SUPPORTED_VERSIONS.contains(&hello.version) {
+            return Err(format!(
+                "unsupported protocol version {}, supported versions: {}",
+                hello.version,
+                SUPPORTED_VERSIONS
+                    .map(|version| version.to_string())
+                    .join(", ")
+            ));
+        }
+
+        let capabilities = hello
+            .capabilities
+            .into_iter()
+            .filter(|capability| SERVER_CAPABILITIES.contains(capability))
+            .collect();
+        Ok(Protocol {
+            version: hello.version,
+            encoding,
+            capabilities,
+        })
+    }
+
+    /// Whether one connection serves several documents
+    pub fn multiplexed(&self) -> bool {
+        self.version >= 3
+    }
+
+    /// Whether the author of an operation receives `ack` instead of the operation
+    pub fn acknowledges(&self) -> bool {
+        self.version >= 4
+    }
+
+    /// Checks that both sides support `capability`
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Hello of the server, it carries the agreed version and all server capabilities
+    pub fn hello(&self) -> Frame {
+        self.encoding
+            .encode(&ServerMessage::Hello(HelloJSONContract {
+                version: self.version,
+                capabilities: SERVER_CAPABILITIES.to_vec(),
+            }))
+    }
+
+    /// `id` is the id given to the operation by its author, it is sent since the fourth version
+    pub fn operation(&self, document: &str, operation: &ArcOperation, id: Option<&str>) -> Frame {
+        let id = id.filter(|_| self.acknowledges());
+        self.encode(ServerMessage::Operation(
+            OperationJSONFreeCopy::from_operation(operation)
+                .with_document(self.tag(document))
+                .with_id(id),
+        ))
+    }
+
+    pub fn error(&self, document: Option<&str>, error: &str) -> Frame {
+        let document = document.and_then(|document| self.tag(document));
+        self.encode(ServerMessage::Error(ErrorJSONContract::new(
+            document, error,
+        )))
+    }
+
+    /// Error about the operation with the id, so its author knows which operation failed
+    pub fn operation_error(&self, document: Option<&str>, id: Option<&str>, error: &str) -> Frame {
+        let document = document.and_then(|document| self.tag(document));
+        let error = ErrorJSONContract::new(document, error);
+        self.encode(ServerMessage::Error(error.with_id(id)))
+    }
+
+    /// Error about the operation with the id which the document rejected
+    pub fn rejected(&self, document: &str, id: Option<&str>) -> Frame {
+        self.operation_error(Some(document), id, "operation rejected by the document")
+    }
+
+    pub fn ack(&self, document: &str, revision: usize) -> Frame {
+        self.encode(ServerMessage::Ack(RevisionJSONContract::new(
+            document, revision,
+        )))
+    }
+
+    pub fn subscribed(&self, document: &str, revision: usize) -> Frame {
+        self.encode(ServerMessage::Subscribed(RevisionJSONContract::new(
+            document, revision,
+        )))
+    }
+
+    pub fn presence(&self, document: &str, client: &str, cursor: Option<usize>) -> Frame {
+        self.encode(ServerMessage::Presence(PresenceJSONContract::new(
+            document, client, cursor,
+        )))
+    }
+
+    pub fn role(&self, document: &str, role: Role) -> Frame {
+        self.encode(ServerMessage::Role(RoleJSONContract::new(
+            self.tag(document),
+            role,
+        )))
+    }
+
+    /// Decodes the message sent by the client in a frame of `encoding`.
+    ///
+    /// Returns `None` if the message is malformed or not supported by the protocol version.
+    pub fn decode(&self, encoding: Encoding, payload: &[u8]) -> Option<Request> {
+        let message = match self.version {
+            1 => ClientMessage::Operation(encoding.decode::<Box<OperationJSONContract>>(payload)?),
+            _ => encoding.decode::<ClientMessage>(payload)?,
+        };
+
+        match message {
+            ClientMessage::Operation(mut operation) => {
+                let document = match self.multiplexed() {
+                    true => operation.document.take(),
+                    false => None,
+                };
+                let id = operation.id.take();
+                Some(Request::Operation(
+                    document,
+                    operation.into_operation()?,
+                    id,
+                ))
+            }
+            ClientMessage::Subscribe(subscription) if self.multiplexed() => Some(
+                Request::Subscribe(subscription.document, subscription.since),
+            ),
+            ClientMessage::Unsubscribe(subscription) if self.multiplexed() => {
+                Some(Request::Unsubscribe(subscription.document))
+            }
+            ClientMessage::Presence(presence)
+                if self.multiplexed() && self.supports(Capability::Presence) =>
+            {
+                Some(Request::Presence(presence.document, presence.cursor))
+            }
+            _ => None,
+        }
+    }
+
+    /// Documents are named in the messages only when the connection is multiplexed
+    fn tag<'a>(&self, document: &'a str) -> Option<&'a str> {
+        self.multiplexed().then_some(document)
+    }
+
+    fn encode(&self, message: ServerMessage) -> Frame {
+        match self.version {
+            1 => match message {
+                ServerMessage::Hello(hello) => self.encoding.encode(&hello),
+                ServerMessage::Operation(operation) => self.encoding.encode(&operation),
+                ServerMessage::Error(error) => self.encoding.encode(&error),
+                ServerMessage::Role(role) => self.encoding.encode(&role),
+                ServerMessage::Ack(ack) => self.encoding.encode(&ack),
+                ServerMessage::Subscribed(subscribed) => self.encoding.encode(&subscribed),
+                ServerMessage::Presence(presence) => self.encoding.encode(&presence),
+            },
+            _ => self.encoding.encode(&message),
+        }
+    }
+}
+
+/// Message of the client after the handshake
+pub(super) enum Request {
+    /// Operation on the named document and the id which the client gave to the operation,
+    /// the document is `None` before the third version
+    Operation(Option<String>, Operation, Option<String>),
+    /// Document and the first revision the client doesn't know
+    Subscribe(String, Option<usize>),
+    Unsubscribe(String),
+    /// Document and the cursor of the client in it, `None` if the client has left it
+    Presence(String, Option<usize>),
+}
+
+/// Decodes the hello of the client, it is tagged in all protocol versions
+pub(super) fn decode_hello(encoding: Encoding, payload: &[u8]) -> Option<HelloJSONContract> {
+    match encoding.decode::<ClientMessage>(payload)? {
+        ClientMessage::Hello(hello) => Some(hello),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{decode_hello, Capability, Protocol, Request};
+    use crate::{
+        api::{contracts::HelloJSONContract, encoding::Encoding},
+        ot::{
+            json::JsonOperation,
+            operations::{
+                InsertOperation, MoveOperation, Operation, OperationTrait, ReplaceOperation,
+                TransactionOperation,
+            },
+            rich_text::FormatOperation,
+            sequence::SequenceOperation,
+        },
+    };
+
+    fn protocol(version: u32) -> Protocol {
+        Protocol::accept(
+            HelloJSONContract {
+                version,
+                capabilities: vec![],
+            },
+            Encoding::Json,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let hello = decode_hello(Encoding::Json, br#"{"type": "hello", "version": 5}"#).unwrap();
+        assert_eq!(
+            Protocol::accept(hello, Encoding::Json).unwrap_err(),
+            "unsupported protocol version 5, supported versions: 1, 2, 3, 4"
+        );
+    }
+
+    #[test]
+    fn capabilities_are_agreed() {
+        let hello = decode_hello(
+            Encoding::Json,
+            br#"{"type": "hello", "version": 2, "capabilities": ["binary", "compose"]}"#,
+        )
+        .unwrap();
+        let protocol = Protocol::accept(hello, Encoding::Json).unwrap();
+        assert!(protocol.supports(super::Capability::Binary));
+        assert!(!protocol.supports(super::Capability::Compose));
+    }
+
+    #[test]
+    fn operation_frames() {
+        let operation: Operation = Box::new(InsertOperation::new(1, 0, String::from("a")));
+        let operation = Arc::new(operation);
+
+        assert_eq!(
+            protocol(1).operation("doc", &operation, None).as_bytes(),
+            br#"{"kind":"INSERT","position":1,"revision":0,"content":"a"}"#
+        );
+        assert_eq!(
+            protocol(2).operation("doc", &operation, None).as_bytes(),
+            br#"{"type":"operation","kind":"INSERT","position":1,"revision":0,"content":"a"}"#
+        );
+        assert_eq!(
+            protocol(3).operation("doc", &operation, None).as_bytes(),
+            br#"{"type":"operation","document":"doc","kind":"INSERT","position":1,"revision":0,"content":"a"}"#
+        );
+        // the ids are sent since the fourth version
+        assert_eq!(
+            protocol(3).operation("doc", &operation, Some("a-1")).as_bytes(),
+            br#"{"type":"operation","document":"doc","kind":"INSERT","position":1,"revision":0,"content":"a"}"#
+        );
+        assert_eq!(
+            protocol(4).operation("doc", &operation, Some("a-1")).as_bytes(),
+            br#"{"type":"operation","document":"doc","id":"a-1","kind":"INSERT","position":1,"revision":0,"content":"a"}"#
+        );
+    }
+
+    #[test]
+    fn presence_needs_capability() {
+        let message = br#"{"type":"presence","document":"doc","cursor":4}"#;
+        let with_presence = Protocol::accept(
+            HelloJSONContract {
+                version: 3,
+                capabilities: vec![Capability::Presence],
+            },
+            Encoding::Json,
+        )
+        .unwrap();
+
+        assert!(protocol(3).decode(Encoding::Json, message).is_none());
+        assert!(matches!(
+            with_presence.decode(Encoding::Json, message),
+            Some(Request::Presence(document, Some(4))) if document == "doc"
+        ));
+        assert_eq!(
+            with_presence.presence("doc", "bob", None).as_bytes(),
+            br#"{"type":"presence","document":"doc","client":"bob","cursor":null}"#
+        );
+    }
+
+    #[test]
+    fn revision_frames() {
+        assert!(!protocol(3).acknowledges());
+        assert!(protocol(4).acknowledges());
+        assert_eq!(
+            protocol(4).ack("doc", 3).as_bytes(),
+            br#"{"type":"ack","document":"doc","revision":3}"#
+        );
+        assert_eq!(
+            protocol(4).subscribed("doc", 5).as_bytes(),
+            br#"{"type":"subscribed","document":"doc","revision":5}"#
+        );
+        assert_eq!(
+            protocol(4).rejected("doc", Some("a-1")).as_bytes(),
+            br#"{"type":"error","document":"doc","error":"operation rejected by the document","id":"a-1"}"#
+        );
+    }
+
+    #[test]
+    fn decode_operation() {
+        let v1 = br#"{"kind":"DELETE","position":1,"revision":0,"length":2}"#;
+        let v2 = br#"{"type":"operation","kind":"DELETE","position":1,"revision":0,"length":2}"#;
+        let v3 = br#"{"type":"operation","document":"doc","kind":"DELETE","position":1,"revision":0,"length":2}"#;
+
+        assert!(matches!(
+            protocol(1).decode(Encoding::Json, v1),
+            Some(Request::Operation(None, _, None))
+        ));
+        assert!(protocol(2).decode(Encoding::Json, v1).is_none());
+        assert!(matches!(
+            protocol(2).decode(Encoding::Json, v2),
+            Some(Request::Operation(None, _, None))
+        ));
+        assert!(matches!(
+            protocol(2).decode(Encoding::Json, v3),
+            Some(Request::Operation(None, _, None))
+        ));
+        assert!(matches!(
+            protocol(3).decode(Encoding::Json, v3),
+            Some(Request::Operation(Some(document), _, None)) if document == "doc"
+        ));
+        assert!(matches!(
+            protocol(4).decode(
+                Encoding::Json,
+                br#"{"type":"operation","document":"doc","id":"a-1","kind":"DELETE","position":1,"revision":0,"length":2}"#
+            ),
+            Some(Request::Operation(_, _, Some(id))) if id == "a-1"
+        ));
+    }
+
+    #[test]
+    fn format_frames() {
+        let frame = br#"{"type":"operation","document":"doc","kind":"FORMAT","position":1,"revision":0,"spans":[{"length":3,"attributes":{"bold":true}},{"length":2,"attributes":{"link":null}}]}"#;
+
+        let Some(Request::Operation(_, operation, None)) =
+            protocol(4).decode(Encoding::Json, frame)
+        else {
+            panic!("format operation isn't decoded");
+        };
+        let format = operation.downcast::<FormatOperation>().unwrap();
+        assert_eq!((format.position(), format.len()), (1, 5));
+        assert_eq!(
+            protocol(4)
+                .operation("doc", &Arc::new(operation), None)
+                .as_bytes(),
+            frame
+        );
+    }
+
+    #[test]
+    fn json_frames() {
+        let frame = br#"{"type":"operation","document":"doc","kind":"JSON","revision":0,"path":["items",1],"action":{"type":"insert","value":{"done":false}}}"#;
+
+        let Some(Request::Operation(_, operation, None)) =
+            protocol(4).decode(Encoding::Json, frame)
+        else {
+            panic!("JSON operation isn't decoded");
+        };
+        assert!(operation.downcast::<JsonOperation>().is_some());
+        assert_eq!(
+            protocol(4)
+                .operation("doc", &Arc::new(operation), None)
+                .as_bytes(),
+            frame
+        );
+        // only JSON operations have no position
+        assert!(protocol(4)
+            .decode(
+                Encoding::Json,
+                br#"{"type":"operation","document":"doc","kind":"INSERT","revision":0,"content":"a"}"#
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn list_frames() {
+        let frame = br#"{"type":"operation","document":"doc","kind":"LIST","revision":2,"action":{"type":"delete","ranges":[{"start":1,"end":3}]}}"#;
+
+        let Some(Request::Operation(_, operation, None)) =
+            protocol(4).decode(Encoding::Json, frame)
+        else {
+            panic!("list operation isn't decoded");
+        };
+        assert!(operation
+            .downcast::<SequenceOperation<serde_json::Value>>()
+            .is_some());
+        assert_eq!(
+            protocol(4)
+                .operation("doc", &Arc::new(operation), None)
+                .as_bytes(),
+            frame
+        );
+    }
+
+    #[test]
+    fn move_frames() {
+        let single = br#"{"type":"operation","document":"doc","kind":"MOVE","position":1,"revision":2,"length":3,"to":6}"#;
+        let several = br#"{"type":"operation","document":"doc","kind":"MOVE","revision":2,"ranges":[{"start":1,"end":2},{"start":4,"end":5}],"to":6}"#;
+
+        for frame in [&single[..], &several[..]] {
+            let Some(Request::Operation(_, operation, None)) =
+                protocol(4).decode(Encoding::Json, frame)
+            else {
+                panic!("move operation isn't decoded");
+            };
+            assert!(operation.downcast::<MoveOperation>().is_some());
+            assert_eq!(
+                protocol(4)
+                    .operation("doc", &Arc::new(operation), None)
+                    .as_bytes(),
+                frame
+            );
+        }
+    }
+
+    #[test]
+    fn transaction_frames() {
+        let frame = br#"{"type":"operation","document":"doc","kind":"TRANSACTION","revision":2,"operations":[{"kind":"REPLACE","position":1,"content":"ab","length":3},{"kind":"REPLACE","position":6,"content":"ab","ranges":[{"start":4,"end":5},{"start":7,"end":8}]}]}"#;
+
+        let Some(Request::Operation(_, operation, None)) =
+            protocol(4).decode(Encoding::Json, frame)
+        else {
+            panic!("transaction isn't decoded");
+        };
+        let transaction = operation.downcast::<TransactionOperation>().unwrap();
+        assert_eq!(transaction.operations().len(), 2);
+        assert!(transaction.operations()[1]
+            .downcast::<ReplaceOperation>()
+            .is_some());
+        assert_eq!(
+            protocol(4)
+                .operation("doc", &Arc::new(operation), None)
+                .as_bytes(),
+            frame
+        );
+    }
+
+    #[test]
+    fn subscriptions_need_multiplexing() {
+        let subscribe = br#"{"type":"subscribe","document":"doc"}"#;
+
+        assert!(protocol(2).decode(Encoding::Json, subscribe).is_none());
+        assert!(matches!(
+            protocol(3).decode(Encoding::Json, subscribe),
+            Some(Request::Subscribe(document, None)) if document == "doc"
+        ));
+        assert!(matches!(
+            protocol(4).decode(Encoding::Json, br#"{"type":"subscribe","document":"doc","since":3}"#),
+            Some(Request::Subscribe(document, Some(3))) if document == "doc"
+        ));
+    }
+}
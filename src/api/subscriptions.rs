@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use actix_web::web;
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, watch,
+    },
+    task::JoinHandle,
+};
+use tracing::Instrument;
+
+use crate::{
+    collaboration::{
+        access::Role,
+        manager::Manager,
+        sessions::{Presence, Rejection, Submission},
+    },
+    metrics::METRICS,
+    ot::operations::ArcOperation,
+};
+
+/// What has to be sent to the client about one of its documents
+pub(super) enum Event {
+    /// The operation, the id given to it by its author and whether the connection submitted it
+    Operation(String, ArcOperation, Option<String>, bool),
+    /// Id of an operation of the connection which the document rejected
+    Rejected(String, Option<String>),
+    Role(String, Role),
+    /// The missed operations are sent, the revision is the next one
+    Subscribed(String, usize),
+    /// Cursor of another client
+    Presence(String, Presence),
+}
+
+struct Subscription {
+    input: mpsc::Sender<Submission>,
+    role: watch::Receiver<Role>,
+    forwarder: JoinHandle<()>,
+}
+
+/// Documents of one connection.
+///
+/// The operations, role changes and cursors of every document are forwarded to one channel,
+/// so the connection has a single writer whatever the number of documents.
+pub(super) struct Subscriptions {
+    client_name: String,
+    /// Number of the connection, see [`Submission::connection`]
+    connection: u64,
+    manager: web::Data<Manager>,
+    events: mpsc::Sender<Event>,
+    documents: HashMap<String, Subscription>,
+}
+
+impl Subscriptions {
+    pub fn new(
+        client_name: String,
+        connection: u64,
+        manager: web::Data<Manager>,
+    ) -> (Self, mpsc::Receiver<Event>) {
+        let (events, events_receiver) = mpsc::channel(64);
+        (
+            Subscriptions {
+                client_name,
+                connection,
+                manager,
+                events,
+                documents: HashMap::new(),
+            },
+            events_receiver,
+        )
+    }
+
+    pub fn client_name(&self) -> &str {
+        &self.client_name
+    }
+
+    pub fn connection(&self) -> u64 {
+        self.connection
+    }
+
+    /// Subscribes the connection to the document.
+    ///
+    /// If `since` is set, the operations starting from that revision are sent first,
+    /// followed by [`Event::Subscribed`].
+    pub async fn subscribe(&mut self, document_id: String, since: Option<usize>) {
+        if self.documents.contains_key(&document_id) {
+            return;
+        }
+
+        let (client_name, cur_document_id) = (self.client_name.clone(), document_id.clone());
+        let manager = self.manager.clone();
+        let (input, output, role) =
+            tokio::task::spawn_blocking(move || manager.connect(client_name, cur_document_id))
+                .await
+                .unwrap();
+        // taken before the connection can submit anything to the document
+        let Some(rejections) = self.manager.rejections(&document_id) else {
+            return;
+        };
+
+        tracing::info!(document = %document_id, since, "subscribed");
+        let span = tracing::info_span!("document", document = %document_id);
+        let forwarder = tokio::spawn(
+            Self::forward(
+                Forwarder {
+                    document_id: document_id.clone(),
+                    client_name: self.client_name.clone(),
+                    connection: self.connection,
+                    manager: self.manager.clone(),
+                    events: self.events.clone(),
+                },
+                since,
+                output,
+                rejections,
+                role.clone(),
+            )
+            .instrument(span),
+        );
+        self.documents.insert(
+            document_id,
+            Subscription {
+                input,
+                role,
+                forwarder,
+            },
+        );
+    }
+
+    /// Returns `false` if the connection wasn't subscribed to the document
+    pub fn unsubscribe(&mut self, document_id: &str) -> bool {
+        match self.documents.remove(document_id) {
+            Some(subscription) => {
+                tracing::info!(document = %document_id, "unsubscribed");
+                subscription.forwarder.abort();
+                self.manager
+                    .set_presence(document_id, self.client_name.clone(), None);
+                self.manager
+                    .disconnect(self.client_name.clone(), document_id.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns `false` if the connection isn't subscribed to the document
+    pub fn set_presence(&self, document_id: &str, cursor: Option<usize>) -> bool {
+        if !self.documents.contains_key(document_id) {
+            return false;
+        }
+        self.manager
+            .set_presence(document_id, self.client_name.clone(), cursor);
+        true
+    }
+
+    /// Input of the document and the actual role of the client in it
+    pub fn get(
+        &self,
+        document_id: &str,
+    ) -> Option<(&mpsc::Sender<Submission>, &watch::Receiver<Role>)> {
+        self.documents
+            .get(document_id)
+            .map(|subscription| (&subscription.input, &subscription.role))
+    }
+
+    async fn forward(
+        forwarder: Forwarder,
+        since: Option<usize>,
+        mut operation_receiver: broadcast::Receiver<ArcOperation>,
+        mut rejections: broadcast::Receiver<Rejection>,
+        mut role: watch::Receiver<Role>,
+    ) {
+        let Some((cursors, mut presence)) = forwarder.manager.presence(&forwarder.document_id)
+        else {
+            return;
+        };
+
+        // the receiver was created before the history was taken,
+        // so it can repeat the last operations of the history.
+        // Without `since` the next revision is unknown until the first operation
+        let mut next_revision = None;
+        if let Some(since) = since {
+            let Some(next) = forwarder.catch_up(since).await else {
+                return;
+            };
+            next_revision = Some(next);
+            let subscribed = Event::Subscribed(forwarder.document_id.clone(), next);
+            if forwarder.events.send(subscribed).await.is_err() {
+                return;
+            }
+        }
+        for cursor in cursors {
+            if !forwarder.send_presence(cursor).await {
+                return;
+            }
+        }
+
+        // operations missed before the first one was received
+        let mut missed_first = 0;
+        loop {
+            let sent = tokio::select! {
+                operation = operation_receiver.recv() => match operation {
+                    Ok(operation) => {
+                        if next_revision.is_none() && missed_first > 0 {
+                            // the missed operations go right before the oldest kept one
+                            let since = operation.revision().saturating_sub(missed_first);
+                            let Some(next) = forwarder.catch_up(since).await else {
+                                break;
+                            };
+                            next_revision = Some(next);
+                        }
+                        if operation.revision() < next_revision.unwrap_or(0) {
+                            continue;
+                        }
+                        next_revision = Some(operation.revision() + 1);
+                        forwarder.send_operation(operation).await
+                    }
+                    // the missed operations are taken from the history
+                    Err(RecvError::Lagged(missed)) => {
+                        METRICS.broadcast_lagged.add(missed);
+                        tracing::warn!(missed, "the subscriber lagged behind the broadcast");
+                        match next_revision {
+                            Some(next) => match forwarder.catch_up(next).await {
+                                Some(next) => {
+                                    next_revision = Some(next);
+                                    true
+                                }
+                                None => false,
+                            },
+                            None => {
+                                missed_first += missed as usize;
+                                true
+                            }
+                        }
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                Ok(()) = role.changed() => {
+                    let event = Event::Role(forwarder.document_id.clone(), *role.borrow_and_update());
+                    forwarder.events.send(event).await.is_ok()
+                },
+                Ok(cursor) = presence.recv() => forwarder.send_presence(cursor).await,
+                Ok(rejection) = rejections.recv() => forwarder.send_rejection(rejection).await,
+            };
+            if !sent {
+                break;
+            }
+        }
+    }
+}
+
+/// Forwards the events of one document to the connection
+struct Forwarder {
+    document_id: String,
+    client_name: String,
+    connection: u64,
+    manager: web::Data<Manager>,
+    events: mpsc::Sender<Event>,
+}
+
+impl Forwarder {
+    /// Sends the operations of the history starting from revision `since`.
+    ///
+    /// Returns the revision after them, `None` if the connection is closed.
+    async fn catch_up(&self, since: usize) -> Option<usize> {
+        let history = self
+            .manager
+            .operations_since(&self.document_id, since)
+            .map(|(history, _)| history)
+            .unwrap_or_default();
+        let next_revision = since + history.len();
+        for operation in history {
+            if !self.send_operation(operation).await {
+                return None;
+            }
+        }
+        Some(next_revision)
+    }
+
+    /// Returns `false` if the connection is closed
+    async fn send_operation(&self, operation: ArcOperation) -> bool {
+        let (operation_id, own) = match self.manager.stamp(&self.document_id, operation.revision())
+        {
+            Some(entry) => (
+                entry.operation_id,
+                entry.connection == Some(self.connection),
+            ),
+            None => (None, false),
+        };
+        let event = Event::Operation(self.document_id.clone(), operation, operation_id, own);
+        self.events.send(event).await.is_ok()
+    }
+
+    /// Skips the operations of the other connections, returns `false` if the connection is closed
+    async fn send_rejection(&self, rejection: Rejection) -> bool {
+        if rejection.connection != self.connection {
+            return true;
+        }
+        let event = Event::Rejected(self.document_id.clone(), rejection.operation_id);
+        self.events.send(event).await.is_ok()
+    }
+
+    /// Skips the cursor of the client itself, returns `false` if the connection is closed
+    async fn send_presence(&self, presence: Presence) -> bool {
+        if presence.client == self.client_name {
+            return true;
+        }
+        let event = Event::Presence(self.document_id.clone(), presence);
+        self.events.send(event).await.is_ok()
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        let documents: Vec<String> = self.documents.keys().cloned().collect();
+        for document_id in documents {
+            self.unsubscribe(&document_id);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+//! Terminal client for manual testing of the server.
+//!
+//! ```text
+//! client --document <id> [--name <client>] [--url <ws url>] [--script <file>]
+//! ```
+//!
+//! Interactively the text of the document is shown with the cursors of the other clients,
+//! typed characters, `Enter`, `Backspace` and `Delete` are sent as operations,
+//! `Esc` or `Ctrl+C` quits.
+//!
+//! With `--script` the edits are read from the file, one per line, and the final text is printed:
+//!
+//! ```text
+//! insert <position> <text>
+//! delete <position> <len>
+//! replace <position> <len> <text>
+//! sleep <milliseconds>
+//! ```
+//!
+//! Positions are byte offsets in the local text, `\n` in the inserted text is a line break.
+
+use std::{collections::BTreeMap, io::Write, time::Duration};
+
+use crossterm::{
+    cursor,
+    event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Print, Stylize},
+    terminal::{self, ClearType},
+};
+use futures_util::StreamExt;
+use rust_live_server::{
+    client::{Client, ClientEvent, Config},
+    ot::operations::{InsertOperation, OperationTrait},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+const USAGE: &str =
+    "usage: client --document <id> [--name <client>] [--url <ws url>] [--script <file>]";
+
+struct Args {
+    url: String,
+    name: String,
+    document: String,
+    script: Option<String>,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut url = "ws://127.0.0.1:8080/ws".to_string();
+        let mut name = format!("terminal-{}", std::process::id());
+        let mut document = None;
+        let mut script = None;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = match arg.as_str() {
+                "--url" => &mut url,
+                "--name" => &mut name,
+                "--document" => document.insert(String::new()),
+                "--script" => script.insert(String::new()),
+                _ => return Err(format!("unknown argument {arg}")),
+            };
+            *value = args.next().ok_or(format!("{arg} needs a value"))?;
+        }
+
+        Ok(Args {
+            url,
+            name,
+            document: document.ok_or("--document is required")?,
+            script,
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let client = Client::connect(Config::new(args.url, args.name, args.document));
+    let result = match args.script {
+        Some(script) => run_script(&client, &script).await,
+        None => run_interactive(&client).await,
+    };
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+/// Replays the edits from the file and prints the resulting text
+async fn run_script(client: &Client, path: &str) -> Result<(), String> {
+    let script = std::fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+
+    // the positions of the script are in the text of the server
+    let mut events = client.subscribe();
+    loop {
+        match events.recv().await {
+            Ok(ClientEvent::Connected) => break,
+            Ok(ClientEvent::Disconnected) => eprintln!("connecting..."),
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return Err("the client is stopped".to_string()),
+        }
+    }
+
+    for (idx, line) in script.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        run_command(client, line)
+            .await
+            .map_err(|err| format!("{path}:{}: {err}", idx + 1))?;
+    }
+
+    client.synchronized().await;
+    println!("{}", client.text());
+    Ok(())
+}
+
+async fn run_command(client: &Client, line: &str) -> Result<(), String> {
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    let number = |value: &str| {
+        value
+            .parse::<usize>()
+            .map_err(|_| format!("{value:?} is not a number"))
+    };
+
+    match command {
+        "insert" => {
+            let (position, text) = args.split_once(' ').ok_or("insert <position> <text>")?;
+            let text = text.replace("\\n", "\n");
+            client
+                .insert(number(position)?, text)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        "delete" => {
+            let (position, len) = args.split_once(' ').ok_or("delete <position> <len>")?;
+            client
+                .delete(number(position)?, number(len)?)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        "replace" => {
+            let usage = "replace <position> <len> <text>";
+            let (position, args) = args.split_once(' ').ok_or(usage)?;
+            let (len, text) = args.split_once(' ').unwrap_or((args, ""));
+            let text = text.replace("\\n", "\n");
+            client
+                .replace(number(position)?, number(len)?, text)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        "sleep" => {
+            tokio::time::sleep(Duration::from_millis(number(args)? as u64)).await;
+            Ok(())
+        }
+        _ => Err(format!("unknown command {command}")),
+    }
+}
+
+/// What is shown on the screen besides the text
+struct View {
+    /// Byte offset of the cursor in the local text
+    cursor: usize,
+    /// Cursors of the other clients
+    others: BTreeMap<String, usize>,
+    status: String,
+}
+
+async fn run_interactive(client: &Client) -> Result<(), String> {
+    terminal::enable_raw_mode().map_err(|err| err.to_string())?;
+    let mut stdout = std::io::stdout();
+    let _ = execute!(stdout, terminal::EnterAlternateScreen);
+
+    let result = interact(client).await;
+
+    let _ = execute!(stdout, terminal::LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+    result
+}
+
+async fn interact(client: &Client) -> Result<(), String> {
+    let mut keys = EventStream::new();
+    let mut events = client.subscribe();
+    let mut text = client.watch_text();
+    let mut view = View {
+        cursor: 0,
+        others: BTreeMap::new(),
+        status: "connecting...".to_string(),
+    };
+
+    loop {
+        render(&client.text(), &view).map_err(|err| err.to_string())?;
+
+        tokio::select! {
+            key = keys.next() => match key {
+                Some(Ok(Event::Key(key))) => {
+                    // the cursor must be moved by the remote operations before it is used
+                    while let Ok(event) = events.try_recv() {
+                        handle_event(event, &mut view);
+                    }
+                    if !handle_key(client, key, &mut view).await {
+                        return Ok(());
+                    }
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => return Err(err.to_string()),
+                None => return Ok(()),
+            },
+            event = events.recv() => match event {
+                Ok(event) => handle_event(event, &mut view),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return Err("the client is stopped".to_string()),
+            },
+            changed = text.changed() => {
+                if changed.is_err() {
+                    return Err("the client is stopped".to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Returns `false` when the client has to quit
+async fn handle_key(client: &Client, key: KeyEvent, view: &mut View) -> bool {
+    if key.kind == KeyEventKind::Release {
+        return true;
+    }
+
+    let text = client.text();
+    let cursor = clamp(&text, view.cursor);
+    let previous = text[..cursor].chars().next_back().map(char::len_utf8);
+    let next = text[cursor..].chars().next().map(char::len_utf8);
+
+    let result = match key.code {
+        KeyCode::Esc => return false,
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+        KeyCode::Char(char) => {
+            view.cursor = cursor + char.len_utf8();
+            client.insert(cursor, char.to_string()).await
+        }
+        KeyCode::Enter => {
+            view.cursor = cursor + 1;
+            client.insert(cursor, "\n".to_string()).await
+        }
+        KeyCode::Backspace => match previous {
+            Some(len) => {
+                view.cursor = cursor - len;
+                client.delete(cursor - len, len).await
+            }
+            None => Ok(()),
+        },
+        KeyCode::Delete => match next {
+            Some(len) => client.delete(cursor, len).await,
+            None => Ok(()),
+        },
+        KeyCode::Left => {
+            view.cursor = cursor - previous.unwrap_or(0);
+            Ok(())
+        }
+        KeyCode::Right => {
+            view.cursor = cursor + next.unwrap_or(0);
+            Ok(())
+        }
+        KeyCode::Home => {
+            view.cursor = text[..cursor].rfind('\n').map_or(0, |line| line + 1);
+            Ok(())
+        }
+        KeyCode::End => {
+            view.cursor = text[cursor..]
+                .find('\n')
+                .map_or(text.len(), |end| cursor + end);
+            Ok(())
+        }
+        _ => return true,
+    };
+
+    match result {
+        Ok(()) => {
+            let _ = client.set_cursor(view.cursor).await;
+        }
+        Err(err) => {
+            view.cursor = cursor;
+            view.status = err.to_string();
+        }
+    }
+    true
+}
+
+fn handle_event(event: ClientEvent, view: &mut View) {
+    match event {
+        ClientEvent::Connected => view.status = "connected".to_string(),
+        ClientEvent::Disconnected => view.status = "disconnected, reconnecting...".to_string(),
+        ClientEvent::Role(role) => view.status = format!("role: {role:?}"),
+        ClientEvent::Error(err) => view.status = format!("error: {err}"),
+        ClientEvent::Presence(client, Some(cursor)) => {
+            view.others.insert(client, cursor);
+        }
+        ClientEvent::Presence(client, None) => {
+            view.others.remove(&client);
+        }
+        ClientEvent::Remote(operation) => {
+            view.cursor = shift(view.cursor, operation.as_ref().as_ref());
+            for cursor in view.others.values_mut() {
+                *cursor = shift(*cursor, operation.as_ref().as_ref());
+            }
+        }
+    }
+}
+
+/// Moves the cursor the way an empty insertion at it would be moved
+fn shift(cursor: usize, operation: &dyn OperationTrait) -> usize {
+    let mut marker = InsertOperation::new(cursor, operation.revision(), String::new());
+    marker.transform_relative_to(operation);
+    marker.position()
+}
+
+/// Moves the position into the text and onto a character boundary
+fn clamp(text: &str, position: usize) -> usize {
+    let mut position = position.min(text.len());
+    while !text.is_char_boundary(position) {
+        position -= 1;
+    }
+    position
+}
+
+/// Line and column of the byte offset, both start from zero
+fn line_column(text: &str, position: usize) -> (usize, usize) {
+    let before = &text[..clamp(text, position)];
+    let line = before.matches('\n').count();
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |line| line.chars().count());
+    (line, column)
+}
+
+fn render(text: &str, view: &View) -> std::io::Result<()> {
+    let mut stdout = std::io::stdout();
+    queue!(
+        stdout,
+        terminal::Clear(ClearType::All),
+        cursor::MoveTo(0, 0),
+        Print(view.status.as_str().reverse()),
+    )?;
+    for (name, cursor) in &view.others {
+        let (line, column) = line_column(text, *cursor);
+        queue!(
+            stdout,
+            Print(format!("  {name} {}:{}", line + 1, column + 1))
+        )?;
+    }
+
+    for (idx, line) in text.split('\n').enumerate() {
+        queue!(stdout, cursor::MoveTo(0, idx as u16 + 1), Print(line))?;
+    }
+
+    let (line, column) = line_column(text, view.cursor);
+    queue!(stdout, cursor::MoveTo(column as u16, line as u16 + 1))?;
+    stdout.flush()
+}
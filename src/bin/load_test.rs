@@ -0,0 +1,390 @@
+//! Load generator for the `/ws` endpoint.
+//!
+//! ```text
+//! load_test [--url <ws url>] [--connections <n>] [--documents <n>]
+//!           [--rate <ops/sec per connection>] [--duration <seconds>]
+//! ```
+//!
+//! The connections are spread over the documents evenly and speak the fourth protocol version
+//! in JSON. When all connections are open, every connection inserts at the start of its document
+//! at the given rate. The inserted text is the time of sending, so the receivers measure
+//! the broadcast latency without a shared clock. The report contains the throughput of acknowledged
+//! operations, p50/p99 of the acknowledgement and broadcast latencies and the errors.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore},
+    time::MissedTickBehavior,
+};
+use tokio_tungstenite::{
+    tungstenite::{client::IntoClientRequest, http::HeaderValue, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+const USAGE: &str = "usage: load_test [--url <ws url>] [--connections <n>] [--documents <n>] \
+                     [--rate <ops/sec per connection>] [--duration <seconds>]";
+
+/// How many connections are opened at the same time
+const CONNECTING_LIMIT: usize = 64;
+
+/// How long the messages are received after the sending stops
+const DRAIN_TIME: Duration = Duration::from_secs(2);
+
+struct Args {
+    url: String,
+    connections: usize,
+    documents: usize,
+    rate: f64,
+    duration: Duration,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = Args {
+            url: "ws://127.0.0.1:8080/ws".to_string(),
+            connections: 100,
+            documents: 10,
+            rate: 1.0,
+            duration: Duration::from_secs(10),
+        };
+
+        let mut values = std::env::args().skip(1);
+        while let Some(arg) = values.next() {
+            let value = values.next().ok_or(format!("{arg} needs a value"))?;
+            let invalid = || format!("invalid value of {arg}: {value}");
+            match arg.as_str() {
+                "--url" => args.url = value,
+                "--connections" => args.connections = value.parse().map_err(|_| invalid())?,
+                "--documents" => args.documents = value.parse().map_err(|_| invalid())?,
+                "--rate" => args.rate = value.parse().map_err(|_| invalid())?,
+                "--duration" => {
+                    args.duration = Duration::from_secs(value.parse().map_err(|_| invalid())?)
+                }
+                _ => return Err(format!("unknown argument {arg}")),
+            }
+        }
+
+        if args.connections == 0 || args.documents == 0 || args.rate <= 0.0 {
+            return Err("the numbers must be positive".to_string());
+        }
+        Ok(args)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage {
+    Hello {},
+    Operation {
+        revision: usize,
+        content: Option<String>,
+    },
+    Ack {
+        revision: usize,
+    },
+    Error {
+        error: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// What happened to one connection
+#[derive(Default)]
+struct Stats {
+    connected: bool,
+    sent: usize,
+    acknowledged: usize,
+    /// Error messages of the server and their numbers
+    errors: BTreeMap<String, usize>,
+    disconnected: bool,
+    /// Time between sending an operation and its acknowledgement, in microseconds
+    ack_latencies: Vec<u64>,
+    /// Time between sending an operation and receiving it by another client, in microseconds
+    broadcast_latencies: Vec<u64>,
+}
+
+impl Stats {
+    fn merge(&mut self, other: Stats) {
+        self.sent += other.sent;
+        self.acknowledged += other.acknowledged;
+        for (error, count) in other.errors {
+            *self.errors.entry(error).or_default() += count;
+        }
+        self.ack_latencies.extend(other.ack_latencies);
+        self.broadcast_latencies.extend(other.broadcast_latencies);
+    }
+}
+
+/// Phases of the test shared by all connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Connecting,
+    Sending,
+    Draining,
+    Done,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let epoch = Instant::now();
+    let (phase_sender, phase) = watch::channel(Phase::Connecting);
+    let (ready_sender, mut ready) = mpsc::channel(args.connections);
+    let connecting = Arc::new(Semaphore::new(CONNECTING_LIMIT));
+
+    let tasks: Vec<_> = (0..args.connections)
+        .map(|idx| {
+            let connection = Connection {
+                url: format!("{}?client=load-{idx}", args.url),
+                document: format!("load-{}", idx % args.documents),
+                period: Duration::from_secs_f64(1.0 / args.rate),
+                epoch,
+            };
+            let phase = phase.clone();
+            let connecting = connecting.clone();
+            let ready = ready_sender.clone();
+            tokio::spawn(async move {
+                let permit = connecting.acquire_owned().await.unwrap();
+                connection.run(phase, permit, ready).await
+            })
+        })
+        .collect();
+
+    eprintln!("opening {} connections...", args.connections);
+    // every connection reports once it has opened or failed
+    for _ in 0..args.connections {
+        ready.recv().await;
+    }
+
+    eprintln!("sending for {:?}...", args.duration);
+    phase_sender.send_replace(Phase::Sending);
+    let start = Instant::now();
+    tokio::time::sleep(args.duration).await;
+    let elapsed = start.elapsed();
+    phase_sender.send_replace(Phase::Draining);
+    tokio::time::sleep(DRAIN_TIME).await;
+    phase_sender.send_replace(Phase::Done);
+
+    let mut total = Stats::default();
+    let (mut opened, mut failed, mut disconnected) = (0, 0, 0);
+    for task in tasks {
+        let stats = task.await.unwrap_or_default();
+        match stats.connected {
+            true => opened += 1,
+            false => failed += 1,
+        }
+        if stats.disconnected {
+            disconnected += 1;
+        }
+        total.merge(stats);
+    }
+
+    println!("connections: {opened} opened, {failed} failed, {disconnected} disconnected");
+    println!(
+        "operations: {} sent, {} acknowledged, {} rejected",
+        total.sent,
+        total.acknowledged,
+        total.errors.values().sum::<usize>()
+    );
+    println!(
+        "throughput: {:.1} ops/sec",
+        total.acknowledged as f64 / elapsed.as_secs_f64()
+    );
+    println!("ack latency: {}", percentiles(&mut total.ack_latencies));
+    println!(
+        "broadcast latency: {}",
+        percentiles(&mut total.broadcast_latencies)
+    );
+    for (error, count) in total.errors {
+        println!("error: {error} ({count} times)");
+    }
+}
+
+fn percentiles(latencies: &mut [u64]) -> String {
+    if latencies.is_empty() {
+        return "no samples".to_string();
+    }
+    latencies.sort_unstable();
+    let percentile = |p: f64| {
+        let idx = ((latencies.len() - 1) as f64 * p).round() as usize;
+        latencies[idx] as f64 / 1000.0
+    };
+    format!(
+        "p50 {:.2} ms, p99 {:.2} ms, max {:.2} ms ({} samples)",
+        percentile(0.5),
+        percentile(0.99),
+        percentile(1.0),
+        latencies.len()
+    )
+}
+
+struct Connection {
+    url: String,
+    document: String,
+    period: Duration,
+    /// Start of the clock of the inserted timestamps
+    epoch: Instant,
+}
+
+impl Connection {
+    async fn run(
+        self,
+        mut phase: watch::Receiver<Phase>,
+        permit: OwnedSemaphorePermit,
+        ready: mpsc::Sender<()>,
+    ) -> Stats {
+        let mut stats = Stats::default();
+        let connection = self.open().await;
+        drop(permit);
+        let _ = ready.send(()).await;
+        let Some(connection) = connection else {
+            return stats;
+        };
+        stats.connected = true;
+        let (mut sink, mut stream) = connection.split();
+
+        let _ = phase.wait_for(|phase| *phase != Phase::Connecting).await;
+        let mut ticks = tokio::time::interval(self.period);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        // sending times of the operations which aren't acknowledged yet
+        let mut in_flight = VecDeque::new();
+        // the operations are based on the last known revision,
+        // so the server transforms them only relative to the concurrent ones
+        let mut revision = 0;
+
+        loop {
+            let current_phase = *phase.borrow();
+            tokio::select! {
+                _ = ticks.tick(), if current_phase == Phase::Sending => {
+                    let sent_at = self.epoch.elapsed();
+                    let operation = json!({
+                        "type": "operation",
+                        "document": self.document,
+                        "kind": "INSERT",
+                        "position": 0,
+                        "revision": revision,
+                        "content": format!("{};", sent_at.as_micros()),
+                    });
+                    if sink.send(Message::Text(operation.to_string())).await.is_err() {
+                        stats.disconnected = true;
+                        break;
+                    }
+                    in_flight.push_back(sent_at);
+                    stats.sent += 1;
+                }
+                message = stream.next() => {
+                    let Some(Ok(message)) = message else {
+                        stats.disconnected = true;
+                        break;
+                    };
+                    if let Some(applied) = self.handle(message, &mut in_flight, &mut stats) {
+                        revision = applied + 1;
+                    }
+                }
+                _ = phase.changed() => {
+                    if *phase.borrow() == Phase::Done {
+                        let _ = sink.close().await;
+                        break;
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    async fn open(&self) -> Option<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let mut request = self.url.as_str().into_client_request().ok()?;
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static("ot.json"),
+        );
+        let (mut connection, _) = tokio_tungstenite::connect_async(request).await.ok()?;
+
+        let hello = json!({"type": "hello", "version": 4});
+        connection
+            .send(Message::Text(hello.to_string()))
+            .await
+            .ok()?;
+        loop {
+            let Message::Text(text) = connection.next().await?.ok()? else {
+                continue;
+            };
+            if let Ok(ServerMessage::Hello {}) = serde_json::from_str(&text) {
+                break;
+            }
+        }
+
+        let subscribe = json!({"type": "subscribe", "document": self.document});
+        connection
+            .send(Message::Text(subscribe.to_string()))
+            .await
+            .ok()?;
+        Some(connection)
+    }
+
+    /// Records the message in the stats, returns the revision of the applied operation
+    fn handle(
+        &self,
+        message: Message,
+        in_flight: &mut VecDeque<Duration>,
+        stats: &mut Stats,
+    ) -> Option<usize> {
+        let Message::Text(text) = message else {
+            return None;
+        };
+        let Ok(message) = serde_json::from_str::<ServerMessage>(&text) else {
+            *stats
+                .errors
+                .entry(format!("unexpected message {text}"))
+                .or_default() += 1;
+            return None;
+        };
+        let now = self.epoch.elapsed();
+        match message {
+            ServerMessage::Ack { revision } => {
+                if let Some(sent_at) = in_flight.pop_front() {
+                    stats.acknowledged += 1;
+                    stats.ack_latencies.push((now - sent_at).as_micros() as u64);
+                }
+                Some(revision)
+            }
+            ServerMessage::Operation { revision, content } => {
+                let sent_at = content
+                    .as_deref()
+                    .and_then(|content| content.strip_suffix(';'))
+                    .and_then(|micros| micros.parse().ok())
+                    .map(Duration::from_micros);
+                if let Some(sent_at) = sent_at {
+                    stats
+                        .broadcast_latencies
+                        .push(now.saturating_sub(sent_at).as_micros() as u64);
+                }
+                Some(revision)
+            }
+            ServerMessage::Error { error } => {
+                // only operations are rejected
+                in_flight.pop_front();
+                *stats.errors.entry(error).or_default() += 1;
+                None
+            }
+            ServerMessage::Hello {} | ServerMessage::Other => None,
+        }
+    }
+}
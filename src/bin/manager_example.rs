@@ -1,16 +1,16 @@
 use std::time::Duration;
 
-use rust_live_server::{
-    collaboration::*,
-    ot::operations::{InsertOperation, Operation},
-};
+use rust_live_server::{collaboration::*, ot::operations::InsertOperation};
 use tokio::task::JoinHandle;
 
 fn connect_user(
     m: &mut manager::Manager,
     name: String,
-) -> (tokio::sync::mpsc::Sender<Operation>, JoinHandle<()>) {
-    let (sender, rec) = m.connect("User ".to_string() + &name, "doc1".to_string());
+) -> (
+    tokio::sync::mpsc::Sender<sessions::Submission>,
+    JoinHandle<()>,
+) {
+    let (sender, rec, _role) = m.connect("User ".to_string() + &name, "doc1".to_string());
 
     (
         sender,
@@ -26,7 +26,8 @@ fn connect_user(
 async fn start_test(m: &mut manager::Manager, cur_iteration: usize) {
     // m.connect(subscriber_name, document_id)
     let users = 5;
-    let (sender, sender_task) = connect_user(m, (users * cur_iteration).to_string());
+    let sender_name = (users * cur_iteration).to_string();
+    let (sender, sender_task) = connect_user(m, sender_name.clone());
     let mut tasks = vec![];
     for i in 1 + cur_iteration * users..=users * (cur_iteration + 1) {
         let (_, task) = connect_user(m, i.to_string());
@@ -36,11 +37,10 @@ async fn start_test(m: &mut manager::Manager, cur_iteration: usize) {
     println!("Send operation...");
     for i in 1..5 {
         sender
-            .send(Box::new(InsertOperation::new(
-                0,
-                1,
-                format!("text {i}").to_string(),
-            )))
+            .send(sessions::Submission::new(
+                "User ".to_string() + &sender_name,
+                Box::new(InsertOperation::new(0, 1, format!("text {i}").to_string())),
+            ))
             .await
             .unwrap();
     }
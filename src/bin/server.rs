@@ -1,13 +1,60 @@
-use rust_live_server::api::get_server_future;
+use std::{net::TcpListener, time::Duration};
+
+use rust_live_server::api::start_on;
+use tracing_subscriber::EnvFilter;
+
+/// How long the graceful shutdown may take
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Log filter used when `RUST_LOG` isn't set
+#[cfg(debug_assertions)]
+const DEFAULT_LOG_FILTER: &str = "debug";
+#[cfg(not(debug_assertions))]
+const DEFAULT_LOG_FILTER: &str = "info";
 
 #[tokio::main]
 async fn main() {
-    println!("server_start");
+    init_logging();
+    let listener = TcpListener::bind(("127.0.0.1", 8080)).unwrap();
+    tracing::info!(address = %listener.local_addr().unwrap(), "server started");
+    let (server, shutdown) = start_on(listener);
+    let mut server = tokio::spawn(server);
+
+    tokio::select! {
+        result = &mut server => {
+            result.unwrap().unwrap();
+            return;
+        }
+        _ = shutdown_signal() => {}
+    }
+    tracing::info!("server shutting down");
+    shutdown.shutdown(SHUTDOWN_DEADLINE).await;
+    server.await.unwrap().unwrap();
+}
+
+/// Logs human-readable lines in debug builds and JSON lines in release builds,
+/// the records of the `log` crate (actix) are logged too
+fn init_logging() {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
     #[cfg(debug_assertions)]
+    subscriber.init();
+    #[cfg(not(debug_assertions))]
+    subscriber.json().with_span_list(true).init();
+}
+
+/// Waits for SIGTERM or Ctrl+C
+async fn shutdown_signal() {
+    #[cfg(unix)]
     {
-        println!("Debug on");
-        std::env::set_var("RUST_LOG", "debug");
-        env_logger::init();
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
     }
-    get_server_future().await.unwrap();
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.unwrap();
 }
@@ -0,0 +1,648 @@
+//! Client of the `/ws` endpoint, enabled by the `client` feature.
+//!
+//! [`Client`] keeps a local copy of one document in a [`ClientDocument`]: edits are applied
+//! immediately and sent to the server one at a time, the operations of other clients are
+//! transformed relative to the edits which the server hasn't acknowledged yet.
+//! The connection is restored automatically, the edits made offline are sent after it.
+//! The sent operations carry ids, so after a reconnection the client recognizes its operation
+//! among the missed ones.
+
+use core::fmt;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::TcpStream,
+    sync::{broadcast, mpsc, oneshot, watch},
+};
+use tokio_tungstenite::{
+    tungstenite::{client::IntoClientRequest, http::HeaderValue, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    api::{
+        contracts::{Capability, OperationJSONContract, OperationJSONFreeCopy},
+        encoding::Encoding,
+    },
+    collaboration::access::Role,
+    ot::{
+        client::{ClientDocument, State},
+        operations::{ArcOperation, DeleteOperation, InsertOperation, Operation, ReplaceOperation},
+    },
+};
+
+/// Protocol version spoken by the client
+const PROTOCOL_VERSION: u32 = 4;
+
+/// How long a connection attempt may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Connection = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Address of the `/ws` endpoint, e.g. `ws://127.0.0.1:8080/ws`
+    pub url: String,
+    pub client: String,
+    pub document: String,
+    /// Pause before the next connection attempt
+    pub reconnect_delay: Duration,
+}
+
+impl Config {
+    pub fn new(url: String, client: String, document: String) -> Self {
+        Config {
+            url,
+            client,
+            document,
+            reconnect_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// What happened to the document or the connection
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// The client caught up with the server
+    Connected,
+    Disconnected,
+    /// Operation of another client as it was applied to the local text
+    Remote(ArcOperation),
+    Role(Role),
+    /// Cursor of another client, `None` when the client has left the document
+    Presence(String, Option<usize>),
+    /// The server rejected a message of the client
+    Error(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientError {
+    /// The operation doesn't fit the local text
+    InvalidOperation,
+    /// The client can't edit the document
+    ReadOnly,
+    /// The client is stopped
+    Closed,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::InvalidOperation => write!(f, "operation doesn't fit the document"),
+            ClientError::ReadOnly => write!(f, "viewers can't edit the document"),
+            ClientError::Closed => write!(f, "client is closed"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// Request of the handle to the background task
+enum Command {
+    /// Local edit and the channel for its result
+    Edit(Operation, oneshot::Sender<Result<(), ClientError>>),
+    Cursor(usize),
+}
+
+/// Handle of the client, the connection is served by a background task
+/// which closes it when the handle is dropped.
+pub struct Client {
+    commands: mpsc::Sender<Command>,
+    text: watch::Receiver<String>,
+    synchronized: watch::Receiver<bool>,
+    events: broadcast::Sender<ClientEvent>,
+    stop: CancellationToken,
+}
+
+impl Client {
+    /// Starts the client, it connects to the server in the background
+    pub fn connect(config: Config) -> Self {
+        let (commands, commands_receiver) = mpsc::channel(64);
+        let (text_sender, text) = watch::channel(String::new());
+        let (synchronized_sender, synchronized) = watch::channel(true);
+        let (events, _) = broadcast::channel(64);
+        let stop = CancellationToken::new();
+
+        let worker = Worker {
+            instance: instance_id(&config.client),
+            sent: 0,
+            sent_id: None,
+            config,
+            document: ClientDocument::new(String::new(), 0),
+            role: Role::Editor,
+            cursor: None,
+            text: text_sender,
+            synchronized: synchronized_sender,
+            events: events.clone(),
+            commands: commands_receiver,
+        };
+        tokio::spawn(worker.run(stop.clone()));
+        Client {
+            commands,
+            text,
+            synchronized,
+            events,
+            stop,
+        }
+    }
+
+    /// Applies the operation to the local text and sends it to the server.
+    ///
+    /// The position of the operation is in the current local text.
+    pub async fn edit(&self, operation: Operation) -> Result<(), ClientError> {
+        let (result_sender, result) = oneshot::channel();
+        self.commands
+            .send(Command::Edit(operation, result_sender))
+            .await
+            .map_err(|_| ClientError::Closed)?;
+        result.await.map_err(|_| ClientError::Closed)?
+    }
+
+    pub async fn insert(&self, position: usize, text: String) -> Result<(), ClientError> {
+        self.edit(Box::new(InsertOperation::new(position, 0, text)))
+            .await
+    }
+
+    pub async fn delete(&self, position: usize, len: usize) -> Result<(), ClientError> {
+        self.edit(Box::new(DeleteOperation::new(position, 0, len)))
+            .await
+    }
+
+    pub async fn replace(
+        &self,
+        position: usize,
+        len: usize,
+        text: String,
+    ) -> Result<(), ClientError> {
+        self.edit(Box::new(ReplaceOperation::new(position, 0, len, text)))
+            .await
+    }
+
+    /// Shows the cursor to the other clients, it is sent again after reconnecting
+    pub async fn set_cursor(&self, cursor: usize) -> Result<(), ClientError> {
+        self.commands
+            .send(Command::Cursor(cursor))
+            .await
+            .map_err(|_| ClientError::Closed)
+    }
+
+    /// Current local text
+    pub fn text(&self) -> String {
+        self.text.borrow().clone()
+    }
+
+    /// Returns a receiver which is notified on every change of the local text
+    pub fn watch_text(&self) -> watch::Receiver<String> {
+        self.text.clone()
+    }
+
+    /// Waits until the server has acknowledged all local edits
+    pub async fn synchronized(&self) {
+        let mut synchronized = self.synchronized.clone();
+        let _ = synchronized.wait_for(|synchronized| *synchronized).await;
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+/// Messages of the server in the fourth protocol version
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage {
+    Hello {},
+    Operation(Box<OperationJSONContract>),
+    Ack {
+        revision: usize,
+    },
+    Subscribed {},
+    Error {
+        error: String,
+        /// Id of the operation which caused the error
+        id: Option<String>,
+    },
+    Role {
+        role: Role,
+    },
+    Presence {
+        client: String,
+        cursor: Option<usize>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage<'a> {
+    Hello {
+        version: u32,
+        capabilities: [Capability; 1],
+    },
+    Subscribe {
+        document: &'a str,
+        since: usize,
+    },
+    Operation(OperationJSONFreeCopy<'a>),
+    Presence {
+        document: &'a str,
+        cursor: usize,
+    },
+}
+
+/// What has to be sent to the server
+enum Outgoing {
+    Operation(Operation),
+    Cursor(usize),
+}
+
+/// Why the connection was left
+enum Exit {
+    Disconnected,
+    /// The handle of the client is dropped
+    Stopped,
+}
+
+struct Worker {
+    config: Config,
+    /// Prefix of the ids of the sent operations
+    instance: String,
+    /// Number of the sent operations
+    sent: usize,
+    /// Id of the operation waiting for its acknowledgement
+    sent_id: Option<String>,
+    document: ClientDocument,
+    role: Role,
+    cursor: Option<usize>,
+    text: watch::Sender<String>,
+    synchronized: watch::Sender<bool>,
+    events: broadcast::Sender<ClientEvent>,
+    commands: mpsc::Receiver<Command>,
+}
+
+impl Worker {
+    async fn run(mut self, stop: CancellationToken) {
+        loop {
+            let connection = tokio::select! {
+                connection = self.open() => connection,
+                _ = stop.cancelled() => return,
+            };
+            if let Some(connection) = connection {
+                if let Exit::Stopped = self.serve(connection).await {
+                    return;
+                }
+                let _ = self.events.send(ClientEvent::Disconnected);
+            }
+
+            // the edits are applied locally until the next attempt
+            let pause = tokio::time::sleep(self.config.reconnect_delay);
+            tokio::pin!(pause);
+            loop {
+                tokio::select! {
+                    _ = &mut pause => break,
+                    command = self.commands.recv() => match command {
+                        Some(command) => {
+                            self.command(command);
+                        }
+                        None => return,
+                    },
+                }
+            }
+        }
+    }
+
+    /// Connects to the server and subscribes to the document
+    async fn open(&mut self) -> Option<Connection> {
+        let url = format!(
+            "{}?client={}",
+            self.config.url,
+            encode_query(&self.config.client)
+        );
+        let mut request = url.into_client_request().ok()?;
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(Encoding::JSON_PROTOCOL),
+        );
+        let connect = tokio_tungstenite::connect_async(request);
+        let (mut connection, _) = tokio::time::timeout(CONNECT_TIMEOUT, connect)
+            .await
+            .ok()?
+            .ok()?;
+
+        let hello = ClientMessage::Hello {
+            version: PROTOCOL_VERSION,
+            capabilities: [Capability::Presence],
+        };
+        send(&mut connection, &hello).await?;
+        loop {
+            match receive(&mut connection).await? {
+                Some(ServerMessage::Hello {}) => break,
+                _ => continue,
+            }
+        }
+
+        let subscribe = ClientMessage::Subscribe {
+            document: &self.config.document,
+            since: self.document.revision(),
+        };
+        send(&mut connection, &subscribe).await?;
+        Some(connection)
+    }
+
+    async fn serve(&mut self, mut connection: Connection) -> Exit {
+        // nothing is sent until the missed operations are received,
+        // the server may already have the sent operation
+        let mut subscribed = false;
+
+        loop {
+            let outgoing = tokio::select! {
+                message = receive(&mut connection) => match message {
+                    Some(Some(message)) => match self.handle(message, &mut subscribed) {
+                        Ok(outgoing) => outgoing,
+                        Err(()) => return Exit::Disconnected,
+                    },
+                    Some(None) => continue,
+                    None => return Exit::Disconnected,
+                },
+                command = self.commands.recv() => match command {
+                    Some(command) if subscribed => self.command(command),
+                    Some(command) => {
+                        self.command(command);
+                        vec![]
+                    }
+                    None => {
+                        let _ = connection.close(None).await;
+                        return Exit::Stopped;
+                    }
+                },
+            };
+
+            for outgoing in outgoing {
+                let document = &self.config.document;
+                let sent = match outgoing {
+                    Outgoing::Operation(operation) => {
+                        let id = format!("{}-{}", self.instance, self.sent);
+                        self.sent += 1;
+                        let message = OperationJSONFreeCopy::from_operation(&operation)
+                            .with_document(Some(document))
+                            .with_id(Some(&id));
+                        let sent = send(&mut connection, &ClientMessage::Operation(message)).await;
+                        self.sent_id = Some(id);
+                        sent
+                    }
+                    Outgoing::Cursor(cursor) => {
+                        let presence = ClientMessage::Presence { document, cursor };
+                        send(&mut connection, &presence).await
+                    }
+                };
+                if sent.is_none() {
+                    return Exit::Disconnected;
+                }
+            }
+        }
+    }
+
+    /// Handles the message of the server, returns what has to be sent.
+    ///
+    /// Returns `Err` if the connection has to be restored.
+    fn handle(
+        &mut self,
+        message: ServerMessage,
+        subscribed: &mut bool,
+    ) -> Result<Vec<Outgoing>, ()> {
+        match message {
+            ServerMessage::Hello {} => Ok(vec![]),
+            ServerMessage::Operation(mut operation) => {
+                // the own operation which was sent before the reconnection
+                let own = operation
+                    .id
+                    .take()
+                    .is_some_and(|id| self.sent_id == Some(id));
+                let operation = operation.into_operation().ok_or(())?;
+                if operation.revision() != self.document.revision() {
+                    return Err(());
+                }
+                if own && self.awaiting() {
+                    return Ok(self.acknowledge(*subscribed));
+                }
+                let applied = self.document.receive(operation.as_ref());
+                self.publish();
+                let _ = self.events.send(ClientEvent::Remote(Arc::new(applied)));
+                Ok(vec![])
+            }
+            ServerMessage::Ack { revision } => {
+                if revision != self.document.revision() || !self.awaiting() {
+                    return Err(());
+                }
+                Ok(self.acknowledge(*subscribed))
+            }
+            ServerMessage::Subscribed {} => {
+                *subscribed = true;
+                let _ = self.events.send(ClientEvent::Connected);
+                let pending = self.document.pending().map(Outgoing::Operation);
+                let cursor = self.cursor.map(Outgoing::Cursor);
+                Ok(pending.into_iter().chain(cursor).collect())
+            }
+            ServerMessage::Role { role } => {
+                self.role = role;
+                let _ = self.events.send(ClientEvent::Role(role));
+                Ok(vec![])
+            }
+            ServerMessage::Presence { client, cursor } => {
+                let _ = self.events.send(ClientEvent::Presence(client, cursor));
+                Ok(vec![])
+            }
+            ServerMessage::Error { error, id } => {
+                let _ = self.events.send(ClientEvent::Error(error));
+                // other errors, e.g. about a cursor, don't concern the local edits
+                let rejected = id.is_some() && id == self.sent_id;
+                if rejected && self.awaiting() {
+                    // the sent operation is rejected, the local edits can't be kept
+                    self.document = ClientDocument::new(String::new(), 0);
+                    self.sent_id = None;
+                    self.publish();
+                    return Err(());
+                }
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Handles the acknowledgement of the sent operation, returns what has to be sent
+    fn acknowledge(&mut self, subscribed: bool) -> Vec<Outgoing> {
+        let next = self.document.acknowledge();
+        self.sent_id = None;
+        self.publish();
+        // until the client is subscribed the pending operation waits for `subscribed`
+        if subscribed {
+            next.map(Outgoing::Operation).into_iter().collect()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Executes the command of the handle, returns what has to be sent
+    fn command(&mut self, command: Command) -> Vec<Outgoing> {
+        match command {
+            Command::Edit(operation, result) => match self.edit(operation) {
+                Ok(outgoing) => {
+                    let _ = result.send(Ok(()));
+                    outgoing.map(Outgoing::Operation).into_iter().collect()
+                }
+                Err(err) => {
+                    let _ = result.send(Err(err));
+                    vec![]
+                }
+            },
+            Command::Cursor(cursor) => {
+                self.cursor = Some(cursor);
+                vec![Outgoing::Cursor(cursor)]
+            }
+        }
+    }
+
+    /// Applies the local edit, returns the operation which has to be sent
+    fn edit(&mut self, operation: Operation) -> Result<Option<Operation>, ClientError> {
+        if !self.role.can_edit() {
+            return Err(ClientError::ReadOnly);
+        }
+        if !operation.can_apply(self.document.text()) {
+            return Err(ClientError::InvalidOperation);
+        }
+        let outgoing = self.document.edit(operation);
+        self.publish();
+        Ok(outgoing)
+    }
+
+    /// Shares the local text and the state of the document with the handle
+    fn publish(&self) {
+        self.text.send_replace(self.document.text().to_string());
+        self.synchronized.send_replace(!self.awaiting());
+    }
+
+    fn awaiting(&self) -> bool {
+        !matches!(self.document.state(), State::Synchronized)
+    }
+}
+
+/// Returns `None` if the connection is broken
+async fn send(connection: &mut Connection, message: &ClientMessage<'_>) -> Option<()> {
+    let message = serde_json::to_string(message).unwrap();
+    connection.send(Message::Text(message)).await.ok()
+}
+
+/// Returns `None` if the connection is closed and `Some(None)` for frames without messages
+async fn receive(connection: &mut Connection) -> Option<Option<ServerMessage>> {
+    match connection.next().await? {
+        Ok(Message::Text(text)) => Some(serde_json::from_str(&text).ok()),
+        Ok(Message::Close(_)) | Err(_) => None,
+        Ok(_) => Some(None),
+    }
+}
+
+/// Prefix of the operation ids which no other instance of the client has
+fn instance_id(client: &str) -> String {
+    static NEXT_INSTANCE: AtomicU64 = AtomicU64::new(0);
+    let time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let instance = NEXT_INSTANCE.fetch_add(1, Ordering::Relaxed);
+    format!("{client}-{:x}-{instance}", time.as_nanos())
+}
+
+/// Percent-encodes the value of a query parameter
+fn encode_query(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::{broadcast, mpsc, oneshot, watch};
+
+    use super::{encode_query, ClientDocument, Command, Config, Outgoing, ServerMessage, Worker};
+    use crate::{collaboration::access::Role, ot::operations::InsertOperation};
+
+    fn worker() -> Worker {
+        let config = Config::new(String::new(), String::from("alice"), String::from("doc"));
+        Worker {
+            instance: String::from("alice-1"),
+            sent: 0,
+            sent_id: None,
+            config,
+            document: ClientDocument::new(String::new(), 0),
+            role: Role::Editor,
+            cursor: None,
+            text: watch::channel(String::new()).0,
+            synchronized: watch::channel(true).0,
+            events: broadcast::channel(16).0,
+            commands: mpsc::channel(1).1,
+        }
+    }
+
+    fn error(error: &str, id: Option<&str>) -> ServerMessage {
+        ServerMessage::Error {
+            error: error.to_string(),
+            id: id.map(String::from),
+        }
+    }
+
+    #[test]
+    fn unrelated_errors_keep_edits() {
+        let mut worker = worker();
+        let operation = Box::new(InsertOperation::new(0, 0, String::from("hello")));
+        let outgoing = worker.command(Command::Edit(operation, oneshot::channel().0));
+        assert!(matches!(outgoing[..], [Outgoing::Operation(_)]));
+        // as the connection does when it sends the operation
+        worker.sent_id = Some(String::from("alice-1-0"));
+        assert!(worker.awaiting());
+
+        let mut subscribed = true;
+        let message = error("not subscribed to the document", None);
+        assert!(worker.handle(message, &mut subscribed).unwrap().is_empty());
+        let message = error("malformed message", Some("alice-1-5"));
+        assert!(worker.handle(message, &mut subscribed).unwrap().is_empty());
+        assert_eq!(worker.document.text(), "hello");
+        assert!(worker.awaiting());
+
+        let message = error("operation rejected by the document", Some("alice-1-0"));
+        assert!(worker.handle(message, &mut subscribed).is_err());
+        assert_eq!(worker.document.text(), "");
+        assert!(!worker.awaiting());
+    }
+
+    #[test]
+    fn query_encoding() {
+        let cases = [
+            ("alice", "alice"),
+            ("User 1", "User%201"),
+            ("a&b=c", "a%26b%3Dc"),
+            ("é", "%C3%A9"),
+        ];
+
+        for (value, ans) in cases {
+            assert_eq!(encode_query(value), ans, "value: {value:?}");
+        }
+    }
+}
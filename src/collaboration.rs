@@ -1,2 +1,5 @@
+pub mod access;
+pub mod audit;
+pub mod checkpoints;
 pub mod manager;
 pub mod sessions;
@@ -0,0 +1,199 @@
+use core::fmt;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// Role of a client in a particular document
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Receives operations, but can't submit them
+    Viewer,
+    /// Receives and submits operations
+    Editor,
+    /// Editor who can also change the roles of other clients
+    Owner,
+}
+
+impl Role {
+    pub fn can_view(&self) -> bool {
+        matches!(self, Role::Viewer | Role::Editor | Role::Owner)
+    }
+
+    pub fn can_edit(&self) -> bool {
+        matches!(self, Role::Editor | Role::Owner)
+    }
+
+    pub fn can_manage(&self) -> bool {
+        matches!(self, Role::Owner)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AccessError {
+    DocumentNotFound,
+    /// The document can't be created, it is already open
+    DocumentExists,
+    /// The client who tries to change roles is not an owner of the document
+    NotOwner,
+    /// The change would leave the document without owners
+    LastOwner,
+    /// The client can't read the document
+    NoAccess,
+    /// The client can't submit operations
+    ReadOnly,
+    /// The operation is based on an unknown revision or doesn't fit the text
+    InvalidOperation,
+    /// The revision is not in the history of the document yet
+    RevisionNotFound,
+    CheckpointNotFound,
+    /// Another checkpoint of the document has the name
+    CheckpointExists,
+    /// The document wasn't forked from another one
+    NotBranch,
+    /// The branch was merged, its later operations can't be merged again
+    BranchMerged,
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessError::DocumentNotFound => write!(f, "document not found"),
+            AccessError::DocumentExists => write!(f, "document already exists"),
+            AccessError::NotOwner => write!(f, "only owners can change roles"),
+            AccessError::LastOwner => write!(f, "document must have at least one owner"),
+            AccessError::NoAccess => write!(f, "client can't read the document"),
+            AccessError::ReadOnly => write!(f, "viewers can't submit operations"),
+            AccessError::InvalidOperation => write!(f, "operation doesn't fit the document"),
+            AccessError::RevisionNotFound => write!(f, "revision is in the future"),
+            AccessError::CheckpointNotFound => write!(f, "checkpoint not found"),
+            AccessError::CheckpointExists => write!(f, "checkpoint already exists"),
+            AccessError::NotBranch => write!(f, "document is not a branch"),
+            AccessError::BranchMerged => write!(f, "branch is already merged"),
+        }
+    }
+}
+
+impl std::error::Error for AccessError {}
+
+/// Roles of the clients of one document.
+///
+/// Every role is kept in a `watch` channel, so connected clients see the change
+/// of their role immediately.
+#[derive(Debug)]
+pub struct DocumentAccess {
+    default_role: Role,
+    roles: HashMap<String, watch::Sender<Role>>,
+}
+
+impl DocumentAccess {
+    /// Creates access rules where `owner` is the only owner
+    /// and everybody else is an editor
+    pub fn new(owner: String) -> Self {
+        let mut access = DocumentAccess {
+            default_role: Role::Editor,
+            roles: HashMap::new(),
+        };
+        access.roles.insert(owner, watch::channel(Role::Owner).0);
+        access
+    }
+
+    pub fn role(&self, client: &str) -> Role {
+        match self.roles.get(client) {
+            Some(role) => *role.borrow(),
+            None => self.default_role,
+        }
+    }
+
+    /// Returns a receiver which always holds the actual role of `client`
+    pub fn watch(&mut self, client: String) -> watch::Receiver<Role> {
+        let default_role = self.default_role;
+        self.roles
+            .entry(client)
+            .or_insert_with(|| watch::channel(default_role).0)
+            .subscribe()
+    }
+
+    /// Sets the role of `client` on behalf of `actor`
+    pub fn set_role(&mut self, actor: &str, client: String, role: Role) -> Result<(), AccessError> {
+        if !self.role(actor).can_manage() {
+            return Err(AccessError::NotOwner);
+        }
+        if self.role(&client) == Role::Owner && role != Role::Owner && self.owners() == 1 {
+            return Err(AccessError::LastOwner);
+        }
+
+        match self.roles.get(&client) {
+            Some(sender) => {
+                sender.send_replace(role);
+            }
+            None => {
+                self.roles.insert(client, watch::channel(role).0);
+            }
+        }
+        Ok(())
+    }
+
+    fn owners(&self) -> usize {
+        self.roles
+            .values()
+            .filter(|role| *role.borrow() == Role::Owner)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessError, DocumentAccess, Role};
+
+    #[test]
+    fn default_roles() {
+        let access = DocumentAccess::new(String::from("alice"));
+        assert_eq!(access.role("alice"), Role::Owner);
+        assert_eq!(access.role("bob"), Role::Editor);
+    }
+
+    #[test]
+    fn only_owner_can_change_roles() {
+        let mut access = DocumentAccess::new(String::from("alice"));
+        assert_eq!(
+            access.set_role("bob", String::from("carol"), Role::Viewer),
+            Err(AccessError::NotOwner)
+        );
+        assert_eq!(
+            access.set_role("alice", String::from("bob"), Role::Viewer),
+            Ok(())
+        );
+        assert_eq!(access.role("bob"), Role::Viewer);
+    }
+
+    #[test]
+    fn last_owner_is_kept() {
+        let mut access = DocumentAccess::new(String::from("alice"));
+        assert_eq!(
+            access.set_role("alice", String::from("alice"), Role::Editor),
+            Err(AccessError::LastOwner)
+        );
+        access
+            .set_role("alice", String::from("bob"), Role::Owner)
+            .unwrap();
+        assert_eq!(
+            access.set_role("bob", String::from("alice"), Role::Viewer),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn connected_client_sees_downgrade() {
+        let mut access = DocumentAccess::new(String::from("alice"));
+        let bob = access.watch(String::from("bob"));
+        assert!(bob.borrow().can_edit());
+
+        access
+            .set_role("alice", String::from("bob"), Role::Viewer)
+            .unwrap();
+        assert!(bob.has_changed().unwrap());
+        assert!(!bob.borrow().can_edit());
+    }
+}
@@ -0,0 +1,339 @@
+//! Who applied each operation of a document and when.
+//!
+//! The entries are kept by revision next to the history of the document,
+//! the audit records and the blame are computed from both on request.
+
+use std::{ops::Range, time::SystemTime};
+
+use crate::ot::{
+    operations::{ArcOperation, DeleteOperation, InsertOperation, MoveOperation, OperationTrait},
+    rich_text::{move_runs, split_runs},
+};
+
+/// Stamp of an applied operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub author: String,
+    /// Time when the server applied the operation
+    pub timestamp: SystemTime,
+    /// Id which the client gave to the operation
+    pub operation_id: Option<String>,
+    /// `/ws` connection which submitted the operation, it isn't exported
+    pub connection: Option<u64>,
+}
+
+/// Filter of the audit log, the fields which are `None` match every entry
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    pub author: Option<String>,
+    /// Inclusive start of the time range
+    pub from: Option<SystemTime>,
+    /// Exclusive end of the time range
+    pub to: Option<SystemTime>,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.author
+            .as_ref()
+            .is_none_or(|author| *author == entry.author)
+            && self.from.is_none_or(|from| entry.timestamp >= from)
+            && self.to.is_none_or(|to| entry.timestamp < to)
+    }
+}
+
+/// Applied operation with its stamp
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    pub revision: usize,
+    pub entry: AuditEntry,
+    pub operation: ArcOperation,
+    /// Text removed by a delete operation
+    pub deleted: Option<String>,
+}
+
+/// Range of the current text and the operation which inserted it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameRange {
+    /// Byte offsets of the range, the end is exclusive
+    pub start: usize,
+    pub end: usize,
+    pub revision: usize,
+    pub entry: AuditEntry,
+}
+
+/// Stamps of the operations of a document by revision
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    entries: Vec<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        AuditLog::default()
+    }
+
+    /// Stamps the operation with the next revision
+    pub fn push(&mut self, entry: AuditEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn get(&self, revision: usize) -> Option<&AuditEntry> {
+        self.entries.get(revision)
+    }
+
+    /// Log of a branch which shares the operations before `revision`,
+    /// the connections of the parent don't submit to the branch
+    pub fn fork(&self, revision: usize) -> AuditLog {
+        let entries = self.entries[..revision].iter().map(|entry| AuditEntry {
+            connection: None,
+            ..entry.clone()
+        });
+        AuditLog {
+            entries: entries.collect(),
+        }
+    }
+
+    /// Returns the operations of `history` whose stamps match `query`, oldest first.
+    ///
+    /// `history` is the whole history of the document, it is replayed
+    /// to find the text removed by the delete operations.
+    pub fn query(&self, history: &[ArcOperation], query: &AuditQuery) -> Vec<AuditRecord> {
+        self.records(history, |_, entry| query.matches(entry))
+    }
+
+    /// Returns the operations of `history` with the revisions in the range, see [`AuditLog::query`].
+    ///
+    /// The range must end inside the history.
+    pub fn range(&self, history: &[ArcOperation], revisions: Range<usize>) -> Vec<AuditRecord> {
+        self.records(&history[..revisions.end], |revision, _| {
+            revisions.contains(&revision)
+        })
+    }
+
+    fn records(
+        &self,
+        history: &[ArcOperation],
+        filter: impl Fn(usize, &AuditEntry) -> bool,
+    ) -> Vec<AuditRecord> {
+        let mut text = String::new();
+        let mut records = vec![];
+        for (revision, (operation, entry)) in history.iter().zip(&self.entries).enumerate() {
+            if filter(revision, entry) {
+                let deleted = deleted_text(Box::as_ref(operation), &text);
+                records.push(AuditRecord {
+                    revision,
+                    entry: entry.clone(),
+                    operation: ArcOperation::clone(operation),
+                    deleted,
+                });
+            }
+            operation.apply(&mut text);
+        }
+        records
+    }
+
+    /// Splits the text produced by `history` into ranges inserted by different operations
+    pub fn blame(&self, history: &[ArcOperation]) -> Vec<BlameRange> {
+        // lengths of the pieces of the text and the revisions which inserted them
+        let mut pieces: Vec<(usize, usize)> = vec![];
+        for (revision, operation) in history.iter().enumerate() {
+            blame_operation(&mut pieces, Box::as_ref(operation), revision);
+        }
+
+        let mut ranges: Vec<BlameRange> = vec![];
+        let mut start = 0;
+        for (len, revision) in pieces {
+            match ranges.last_mut() {
+                Some(last) if last.revision == revision => last.end += len,
+                _ => ranges.push(BlameRange {
+                    start,
+                    end: start + len,
+                    revision,
+                    entry: self.entries[revision].clone(),
+                }),
+            }
+            start += len;
+        }
+        ranges
+    }
+}
+
+/// Text removed from `text` by the deletions of the operation, `None` if it deletes nothing
+fn deleted_text(operation: &dyn OperationTrait, text: &str) -> Option<String> {
+    if let Some(parts) = operation.parts() {
+        let mut text = String::from(text);
+        let mut deleted: Option<String> = None;
+        for part in parts {
+            if let Some(part_deleted) = deleted_text(part, &text) {
+                deleted
+                    .get_or_insert_with(String::new)
+                    .push_str(&part_deleted);
+            }
+            part.apply(&mut text);
+        }
+        return deleted;
+    }
+    operation.downcast::<DeleteOperation>().map(|delete| {
+        (delete.ranges().iter())
+            .map(|range| &text[range.clone()])
+            .collect()
+    })
+}
+
+/// Updates the pieces of the text and the revisions which inserted them
+fn blame_operation(
+    pieces: &mut Vec<(usize, usize)>,
+    operation: &dyn OperationTrait,
+    revision: usize,
+) {
+    if let Some(parts) = operation.parts() {
+        for part in parts {
+            blame_operation(pieces, part, revision);
+        }
+    } else if let Some(insert) = operation.downcast::<InsertOperation>() {
+        let idx = split_runs(pieces, insert.position());
+        pieces.insert(idx, (insert.text().len(), revision));
+    } else if let Some(delete) = operation.downcast::<DeleteOperation>() {
+        for range in delete.ranges().iter().rev() {
+            let start = split_runs(pieces, range.start);
+            let end = split_runs(pieces, range.end);
+            pieces.drain(start..end);
+        }
+    } else if let Some(operation) = operation.downcast::<MoveOperation>() {
+        move_runs(pieces, operation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{AuditEntry, AuditLog, AuditQuery};
+    use crate::ot::operations::{
+        ArcOperation, DeleteOperation, InsertOperation, Operation, ReplaceOperation,
+        TransactionOperation,
+    };
+
+    fn entry(author: &str, seconds: u64) -> AuditEntry {
+        AuditEntry {
+            author: author.to_string(),
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(seconds),
+            operation_id: None,
+            connection: None,
+        }
+    }
+
+    fn insert(position: usize, text: &str) -> ArcOperation {
+        let operation: Operation = Box::new(InsertOperation::new(position, 0, text.to_string()));
+        Arc::new(operation)
+    }
+
+    fn delete(position: usize, len: usize) -> ArcOperation {
+        let operation: Operation = Box::new(DeleteOperation::new(position, 0, len));
+        Arc::new(operation)
+    }
+
+    /// "hello world" by alice, then bob deletes "lo w" and inserts "p"
+    fn document() -> (AuditLog, Vec<ArcOperation>) {
+        let mut log = AuditLog::new();
+        log.push(entry("alice", 10));
+        log.push(entry("alice", 20));
+        log.push(entry("bob", 30));
+        log.push(entry("bob", 40));
+        let history = vec![
+            insert(0, "hello"),
+            insert(5, " world"),
+            delete(3, 4),
+            insert(3, "p"),
+        ];
+        (log, history)
+    }
+
+    #[test]
+    fn query_filters_by_author_and_time() {
+        let (log, history) = document();
+        let revisions = |query: &AuditQuery| -> Vec<usize> {
+            log.query(&history, query)
+                .iter()
+                .map(|record| record.revision)
+                .collect()
+        };
+
+        assert_eq!(revisions(&AuditQuery::default()), [0, 1, 2, 3]);
+        let by_bob = AuditQuery {
+            author: Some(String::from("bob")),
+            ..Default::default()
+        };
+        assert_eq!(revisions(&by_bob), [2, 3]);
+        let in_range = AuditQuery {
+            from: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(20)),
+            to: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(40)),
+            ..Default::default()
+        };
+        assert_eq!(revisions(&in_range), [1, 2]);
+    }
+
+    #[test]
+    fn range_selects_revisions() {
+        let (log, history) = document();
+        let records = log.range(&history, 1..3);
+        let revisions: Vec<_> = records.iter().map(|record| record.revision).collect();
+        assert_eq!(revisions, [1, 2]);
+        assert_eq!(records[1].deleted.as_deref(), Some("lo w"));
+        assert_eq!(records[1].entry.author, "bob");
+    }
+
+    #[test]
+    fn query_returns_deleted_text() {
+        let (log, history) = document();
+        let records = log.query(&history, &AuditQuery::default());
+        let deleted: Vec<_> = records
+            .iter()
+            .map(|record| record.deleted.as_deref())
+            .collect();
+        assert_eq!(deleted, [None, None, Some("lo w"), None]);
+    }
+
+    #[test]
+    fn blame_maps_ranges_to_insertions() {
+        let (log, history) = document();
+        let blame: Vec<_> = log
+            .blame(&history)
+            .iter()
+            .map(|range| (range.start, range.end, range.revision))
+            .collect();
+        // "hel" + "p" + "orld"
+        assert_eq!(blame, [(0, 3, 0), (3, 4, 3), (4, 8, 1)]);
+    }
+
+    #[test]
+    fn transactions_are_blamed_as_one_revision() {
+        let (mut log, mut history) = document();
+        log.push(entry("carol", 50));
+        let operation: Operation = Box::new(TransactionOperation::new(
+            0,
+            vec![
+                Box::new(ReplaceOperation::new(0, 0, 1, String::from("H"))),
+                Box::new(ReplaceOperation::new(7, 0, 1, String::from("D"))),
+            ],
+        ));
+        history.push(Arc::new(operation));
+
+        let records = log.query(&history, &AuditQuery::default());
+        assert_eq!(records[4].deleted.as_deref(), Some("hd"));
+        let blame: Vec<_> = log
+            .blame(&history)
+            .iter()
+            .map(|range| (range.start, range.end, range.revision))
+            .collect();
+        // "H" + "el" + "p" + "orl" + "D"
+        assert_eq!(
+            blame,
+            [(0, 1, 4), (1, 3, 0), (3, 4, 3), (4, 7, 1), (7, 8, 4)]
+        );
+    }
+}
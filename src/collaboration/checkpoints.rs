@@ -0,0 +1,88 @@
+//! Named revisions of a document, e.g. "v1 submitted".
+//!
+//! A checkpoint only remembers a revision, the content at it is replayed from the history
+//! of the document. Restoring a checkpoint submits a new operation which brings the content
+//! back, so the clients converge on it like on any other edit.
+
+use std::time::SystemTime;
+
+use super::access::AccessError;
+
+/// Revision of a document marked with a name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub name: String,
+    pub revision: usize,
+    /// Client who created the checkpoint
+    pub author: String,
+    pub timestamp: SystemTime,
+}
+
+/// Checkpoints of a document ordered by revision
+#[derive(Debug, Default)]
+pub struct Checkpoints {
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Checkpoints {
+    pub fn new() -> Self {
+        Checkpoints::default()
+    }
+
+    /// Adds the checkpoint after the ones with the same or an earlier revision
+    pub fn insert(&mut self, checkpoint: Checkpoint) -> Result<(), AccessError> {
+        if self.get(&checkpoint.name).is_some() {
+            return Err(AccessError::CheckpointExists);
+        }
+        let idx = self
+            .checkpoints
+            .partition_point(|other| other.revision <= checkpoint.revision);
+        self.checkpoints.insert(idx, checkpoint);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Checkpoint> {
+        self.checkpoints
+            .iter()
+            .find(|checkpoint| checkpoint.name == name)
+    }
+
+    pub fn list(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::{Checkpoint, Checkpoints};
+    use crate::collaboration::access::AccessError;
+
+    fn checkpoint(name: &str, revision: usize) -> Checkpoint {
+        Checkpoint {
+            name: name.to_string(),
+            revision,
+            author: String::from("alice"),
+            timestamp: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    #[test]
+    fn checkpoints_are_ordered_by_revision() {
+        let mut checkpoints = Checkpoints::new();
+        checkpoints.insert(checkpoint("draft", 5)).unwrap();
+        checkpoints.insert(checkpoint("outline", 2)).unwrap();
+        checkpoints.insert(checkpoint("review", 5)).unwrap();
+
+        let names: Vec<_> = (checkpoints.list().iter())
+            .map(|checkpoint| checkpoint.name.as_str())
+            .collect();
+        assert_eq!(names, ["outline", "draft", "review"]);
+        assert_eq!(
+            checkpoints.insert(checkpoint("draft", 7)),
+            Err(AccessError::CheckpointExists)
+        );
+        assert_eq!(checkpoints.get("draft").unwrap().revision, 5);
+    }
+}
@@ -1,15 +1,42 @@
-use super::sessions::Session;
-use crate::ot::operations::{ArcOperation, Operation};
+use super::{
+    access::{AccessError, Role},
+    audit::{AuditEntry, AuditQuery, AuditRecord, BlameRange},
+    checkpoints::Checkpoint,
+    sessions::{Merge, Presence, Rejection, Session, Submission},
+};
+use crate::ot::{
+    document::DocumentKind,
+    operations::{ArcOperation, Operation},
+};
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
+    time::Duration,
 };
-use tokio::sync::{broadcast::Receiver, mpsc::Sender};
+use tokio::sync::{broadcast::Receiver, mpsc::Sender, watch};
+
+/// State of an open document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentStats {
+    pub document: String,
+    pub subscribers: usize,
+    /// Length of the history
+    pub revision: usize,
+}
+
+/// How often [`Manager::drain`] checks the input queues
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
 
 pub struct Manager {
     sessions: Mutex<HashMap<String, Arc<Mutex<Session>>>>,
 }
 
+impl Default for Manager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Manager {
     pub fn new() -> Self {
         Manager {
@@ -17,25 +44,51 @@ impl Manager {
         }
     }
 
-    pub fn as_mut(&self) -> &mut Self {
-        unsafe { &mut *(self as *const Manager as *mut Manager) }
+    /// Creates an empty document of `kind`, `owner` becomes its owner.
+    ///
+    /// The documents which are not created beforehand are created as text on the first connection.
+    pub fn create(
+        &self,
+        document_id: String,
+        owner: String,
+        kind: DocumentKind,
+    ) -> Result<(), AccessError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.contains_key(&document_id) {
+            return Err(AccessError::DocumentExists);
+        }
+        tracing::info!(document = %document_id, %owner, ?kind, "session created");
+        let session = Session::new(document_id.clone(), owner, kind);
+        sessions.insert(document_id, Arc::new(Mutex::new(session)));
+        Ok(())
     }
 
+    /// Connects the client to the document, the client who opens a document first becomes its owner.
+    ///
+    /// Returns the input of the document, which takes operations with the names of their authors,
+    /// the stream of applied operations and the actual role of the client.
     pub fn connect(
-        &mut self,
-        _subscriber_name: String,
+        &self,
+        subscriber_name: String,
         document_id: String,
-    ) -> (Sender<Operation>, Receiver<ArcOperation>) {
+    ) -> (
+        Sender<Submission>,
+        Receiver<ArcOperation>,
+        watch::Receiver<Role>,
+    ) {
         let session = {
             Arc::clone(
                 self.sessions
                     .lock()
                     .unwrap()
-                    .entry(document_id)
+                    .entry(document_id.clone())
                     .or_insert_with(|| {
-                        let session = Arc::new(Mutex::new(Session::new()));
-
-                        session
+                        tracing::info!(document = %document_id, owner = %subscriber_name, "session created");
+                        Arc::new(Mutex::new(Session::new(
+                            document_id,
+                            subscriber_name.clone(),
+                            DocumentKind::Text,
+                        )))
                     }),
             )
         };
@@ -46,10 +99,11 @@ impl Manager {
             let mut session = session.lock().unwrap();
             let input = session.get_input();
             let output = session.subscribe();
+            let role = session.access_mut().watch(subscriber_name);
 
             need_listner_start = !session.listner_work();
 
-            (input, output)
+            (input, output, role)
         };
 
         if need_listner_start {
@@ -59,20 +113,452 @@ impl Manager {
         ans
     }
 
-    pub fn disconnect(&mut self, _subscriber_name: String, document_id: String) {
-        let session = {
-            self.sessions
+    pub fn disconnect(&self, _subscriber_name: String, document_id: String) {
+        if let Some(session) = self.get_session(&document_id) {
+            let _connections = session.lock().unwrap().unsubscribe();
+            // TODO? if connections == 0 then delete session from sessions
+        }
+    }
+    /// Returns the role of the client in the document, `None` if the document is not open
+    pub fn role(&self, document_id: &str, subscriber_name: &str) -> Option<Role> {
+        self.get_session(document_id)
+            .map(|session| session.lock().unwrap().access().role(subscriber_name))
+    }
+
+    /// Checks that the client can read an open document, e.g. its history
+    pub fn check_viewer(
+        &self,
+        document_id: &str,
+        subscriber_name: &str,
+    ) -> Result<(), AccessError> {
+        let role = self
+            .role(document_id, subscriber_name)
+            .ok_or(AccessError::DocumentNotFound)?;
+        if !role.can_view() {
+            return Err(AccessError::NoAccess);
+        }
+        Ok(())
+    }
+
+    /// Changes the role of `subscriber_name` on behalf of `actor`.
+    ///
+    /// If the client is connected, the new role takes effect immediately.
+    pub fn set_role(
+        &self,
+        document_id: &str,
+        actor: &str,
+        subscriber_name: String,
+        role: Role,
+    ) -> Result<(), AccessError> {
+        let session = self
+            .get_session(document_id)
+            .ok_or(AccessError::DocumentNotFound)?;
+        let mut session = session.lock().unwrap();
+        session.access_mut().set_role(actor, subscriber_name, role)
+    }
+
+    /// Applies the operation of the client to an open document without connecting the client.
+    ///
+    /// Returns the operation transformed to the current revision.
+    pub fn submit(
+        &self,
+        document_id: &str,
+        mut submission: Submission,
+    ) -> Result<ArcOperation, AccessError> {
+        let session = self
+            .get_session(document_id)
+            .ok_or(AccessError::DocumentNotFound)?;
+        let session = session.lock().unwrap();
+        if !session.access().role(&submission.author).can_edit() {
+            return Err(AccessError::ReadOnly);
+        }
+        submission.span = tracing::debug_span!(
+            parent: &submission.span,
+            "operation",
+            document = document_id,
+            client = %submission.author,
+            kind = submission.operation.kind(),
+            size = submission.operation.size(),
+            base_revision = submission.operation.revision(),
+        );
+        session
+            .apply(submission)
+            .ok_or(AccessError::InvalidOperation)
+    }
+
+    /// Returns the operations of an open document starting from revision `since`
+    /// and a receiver of the following ones, see [`Session::operations_since`]
+    pub fn operations_since(
+        &self,
+        document_id: &str,
+        since: usize,
+    ) -> Option<(Vec<ArcOperation>, Receiver<ArcOperation>)> {
+        self.get_session(document_id)
+            .map(|session| session.lock().unwrap().operations_since(since))
+    }
+
+    /// Returns the text of an open document
+    pub fn text(&self, document_id: &str) -> Option<String> {
+        self.get_session(document_id)
+            .map(|session| session.lock().unwrap().text())
+    }
+
+    /// Returns the text of an open document with its revision
+    pub fn content(&self, document_id: &str) -> Option<(String, usize)> {
+        self.get_session(document_id)
+            .map(|session| session.lock().unwrap().content())
+    }
+
+    /// Updates the cursor of the client in an open document, `None` means the client has left it
+    pub fn set_presence(&self, document_id: &str, subscriber_name: String, cursor: Option<usize>) {
+        if let Some(session) = self.get_session(document_id) {
+            session
                 .lock()
                 .unwrap()
-                .get(&document_id)
-                .and_then(|session| Some(Arc::clone(session)))
+                .set_presence(subscriber_name, cursor);
+        }
+    }
+
+    /// Returns the cursors in an open document and a receiver of their changes
+    pub fn presence(&self, document_id: &str) -> Option<(Vec<Presence>, Receiver<Presence>)> {
+        self.get_session(document_id)
+            .map(|session| session.lock().unwrap().presence())
+    }
+
+    /// Returns a receiver of the operations which an open document rejects
+    pub fn rejections(&self, document_id: &str) -> Option<Receiver<Rejection>> {
+        self.get_session(document_id)
+            .map(|session| session.lock().unwrap().rejections())
+    }
+
+    /// Returns the stamp of the operation with the revision, see [`Session::stamp`]
+    pub fn stamp(&self, document_id: &str, revision: usize) -> Option<AuditEntry> {
+        self.get_session(document_id)?
+            .lock()
+            .unwrap()
+            .stamp(revision)
+    }
+
+    /// Returns the operations of an open document whose stamps match `query`
+    pub fn audit(&self, document_id: &str, query: &AuditQuery) -> Option<Vec<AuditRecord>> {
+        self.get_session(document_id)
+            .map(|session| session.lock().unwrap().audit(query))
+    }
+
+    /// Returns the operations of an open document from revision `from` up to `to`
+    /// with their authors and times, up to the current revision if `to` is `None`
+    pub fn playback(
+        &self,
+        document_id: &str,
+        from: usize,
+        to: Option<usize>,
+    ) -> Result<Vec<AuditRecord>, AccessError> {
+        let session = self
+            .get_session(document_id)
+            .ok_or(AccessError::DocumentNotFound)?;
+        let session = session.lock().unwrap();
+        let to = to.unwrap_or_else(|| session.revision());
+        session.playback(from..to)
+    }
+
+    /// Maps the ranges of the text of an open document to the operations which inserted them
+    pub fn blame(&self, document_id: &str) -> Option<Vec<BlameRange>> {
+        self.get_session(document_id)
+            .map(|session| session.lock().unwrap().blame())
+    }
+
+    /// Names the revision of an open document on behalf of an editor,
+    /// the current revision if `revision` is `None`
+    pub fn create_checkpoint(
+        &self,
+        document_id: &str,
+        author: String,
+        name: String,
+        revision: Option<usize>,
+    ) -> Result<Checkpoint, AccessError> {
+        let session = self
+            .get_session(document_id)
+            .ok_or(AccessError::DocumentNotFound)?;
+        let mut session = session.lock().unwrap();
+        if !session.access().role(&author).can_edit() {
+            return Err(AccessError::ReadOnly);
+        }
+        session.create_checkpoint(name, author, revision)
+    }
+
+    /// Returns the checkpoints of an open document ordered by revision
+    pub fn checkpoints(&self, document_id: &str) -> Option<Vec<Checkpoint>> {
+        self.get_session(document_id)
+            .map(|session| session.lock().unwrap().checkpoints())
+    }
+
+    /// Returns the operations between the checkpoints of an open document,
+    /// see [`Session::diff_checkpoints`]
+    pub fn diff_checkpoints(
+        &self,
+        document_id: &str,
+        from: &str,
+        to: Option<&str>,
+    ) -> Result<Vec<Operation>, AccessError> {
+        self.get_session(document_id)
+            .ok_or(AccessError::DocumentNotFound)?
+            .lock()
+            .unwrap()
+            .diff_checkpoints(from, to)
+    }
+
+    /// Brings back the content of an open document at the checkpoint.
+    ///
+    /// The content is restored by a new operation of `author`, so the concurrent edits
+    /// are transformed against it instead of being lost. Returns the applied operation,
+    /// `None` if the content is the same.
+    pub fn restore(
+        &self,
+        document_id: &str,
+        author: String,
+        name: &str,
+    ) -> Result<Option<ArcOperation>, AccessError> {
+        let operation = self
+            .get_session(document_id)
+            .ok_or(AccessError::DocumentNotFound)?
+            .lock()
+            .unwrap()
+            .restore(name)?;
+        operation
+            .map(|operation| self.submit(document_id, Submission::new(author, operation)))
+            .transpose()
+    }
+
+    /// Returns the kind and the whole history of an open document with the stamps
+    pub fn export(&self, document_id: &str) -> Option<(DocumentKind, Vec<AuditRecord>)> {
+        self.get_session(document_id)
+            .map(|session| session.lock().unwrap().export())
+    }
+
+    /// Creates a document of `kind` with the history exported from another document,
+    /// `owner` becomes its owner. See [`Session::import`].
+    pub fn import(
+        &self,
+        document_id: String,
+        owner: String,
+        kind: DocumentKind,
+        history: Vec<(Operation, AuditEntry)>,
+    ) -> Result<(), AccessError> {
+        if self.get_session(&document_id).is_some() {
+            return Err(AccessError::DocumentExists);
+        }
+        let revision = history.len();
+        let session = Session::import(document_id.clone(), owner.clone(), kind, history)?;
+
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.contains_key(&document_id) {
+            return Err(AccessError::DocumentExists);
+        }
+        tracing::info!(document = %document_id, %owner, ?kind, revision, "document imported");
+        sessions.insert(document_id, Arc::new(Mutex::new(session)));
+        Ok(())
+    }
+
+    /// Forks an open document at `revision`, the current one if it is `None`,
+    /// into a new document `branch_id` owned by `owner`.
+    ///
+    /// The branch shares the history before the fork point with its parent.
+    /// Returns the fork point.
+    pub fn branch(
+        &self,
+        document_id: &str,
+        branch_id: String,
+        owner: String,
+        revision: Option<usize>,
+    ) -> Result<usize, AccessError> {
+        let session = self
+            .get_session(document_id)
+            .ok_or(AccessError::DocumentNotFound)?
+            .lock()
+            .unwrap()
+            .fork(branch_id.clone(), owner.clone(), revision)?;
+        let revision = session.branch().unwrap().revision;
+
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.contains_key(&branch_id) {
+            return Err(AccessError::DocumentExists);
+        }
+        tracing::info!(document = %branch_id, parent = document_id, revision, %owner, "branch created");
+        sessions.insert(branch_id, Arc::new(Mutex::new(session)));
+        Ok(revision)
+    }
+
+    /// Applies the operations of the branch since the fork point to its parent
+    /// on behalf of an editor of the parent.
+    ///
+    /// The operations are transformed through the later operations of the parent and
+    /// keep their authors, who have to be editors of the parent too.
+    /// Either all the operations are applied or none, a branch is merged once.
+    pub fn merge(&self, branch_id: &str, author: &str) -> Result<Merge, AccessError> {
+        let branch = self
+            .get_session(branch_id)
+            .ok_or(AccessError::DocumentNotFound)?;
+        let parent_id = (branch.lock().unwrap().branch())
+            .ok_or(AccessError::NotBranch)?
+            .parent
+            .clone();
+        let parent = self
+            .get_session(&parent_id)
+            .ok_or(AccessError::DocumentNotFound)?;
+
+        // the branch is locked until it is marked as merged, so it is merged once
+        let mut branch = branch.lock().unwrap();
+        let (base, operations) = branch.branch_operations()?;
+        let merge = {
+            let parent = parent.lock().unwrap();
+            let access = parent.access();
+            let authors = (operations.iter()).map(|(_, author)| author.as_str());
+            if !std::iter::once(author)
+                .chain(authors)
+                .all(|author| access.role(author).can_edit())
+            {
+                return Err(AccessError::ReadOnly);
+            }
+            parent.merge(base.revision, operations)?
         };
-        if let Some(session) = session {
-            let _connections = session.lock().unwrap().unsubscribe();
-            // TODO? if connections == 0 then delete session from sessions
+        branch.finish_merge();
+        tracing::info!(
+            document = %parent_id,
+            branch = branch_id,
+            operations = merge.operations.len(),
+            conflicts = merge.conflicts.len(),
+            "branch merged"
+        );
+        Ok(merge)
+    }
+
+    /// Returns the state of every open document
+    pub fn documents(&self) -> Vec<DocumentStats> {
+        self.sessions()
+            .into_iter()
+            .map(|(document, session)| {
+                let session = session.lock().unwrap();
+                DocumentStats {
+                    document,
+                    subscribers: session.subscribers(),
+                    revision: session.revision(),
+                }
+            })
+            .collect()
+    }
+
+    /// Waits until the input queues of all sessions are empty.
+    ///
+    /// The clients must stop sending first, otherwise the queues may never be empty.
+    pub async fn drain(&self) {
+        loop {
+            let mut queued = 0;
+            for (_, session) in self.sessions() {
+                let session = session.lock().unwrap();
+                session.apply_queued();
+                queued += session.queued();
+            }
+            if queued == 0 {
+                return;
+            }
+            tokio::time::sleep(DRAIN_INTERVAL).await;
         }
     }
+
+    /// Returns the open sessions, they are locked one by one,
+    /// so the map isn't locked for long
+    fn sessions(&self) -> Vec<(String, Arc<Mutex<Session>>)> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(document_id, session)| (document_id.clone(), Arc::clone(session)))
+            .collect()
+    }
+
+    fn get_session(&self, document_id: &str) -> Option<Arc<Mutex<Session>>> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(document_id)
+            .map(Arc::clone)
+    }
 }
 
-unsafe impl Sync for Manager {}
-unsafe impl Send for Manager {}
+#[cfg(test)]
+mod tests {
+    use super::Manager;
+    use crate::{
+        collaboration::{
+            access::{AccessError, Role},
+            sessions::Submission,
+        },
+        ot::{document::DocumentKind, operations::InsertOperation},
+    };
+
+    fn insert(manager: &Manager, document_id: &str, author: &str, position: usize, text: &str) {
+        let revision = manager.content(document_id).unwrap().1;
+        let operation = Box::new(InsertOperation::new(position, revision, text.to_string()));
+        (manager.submit(document_id, Submission::new(author.to_string(), operation))).unwrap();
+    }
+
+    #[test]
+    fn branch_authors_have_to_edit_parent() {
+        let manager = Manager::new();
+        manager
+            .create(
+                String::from("doc"),
+                String::from("alice"),
+                DocumentKind::Text,
+            )
+            .unwrap();
+        insert(&manager, "doc", "alice", 0, "hello");
+        manager
+            .branch("doc", String::from("draft"), String::from("bob"), None)
+            .unwrap();
+        insert(&manager, "draft", "bob", 5, " world");
+        manager
+            .set_role("doc", "alice", String::from("bob"), Role::Viewer)
+            .unwrap();
+
+        assert_eq!(
+            manager.merge("draft", "alice").unwrap_err(),
+            AccessError::ReadOnly
+        );
+        assert_eq!(manager.text("doc").unwrap(), "hello");
+
+        // the failed merge doesn't mark the branch as merged
+        manager
+            .set_role("doc", "alice", String::from("bob"), Role::Editor)
+            .unwrap();
+        let merge = manager.merge("draft", "alice").unwrap();
+        assert_eq!(merge.operations.len(), 1);
+        assert_eq!(manager.text("doc").unwrap(), "hello world");
+        assert_eq!(
+            manager.merge("draft", "alice").unwrap_err(),
+            AccessError::BranchMerged
+        );
+    }
+
+    #[test]
+    fn viewers_can_read() {
+        let manager = Manager::new();
+        manager
+            .create(
+                String::from("doc"),
+                String::from("alice"),
+                DocumentKind::Text,
+            )
+            .unwrap();
+        manager
+            .set_role("doc", "alice", String::from("bob"), Role::Viewer)
+            .unwrap();
+
+        assert_eq!(manager.check_viewer("doc", "bob"), Ok(()));
+        assert_eq!(manager.check_viewer("doc", "carol"), Ok(()));
+        assert_eq!(
+            manager.check_viewer("draft", "alice"),
+            Err(AccessError::DocumentNotFound)
+        );
+    }
+}
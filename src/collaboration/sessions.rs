@@ -1,38 +1,153 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
 
 use tokio::{
     sync::{broadcast, mpsc},
     task::JoinHandle,
 };
 use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
-use crate::ot::{
-    document::{self, DocumentTrait},
-    operations::{ArcOperation, Operation},
+use super::{
+    access::{AccessError, DocumentAccess},
+    audit::{AuditEntry, AuditLog, AuditQuery, AuditRecord, BlameRange},
+    checkpoints::{Checkpoint, Checkpoints},
+};
+use crate::{
+    metrics::METRICS,
+    ot::{
+        diff::diff,
+        document::{DocumentKind, DocumentTrait},
+        merge::{rebase, Conflict},
+        operations::{ArcOperation, Operation},
+    },
 };
 
+/// Operation with the name of its author
+pub struct Submission {
+    pub author: String,
+    pub operation: Operation,
+    /// Id which the client gave to the operation
+    pub operation_id: Option<String>,
+    /// Span of the request which submitted the operation, the operation is applied in it
+    pub span: tracing::Span,
+    /// Time of an imported operation, otherwise the operation is stamped when it is applied
+    pub timestamp: Option<SystemTime>,
+    /// Connection which submitted the operation, `None` if it came through REST
+    pub connection: Option<u64>,
+}
+
+impl Submission {
+    /// Creates a submission in the current span
+    pub fn new(author: String, operation: Operation) -> Self {
+        Submission {
+            author,
+            operation,
+            operation_id: None,
+            span: tracing::Span::current(),
+            timestamp: None,
+            connection: None,
+        }
+    }
+
+    pub fn with_operation_id(mut self, operation_id: Option<String>) -> Self {
+        self.operation_id = operation_id;
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn with_connection(mut self, connection: u64) -> Self {
+        self.connection = Some(connection);
+        self
+    }
+}
+
+/// Operation which the document rejected, it is reported to the connection which submitted it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub connection: u64,
+    pub operation_id: Option<String>,
+}
+
+/// Cursor of a client in the document, `None` when the client has left it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Presence {
+    pub client: String,
+    pub cursor: Option<usize>,
+}
+
+/// Document and revision which a branch was forked from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    pub parent: String,
+    /// The branch shares the operations before the revision with its parent
+    pub revision: usize,
+    pub merged: bool,
+}
+
+/// Operations of a branch applied to its parent and the text deleted by both of them
+#[derive(Debug, Clone)]
+pub struct Merge {
+    pub operations: Vec<ArcOperation>,
+    pub conflicts: Vec<Conflict>,
+}
+
 pub struct Session {
-    document: Arc<Mutex<document::DocumentMem>>,
-    input_sender: mpsc::Sender<Operation>,
-    input_receiver: Mutex<Option<mpsc::Receiver<Operation>>>,
+    document_id: String,
+    document: Arc<Mutex<Box<dyn DocumentTrait>>>,
+    input_sender: mpsc::Sender<Submission>,
+    input_receiver: Mutex<Option<mpsc::Receiver<Submission>>>,
     //if we want to interact with each client separately
     // it is better to take mpsc channels in hashmap
     output_sender: broadcast::Sender<ArcOperation>,
     subscribers: Mutex<usize>,
+    /// Cursors reported by the clients
+    cursors: HashMap<String, usize>,
+    presence_sender: broadcast::Sender<Presence>,
+    rejection_sender: broadcast::Sender<Rejection>,
+    /// Authors and times of the operations by revision
+    audit: Mutex<AuditLog>,
+    checkpoints: Checkpoints,
+    /// `None` if the document wasn't forked
+    branch: Option<Branch>,
     listner_cancelled_token: Option<CancellationToken>,
+    access: DocumentAccess,
 }
 
 impl Session {
-    pub fn new() -> Self {
+    /// Creates a session of a new document of `kind`, `owner` is the client who created it
+    pub fn new(document_id: String, owner: String, kind: DocumentKind) -> Self {
+        Session::with_document(document_id, owner, kind.create())
+    }
+
+    fn with_document(document_id: String, owner: String, document: Box<dyn DocumentTrait>) -> Self {
         let (mpsc_sender, mpsc_receiver) = mpsc::channel(128);
         let (broadcast_sender, _) = broadcast::channel(64);
+        let (presence_sender, _) = broadcast::channel(64);
+        let (rejection_sender, _) = broadcast::channel(64);
         Session {
-            document: Arc::new(Mutex::new(document::DocumentMem::new())),
+            document_id,
+            document: Arc::new(Mutex::new(document)),
             input_sender: mpsc_sender,
             input_receiver: Mutex::new(Some(mpsc_receiver)),
             output_sender: broadcast_sender,
             subscribers: Mutex::new(0),
+            cursors: HashMap::new(),
+            presence_sender,
+            rejection_sender,
+            audit: Mutex::new(AuditLog::new()),
+            checkpoints: Checkpoints::new(),
+            branch: None,
             listner_cancelled_token: None,
+            access: DocumentAccess::new(owner),
         }
     }
     pub fn subscribe(&mut self) -> broadcast::Receiver<ArcOperation> {
@@ -40,7 +155,7 @@ impl Session {
         self.output_sender.subscribe()
     }
     pub fn subscribers(&self) -> usize {
-        self.subscribers.lock().unwrap().clone()
+        *self.subscribers.lock().unwrap()
     }
 
     pub fn listner_work(&self) -> bool {
@@ -60,28 +175,402 @@ impl Session {
         *subscribers
     }
 
-    pub fn get_input(&self) -> mpsc::Sender<Operation> {
+    pub fn access(&self) -> &DocumentAccess {
+        &self.access
+    }
+
+    pub fn access_mut(&mut self) -> &mut DocumentAccess {
+        &mut self.access
+    }
+
+    /// Updates the cursor of the client and notifies the other clients
+    pub fn set_presence(&mut self, client: String, cursor: Option<usize>) {
+        let changed = match cursor {
+            Some(cursor) => self.cursors.insert(client.clone(), cursor) != Some(cursor),
+            None => self.cursors.remove(&client).is_some(),
+        };
+        if changed {
+            let _ = self.presence_sender.send(Presence { client, cursor });
+        }
+    }
+
+    /// Returns the current cursors and a receiver of their changes
+    pub fn presence(&self) -> (Vec<Presence>, broadcast::Receiver<Presence>) {
+        let cursors = self
+            .cursors
+            .iter()
+            .map(|(client, cursor)| Presence {
+                client: client.clone(),
+                cursor: Some(*cursor),
+            })
+            .collect();
+        (cursors, self.presence_sender.subscribe())
+    }
+
+    /// Returns a receiver of the operations which the document rejects
+    pub fn rejections(&self) -> broadcast::Receiver<Rejection> {
+        self.rejection_sender.subscribe()
+    }
+
+    pub fn get_input(&self) -> mpsc::Sender<Submission> {
         self.input_sender.clone()
     }
+
+    /// Number of operations waiting in the input queue
+    pub fn queued(&self) -> usize {
+        self.input_sender.max_capacity() - self.input_sender.capacity()
+    }
+
+    /// Applies the queued operations if the listener isn't running,
+    /// otherwise they are applied by the listener
+    pub fn apply_queued(&self) {
+        if let Some(receiver) = self.input_receiver.lock().unwrap().as_mut() {
+            while let Ok(submission) = receiver.try_recv() {
+                self.apply(submission);
+            }
+        }
+    }
+
+    /// Applies the operation bypassing the input queue and sends the result to the subscribers.
+    ///
+    /// The session is locked while the operation is applied,
+    /// so the subscribers receive operations in the order of revisions.
+    /// The operation is applied in the span of the submission.
+    /// Returns `None` if the document rejects the operation, see [`DocumentTrait::apply`],
+    /// the connection which submitted it is notified.
+    pub fn apply(&self, submission: Submission) -> Option<ArcOperation> {
+        let Submission {
+            author,
+            operation,
+            operation_id,
+            span,
+            timestamp,
+            connection,
+        } = submission;
+        let _span = span.entered();
+        let ans = {
+            // TODO I think we can remove mutex here if provide internal mutability in Document
+            // But I don't know if we need it
+            let mut document = self.document.lock().unwrap();
+            let concurrent = document.revision().saturating_sub(operation.revision());
+            let (kind, size) = (operation.kind(), operation.size());
+            let start = Instant::now();
+            let Some(ans) = document.apply(operation) else {
+                METRICS.operations_rejected.inc();
+                tracing::debug!(
+                    document = %self.document_id,
+                    %author,
+                    kind,
+                    size,
+                    "operation rejected"
+                );
+                if let Some(connection) = connection {
+                    let rejection = Rejection {
+                        connection,
+                        operation_id,
+                    };
+                    let _ = self.rejection_sender.send(rejection);
+                }
+                return None;
+            };
+            METRICS.observe_apply(start.elapsed());
+            METRICS.operations_applied.inc();
+            METRICS.transform_steps.add(concurrent as u64);
+            tracing::debug!(
+                document = %self.document_id,
+                %author,
+                revision = ans.revision(),
+                concurrent,
+                kind,
+                size,
+                operation = ?ans,
+                "operation applied"
+            );
+            self.audit.lock().unwrap().push(AuditEntry {
+                author,
+                timestamp: timestamp.unwrap_or_else(SystemTime::now),
+                operation_id,
+                connection,
+            });
+            ans
+        };
+        // nobody may listen, e.g. when the operation came through REST
+        let _ = self.output_sender.send(Arc::clone(&ans));
+        Some(ans)
+    }
+
+    pub fn text(&self) -> String {
+        self.document.lock().unwrap().text()
+    }
+
+    /// Text of the document with its revision, both are read at once
+    pub fn content(&self) -> (String, usize) {
+        let document = self.document.lock().unwrap();
+        (document.text(), document.revision())
+    }
+
+    /// Length of the history of the document
+    pub fn revision(&self) -> usize {
+        self.document.lock().unwrap().revision()
+    }
+
+    /// Stamp of the operation with the revision
+    pub fn stamp(&self, revision: usize) -> Option<AuditEntry> {
+        self.audit.lock().unwrap().get(revision).cloned()
+    }
+
+    /// Returns the applied operations whose stamps match `query`, see [`AuditLog::query`]
+    pub fn audit(&self, query: &AuditQuery) -> Vec<AuditRecord> {
+        let document = self.document.lock().unwrap();
+        self.audit
+            .lock()
+            .unwrap()
+            .query(document.operations(0), query)
+    }
+
+    /// Returns the applied operations with the revisions in the range and their stamps
+    pub fn playback(&self, revisions: Range<usize>) -> Result<Vec<AuditRecord>, AccessError> {
+        let document = self.document.lock().unwrap();
+        if revisions.end > document.revision() {
+            return Err(AccessError::RevisionNotFound);
+        }
+        Ok(self
+            .audit
+            .lock()
+            .unwrap()
+            .range(document.operations(0), revisions))
+    }
+
+    /// Maps the ranges of the current text to the operations which inserted them
+    pub fn blame(&self) -> Vec<BlameRange> {
+        let document = self.document.lock().unwrap();
+        self.audit.lock().unwrap().blame(document.operations(0))
+    }
+
+    /// Names the revision of the document, the current one if `revision` is `None`
+    pub fn create_checkpoint(
+        &mut self,
+        name: String,
+        author: String,
+        revision: Option<usize>,
+    ) -> Result<Checkpoint, AccessError> {
+        let current = self.revision();
+        let revision = revision.unwrap_or(current);
+        if revision > current {
+            return Err(AccessError::RevisionNotFound);
+        }
+        let checkpoint = Checkpoint {
+            name,
+            revision,
+            author,
+            timestamp: SystemTime::now(),
+        };
+        self.checkpoints.insert(checkpoint.clone())?;
+        Ok(checkpoint)
+    }
+
+    pub fn checkpoints(&self) -> Vec<Checkpoint> {
+        self.checkpoints.list().to_vec()
+    }
+
+    /// Returns the operations turning the text at the checkpoint `from` into the text
+    /// at the checkpoint `to`, or into the current text if `to` is `None`.
+    ///
+    /// The operations have the revision of `from` and follow each other, see [`diff`].
+    pub fn diff_checkpoints(
+        &self,
+        from: &str,
+        to: Option<&str>,
+    ) -> Result<Vec<Operation>, AccessError> {
+        let checkpoint = |name| {
+            self.checkpoints
+                .get(name)
+                .ok_or(AccessError::CheckpointNotFound)
+        };
+        let from = checkpoint(from)?.revision;
+        let to = to.map(checkpoint).transpose()?;
+
+        let document = self.document.lock().unwrap();
+        let old = document.text_at(from).unwrap();
+        let new = match to {
+            Some(to) => document.text_at(to.revision).unwrap(),
+            None => document.text(),
+        };
+        Ok(diff(&old, &new, from))
+    }
+
+    /// Returns the operation which brings back the content at the checkpoint,
+    /// `None` if the content is the same
+    pub fn restore(&self, name: &str) -> Result<Option<Operation>, AccessError> {
+        let revision = (self.checkpoints.get(name))
+            .ok_or(AccessError::CheckpointNotFound)?
+            .revision;
+        let document = self.document.lock().unwrap();
+        if document.text_at(revision).unwrap() == document.text() {
+            return Ok(None);
+        }
+        document
+            .restore(revision)
+            .map(Some)
+            .ok_or(AccessError::InvalidOperation)
+    }
+
+    /// Creates a session of a document of `kind` which replays `history`, e.g. exported
+    /// from another server.
+    ///
+    /// The operations keep their stamps and must get the same revisions,
+    /// i.e. each one is based on the previous ones.
+    pub fn import(
+        document_id: String,
+        owner: String,
+        kind: DocumentKind,
+        history: Vec<(Operation, AuditEntry)>,
+    ) -> Result<Session, AccessError> {
+        let session = Session::new(document_id, owner, kind);
+        for (operation, entry) in history {
+            let revision = operation.revision();
+            let submission = Submission::new(entry.author, operation)
+                .with_operation_id(entry.operation_id)
+                .with_timestamp(entry.timestamp);
+            match session.apply(submission) {
+                Some(operation) if operation.revision() == revision => {}
+                _ => return Err(AccessError::InvalidOperation),
+            }
+        }
+        Ok(session)
+    }
+
+    /// Returns the kind of the document and its whole history with the stamps,
+    /// both are read at once
+    pub fn export(&self) -> (DocumentKind, Vec<AuditRecord>) {
+        let document = self.document.lock().unwrap();
+        let history = document.operations(0);
+        let records = self.audit.lock().unwrap().range(history, 0..history.len());
+        (document.kind(), records)
+    }
+
+    /// Creates a session of a branch which shares the history before `revision` with this
+    /// document, the current revision if `revision` is `None`. `owner` becomes its owner.
+    pub fn fork(
+        &self,
+        document_id: String,
+        owner: String,
+        revision: Option<usize>,
+    ) -> Result<Session, AccessError> {
+        let document = self.document.lock().unwrap();
+        let revision = revision.unwrap_or(document.revision());
+        let fork = document
+            .fork(revision)
+            .ok_or(AccessError::RevisionNotFound)?;
+        let mut session = Session::with_document(document_id, owner, fork);
+        session.audit = Mutex::new(self.audit.lock().unwrap().fork(revision));
+        session.branch = Some(Branch {
+            parent: self.document_id.clone(),
+            revision,
+            merged: false,
+        });
+        Ok(session)
+    }
+
+    pub fn branch(&self) -> Option<&Branch> {
+        self.branch.as_ref()
+    }
+
+    /// Returns the operations of the branch since the fork with their authors
+    pub fn branch_operations(&self) -> Result<(Branch, Vec<(ArcOperation, String)>), AccessError> {
+        let branch = self.branch.clone().ok_or(AccessError::NotBranch)?;
+        if branch.merged {
+            return Err(AccessError::BranchMerged);
+        }
+
+        let document = self.document.lock().unwrap();
+        let audit = self.audit.lock().unwrap();
+        let operations = (document.operations(branch.revision).iter())
+            .map(|operation| {
+                let author = audit.get(operation.revision()).unwrap().author.clone();
+                (Arc::clone(operation), author)
+            })
+            .collect();
+        Ok((branch, operations))
+    }
+
+    /// Marks the branch as merged, it can't be merged again
+    pub fn finish_merge(&mut self) {
+        if let Some(branch) = self.branch.as_mut() {
+            branch.merged = true;
+        }
+    }
+
+    /// Applies the operations of a branch forked at `revision`, see [`rebase`].
+    ///
+    /// The operations keep their authors and are sent to the subscribers like any others.
+    /// Either all of them are applied or none: they are tried on a copy of the document first.
+    pub fn merge(
+        &self,
+        revision: usize,
+        operations: Vec<(ArcOperation, String)>,
+    ) -> Result<Merge, AccessError> {
+        let (branch, authors): (Vec<_>, Vec<_>) = operations.into_iter().unzip();
+        let (rebased, conflicts) = {
+            let document = self.document.lock().unwrap();
+            let (rebased, conflicts) = rebase(&branch, document.operations(revision));
+            let mut copy = document
+                .fork(document.revision())
+                .ok_or(AccessError::RevisionNotFound)?;
+            for operation in &rebased {
+                let mut operation = operation.boxed_clone();
+                operation.set_revision(copy.revision());
+                copy.apply(operation).ok_or(AccessError::InvalidOperation)?;
+            }
+            (rebased, conflicts)
+        };
+
+        let mut applied = vec![];
+        for (mut operation, author) in rebased.into_iter().zip(authors) {
+            // the session is locked, nothing is applied in between
+            operation.set_revision(self.revision());
+            let operation = self
+                .apply(Submission::new(author, operation))
+                .ok_or(AccessError::InvalidOperation)?;
+            applied.push(operation);
+        }
+        Ok(Merge {
+            operations: applied,
+            conflicts,
+        })
+    }
+
+    /// Returns the operations starting from revision `since`
+    /// and a receiver of the following ones.
+    ///
+    /// The receiver isn't counted as a subscriber. An operation can be both
+    /// in the history and in the receiver, so the receiver should skip old revisions.
+    pub fn operations_since(
+        &self,
+        since: usize,
+    ) -> (Vec<ArcOperation>, broadcast::Receiver<ArcOperation>) {
+        let receiver = self.output_sender.subscribe();
+        let operations = self.document.lock().unwrap().operations(since).to_vec();
+        (operations, receiver)
+    }
     pub fn start_listen(session: Arc<Mutex<Self>>) -> JoinHandle<()> {
         let cancelled_token = CancellationToken::new();
-        {
-            session.lock().unwrap().listner_cancelled_token = Some(cancelled_token);
+        let span = {
+            let mut session = session.lock().unwrap();
+            session.listner_cancelled_token = Some(cancelled_token);
+            tracing::info_span!(parent: None, "session", document = %session.document_id)
         };
-        tokio::spawn(Self::listen(session))
+        tokio::spawn(Self::listen(session).instrument(span))
     }
 
     async fn listen(session: Arc<Mutex<Self>>) {
         let cancelled_token;
-        let (mut rec, document) = {
+        let mut rec = {
             let session = session.lock().unwrap();
             cancelled_token = session.listner_cancelled_token.clone().unwrap();
 
-            let x = (
-                session.input_receiver.lock().unwrap().take().unwrap(),
-                Arc::clone(&session.document),
-            );
-            x
+            let receiver = session.input_receiver.lock().unwrap().take().unwrap();
+            receiver
         };
 
         loop {
@@ -91,17 +580,12 @@ impl Session {
                 _ = cancelled_token.cancelled() => {
                     let session = session.lock().unwrap();
                     *session.input_receiver.lock().unwrap() = Some(rec);
+                    tracing::debug!("listener stopped");
                     return;
                 },
                 operation = rec.recv() => {
-                    if let Some(operation) = operation {
-                        let ans;
-                        {
-                            let mut document = document.lock().unwrap(); // TODO I think we can remove mutex here if provide internal mutability in Document
-                            // But I don't know if we need it
-                            ans = document.apply(operation);
-                        };
-                        session.lock().unwrap().output_sender.send(ans).unwrap();
+                    if let Some(submission) = operation {
+                        session.lock().unwrap().apply(submission);
                     }
                 }
             }
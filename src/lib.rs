@@ -1,3 +1,6 @@
 pub mod collaboration;
 pub mod ot;
 pub mod api;
+pub mod metrics;
+#[cfg(feature = "client")]
+pub mod client;
@@ -0,0 +1,335 @@
+//! Process-wide metrics in the Prometheus text format, served by `GET /metrics`.
+//!
+//! Counters and histograms are global atomics updated where the events happen,
+//! the gauges of the documents are taken from [`Manager`] when the metrics are scraped.
+
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::collaboration::manager::Manager;
+
+pub static METRICS: Metrics = Metrics::new();
+
+/// Bounds of the buckets of the apply latency in nanoseconds, from 1 µs to 100 ms
+const APPLY_BUCKETS: [u64; 9] = [
+    1_000,
+    5_000,
+    10_000,
+    50_000,
+    100_000,
+    500_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+];
+
+/// Bounds of the buckets of the message sizes in bytes, from 64 B to 1 MiB
+const SIZE_BUCKETS: [u64; 8] = [64, 256, 1024, 4096, 16384, 65536, 262144, 1048576];
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    const fn new() -> Self {
+        Gauge(AtomicI64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Increments the gauge until the returned guard is dropped
+    pub fn track(&'static self) -> GaugeGuard {
+        self.inc();
+        GaugeGuard(self)
+    }
+}
+
+pub struct GaugeGuard(&'static Gauge);
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Histogram of integer observations, `N` is the number of buckets without `+Inf`
+pub struct Histogram<const N: usize> {
+    bounds: [u64; N],
+    /// Observations per bucket, the last one is `+Inf`
+    buckets: [AtomicU64; N],
+    overflow: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl<const N: usize> Histogram<N> {
+    const fn new(bounds: [u64; N]) -> Self {
+        Histogram {
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; N],
+            overflow: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        let bucket = self.bounds.iter().position(|bound| value <= *bound);
+        match bucket {
+            Some(bucket) => self.buckets[bucket].fetch_add(1, Ordering::Relaxed),
+            None => self.overflow.fetch_add(1, Ordering::Relaxed),
+        };
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Writes the series of the histogram, the values are divided by `scale`
+    fn render(&self, out: &mut String, name: &str, labels: &str, scale: f64) {
+        let mut count = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            count += bucket.load(Ordering::Relaxed);
+            let le = *bound as f64 / scale;
+            let _ = writeln!(out, "{name}_bucket{{{labels}le=\"{le}\"}} {count}");
+        }
+        count += self.overflow.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name}_bucket{{{labels}le=\"+Inf\"}} {count}");
+
+        let sum = self.sum.load(Ordering::Relaxed) as f64 / scale;
+        let labels = match labels.trim_end_matches(',') {
+            "" => String::new(),
+            labels => format!("{{{labels}}}"),
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {sum}");
+        let _ = writeln!(out, "{name}_count{labels} {count}");
+    }
+}
+
+pub struct Metrics {
+    /// Open `/ws` connections
+    pub connections: Gauge,
+    pub operations_applied: Counter,
+    /// Transformations of the applied operations relative to the concurrent ones
+    pub transform_steps: Counter,
+    /// Operations which don't fit the document
+    pub operations_rejected: Counter,
+    /// Operations which lagging receivers of the broadcast missed
+    pub broadcast_lagged: Counter,
+    /// Duration of [`DocumentTrait::apply`](crate::ot::document::DocumentTrait::apply) in nanoseconds
+    pub apply_duration: Histogram<9>,
+    /// Sizes of the `/ws` messages of the clients in bytes
+    pub received_size: Histogram<8>,
+    /// Sizes of the `/ws` messages of the server in bytes
+    pub sent_size: Histogram<8>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            connections: Gauge::new(),
+            operations_applied: Counter::new(),
+            transform_steps: Counter::new(),
+            operations_rejected: Counter::new(),
+            broadcast_lagged: Counter::new(),
+            apply_duration: Histogram::new(APPLY_BUCKETS),
+            received_size: Histogram::new(SIZE_BUCKETS),
+            sent_size: Histogram::new(SIZE_BUCKETS),
+        }
+    }
+
+    pub fn observe_apply(&self, duration: Duration) {
+        self.apply_duration.observe(duration.as_nanos() as u64);
+    }
+}
+
+/// Writes the header of a metric family
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Escapes a label value of the text format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Renders the metrics in the Prometheus text exposition format
+pub fn render(manager: &Manager) -> String {
+    let metrics = &METRICS;
+    let mut out = String::new();
+
+    let documents = manager.documents();
+    family(&mut out, "ot_sessions", "gauge", "Open document sessions.");
+    let _ = writeln!(out, "ot_sessions {}", documents.len());
+
+    family(
+        &mut out,
+        "ot_connections",
+        "gauge",
+        "Open WebSocket connections.",
+    );
+    let _ = writeln!(out, "ot_connections {}", metrics.connections.get());
+
+    family(
+        &mut out,
+        "ot_document_subscribers",
+        "gauge",
+        "Subscribers of the document.",
+    );
+    for document in &documents {
+        let _ = writeln!(
+            out,
+            "ot_document_subscribers{{document=\"{}\"}} {}",
+            escape(&document.document),
+            document.subscribers
+        );
+    }
+
+    family(
+        &mut out,
+        "ot_document_history_length",
+        "gauge",
+        "Operations in the history of the document.",
+    );
+    for document in &documents {
+        let _ = writeln!(
+            out,
+            "ot_document_history_length{{document=\"{}\"}} {}",
+            escape(&document.document),
+            document.revision
+        );
+    }
+
+    let counters = [
+        (
+            "ot_operations_applied_total",
+            "Operations applied to the documents.",
+            &metrics.operations_applied,
+        ),
+        (
+            "ot_transform_steps_total",
+            "Transformations of the applied operations relative to the concurrent ones.",
+            &metrics.transform_steps,
+        ),
+        (
+            "ot_operations_rejected_total",
+            "Operations which don't fit the document.",
+            &metrics.operations_rejected,
+        ),
+        (
+            "ot_broadcast_lagged_total",
+            "Operations which lagging receivers of the broadcast missed.",
+            &metrics.broadcast_lagged,
+        ),
+    ];
+    for (name, help, counter) in counters {
+        family(&mut out, name, "counter", help);
+        let _ = writeln!(out, "{name} {}", counter.get());
+    }
+
+    family(
+        &mut out,
+        "ot_apply_duration_seconds",
+        "histogram",
+        "Duration of applying an operation to the document.",
+    );
+    metrics
+        .apply_duration
+        .render(&mut out, "ot_apply_duration_seconds", "", 1e9);
+
+    family(
+        &mut out,
+        "ot_message_size_bytes",
+        "histogram",
+        "Sizes of the WebSocket messages.",
+    );
+    metrics.received_size.render(
+        &mut out,
+        "ot_message_size_bytes",
+        "direction=\"received\",",
+        1.0,
+    );
+    metrics.sent_size.render(
+        &mut out,
+        "ot_message_size_bytes",
+        "direction=\"sent\",",
+        1.0,
+    );
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, Histogram};
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new([10, 100]);
+        for value in [5, 10, 50, 1000] {
+            histogram.observe(value);
+        }
+
+        let mut out = String::new();
+        histogram.render(&mut out, "size", "direction=\"sent\",", 1.0);
+        assert_eq!(
+            out,
+            "size_bucket{direction=\"sent\",le=\"10\"} 2\n\
+             size_bucket{direction=\"sent\",le=\"100\"} 3\n\
+             size_bucket{direction=\"sent\",le=\"+Inf\"} 4\n\
+             size_sum{direction=\"sent\"} 1065\n\
+             size_count{direction=\"sent\"} 4\n"
+        );
+    }
+
+    #[test]
+    fn histogram_values_are_scaled() {
+        let histogram = Histogram::new([1_000]);
+        histogram.observe(500);
+
+        let mut out = String::new();
+        histogram.render(&mut out, "duration_seconds", "", 1e9);
+        assert_eq!(
+            out,
+            "duration_seconds_bucket{le=\"0.000001\"} 1\n\
+             duration_seconds_bucket{le=\"+Inf\"} 1\n\
+             duration_seconds_sum 0.0000005\n\
+             duration_seconds_count 1\n"
+        );
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -1,2 +1,8 @@
+pub mod client;
+pub mod diff;
 pub mod document;
+pub mod json;
+pub mod merge;
 pub mod operations;
+pub mod rich_text;
+pub mod sequence;
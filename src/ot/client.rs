@@ -0,0 +1,175 @@
+use std::{collections::VecDeque, mem};
+
+use super::operations::{Operation, OperationTrait};
+
+/// Operations of the client which the server hasn't acknowledged yet
+#[derive(Debug, Default)]
+pub enum State {
+    /// The server knows all the operations of the client
+    #[default]
+    Synchronized,
+    /// The operation is sent and waits for the acknowledgement
+    AwaitingConfirm(Operation),
+    /// The operation is sent, the buffered ones are sent one by one after the acknowledgement
+    AwaitingWithBuffer(Operation, VecDeque<Operation>),
+}
+
+/// Local copy of a document on the client side.
+///
+/// Local edits are applied immediately, the server operations
+/// are transformed relative to the edits which the server doesn't know yet.
+#[derive(Debug)]
+pub struct ClientDocument {
+    text: String,
+    /// Number of the server operations applied to the text
+    revision: usize,
+    state: State,
+}
+
+impl ClientDocument {
+    pub fn new(text: String, revision: usize) -> Self {
+        ClientDocument {
+            text,
+            revision,
+            state: State::Synchronized,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Applies the local edit.
+    ///
+    /// Returns the operation which has to be sent to the server, if the client isn't waiting
+    /// for an acknowledgement. Otherwise the edit is buffered.
+    pub fn edit(&mut self, operation: Operation) -> Option<Operation> {
+        operation.apply(&mut self.text);
+
+        match mem::take(&mut self.state) {
+            State::Synchronized => {
+                self.state = State::AwaitingConfirm(operation);
+                self.pending()
+            }
+            State::AwaitingConfirm(sent) => {
+                self.state = State::AwaitingWithBuffer(sent, VecDeque::from([operation]));
+                None
+            }
+            State::AwaitingWithBuffer(sent, mut buffer) => {
+                buffer.push_back(operation);
+                self.state = State::AwaitingWithBuffer(sent, buffer);
+                None
+            }
+        }
+    }
+
+    /// Applies the operation of another client received from the server.
+    ///
+    /// Returns the operation as it was applied to the local text.
+    pub fn receive(&mut self, operation: &dyn OperationTrait) -> Operation {
+        let mut operation = operation.boxed_clone();
+
+        // the server has ordered `operation` before all our unacknowledged operations
+        let mut transform = |pending: &mut Operation| {
+            let received = operation.boxed_clone();
+            operation.transform_relative_to_later(pending.as_ref());
+            pending.transform_relative_to(received.as_ref());
+        };
+        match &mut self.state {
+            State::Synchronized => {}
+            State::AwaitingConfirm(sent) => transform(sent),
+            State::AwaitingWithBuffer(sent, buffer) => {
+                transform(sent);
+                buffer.iter_mut().for_each(transform);
+            }
+        }
+
+        operation.apply(&mut self.text);
+        self.revision += 1;
+        operation
+    }
+
+    /// Handles the acknowledgement of the sent operation.
+    ///
+    /// Returns the next operation which has to be sent to the server.
+    pub fn acknowledge(&mut self) -> Option<Operation> {
+        self.state = match mem::take(&mut self.state) {
+            State::Synchronized => panic!("nothing to acknowledge"),
+            State::AwaitingConfirm(_) => State::Synchronized,
+            State::AwaitingWithBuffer(_, mut buffer) => {
+                let next = buffer.pop_front().unwrap();
+                match buffer.is_empty() {
+                    true => State::AwaitingConfirm(next),
+                    false => State::AwaitingWithBuffer(next, buffer),
+                }
+            }
+        };
+        self.revision += 1;
+        self.pending()
+    }
+
+    /// The sent operation based on the current revision.
+    ///
+    /// After a reconnection it has to be sent again, if the server history doesn't contain it.
+    pub fn pending(&self) -> Option<Operation> {
+        let sent = match &self.state {
+            State::Synchronized => return None,
+            State::AwaitingConfirm(sent) | State::AwaitingWithBuffer(sent, _) => sent,
+        };
+        let mut operation = sent.boxed_clone();
+        operation.set_revision(self.revision);
+        Some(operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientDocument, State};
+    use crate::ot::{
+        document::{DocumentMem, DocumentTrait},
+        operations::{DeleteOperation, InsertOperation},
+    };
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let mut server = DocumentMem::new();
+        let mut alice = ClientDocument::new(String::new(), 0);
+        let mut bob = ClientDocument::new(String::new(), 0);
+
+        let alice_op = alice
+            .edit(Box::new(InsertOperation::new(0, 0, String::from("abc"))))
+            .unwrap();
+        let bob_op = bob
+            .edit(Box::new(InsertOperation::new(0, 0, String::from("xyz"))))
+            .unwrap();
+        assert!(bob.edit(Box::new(DeleteOperation::new(1, 0, 1))).is_none());
+        assert!(matches!(bob.state(), State::AwaitingWithBuffer(_, _)));
+
+        // the server receives alice first
+        let first = server.apply(alice_op).unwrap();
+        alice.acknowledge();
+        bob.receive(first.as_ref().as_ref());
+
+        let second = server.apply(bob_op).unwrap();
+        let bob_next = bob.acknowledge().unwrap();
+        alice.receive(second.as_ref().as_ref());
+
+        let third = server.apply(bob_next).unwrap();
+        assert!(bob.acknowledge().is_none());
+        alice.receive(third.as_ref().as_ref());
+
+        assert_eq!(server.text(), "abcxz");
+        assert_eq!(alice.text(), server.text());
+        assert_eq!(bob.text(), server.text());
+        assert_eq!(alice.revision(), 3);
+        assert_eq!(bob.revision(), 3);
+    }
+}
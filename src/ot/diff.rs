@@ -0,0 +1,279 @@
+//! Operations turning one text into another.
+//!
+//! Integrations which produce a whole new text submit its difference from the text they started
+//! from, so it merges with the concurrent edits like the edits of a client.
+//! The difference is found by the Myers algorithm on characters, the bisection of
+//! the edit graph keeps the memory linear for large rewrites. Parts of the texts which
+//! are too different to be compared in reasonable time are replaced as a whole.
+
+use super::operations::{DeleteOperation, InsertOperation, Operation};
+
+/// Number of the steps in the edit graph after which a bisection gives up
+const MAX_STEPS: usize = 1 << 24;
+
+/// Part of the edit script, the lengths are in bytes
+#[derive(Debug, PartialEq, Eq)]
+enum Edit {
+    Keep(usize),
+    Delete(usize),
+    Insert(String),
+}
+
+/// Returns the shortest sequence of insertions and deletions turning `old` into `new`.
+///
+/// Each operation is based on the text left by the previous ones and has `revision`.
+pub fn diff(old: &str, new: &str, revision: usize) -> Vec<Operation> {
+    let old: Vec<char> = old.chars().collect();
+    let new: Vec<char> = new.chars().collect();
+    let mut edits = vec![];
+    diff_chars(&old, &new, &mut edits);
+
+    let mut operations: Vec<Operation> = vec![];
+    let mut position = 0;
+    for edit in edits {
+        match edit {
+            Edit::Keep(len) => position += len,
+            Edit::Delete(len) => {
+                operations.push(Box::new(DeleteOperation::new(position, revision, len)))
+            }
+            Edit::Insert(text) => {
+                let len = text.len();
+                operations.push(Box::new(InsertOperation::new(position, revision, text)));
+                position += len;
+            }
+        }
+    }
+    operations
+}
+
+fn byte_len(chars: &[char]) -> usize {
+    chars.iter().map(|char| char.len_utf8()).sum()
+}
+
+/// Appends the edit, merging it with the previous one of the same kind
+fn push(edits: &mut Vec<Edit>, edit: Edit) {
+    match (edits.last_mut(), edit) {
+        (_, Edit::Keep(0) | Edit::Delete(0)) => {}
+        (_, Edit::Insert(text)) if text.is_empty() => {}
+        (Some(Edit::Keep(last)), Edit::Keep(len)) => *last += len,
+        (Some(Edit::Delete(last)), Edit::Delete(len)) => *last += len,
+        (Some(Edit::Insert(last)), Edit::Insert(text)) => last.push_str(&text),
+        (_, edit) => edits.push(edit),
+    }
+}
+
+fn diff_chars(old: &[char], new: &[char], edits: &mut Vec<Edit>) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    push(edits, Edit::Keep(byte_len(&old[..prefix])));
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = (old.iter().rev())
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new, suffix) = (
+        &old[..old.len() - suffix],
+        &new[..new.len() - suffix],
+        &old[old.len() - suffix..],
+    );
+
+    let split = match old.is_empty() || new.is_empty() {
+        true => None,
+        false => bisect(old, new),
+    };
+    match split {
+        Some((x, y)) => {
+            diff_chars(&old[..x], &new[..y], edits);
+            diff_chars(&old[x..], &new[y..], edits);
+        }
+        // one of the texts is empty or they have nothing in common
+        None => {
+            push(edits, Edit::Delete(byte_len(old)));
+            push(edits, Edit::Insert(new.iter().collect()));
+        }
+    }
+    push(edits, Edit::Keep(byte_len(suffix)));
+}
+
+/// Finds the middle snake of the shortest edit script: the paths from both ends meet at
+/// the returned point, the texts are split there. `None` if the texts have nothing in common
+/// or the search takes more than [`MAX_STEPS`].
+fn bisect(old: &[char], new: &[char]) -> Option<(usize, usize)> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d;
+    let len = 2 * max_d + 2;
+    // the furthest x on each diagonal k = x - y, forward and backward
+    let mut forward = vec![-1; len as usize];
+    let mut backward = vec![-1; len as usize];
+    forward[offset as usize + 1] = 0;
+    backward[offset as usize + 1] = 0;
+    let delta = n - m;
+    // the paths meet on the forward pass if the number of edits is odd
+    let front = delta % 2 != 0;
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+    let mut steps = 0;
+
+    for d in 0..max_d {
+        steps += 2 * d as usize;
+        if steps > MAX_STEPS {
+            return None;
+        }
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let k1_offset = (offset + k1) as usize;
+            let mut x1 =
+                match k1 == -d || (k1 != d && forward[k1_offset - 1] < forward[k1_offset + 1]) {
+                    true => forward[k1_offset + 1],
+                    false => forward[k1_offset - 1] + 1,
+                };
+            let mut y1 = x1 - k1;
+            while x1 < n && y1 < m && old[x1 as usize] == new[y1 as usize] {
+                x1 += 1;
+                y1 += 1;
+                steps += 1;
+            }
+            forward[k1_offset] = x1;
+            if x1 > n {
+                // ran off the right of the graph
+                k1_end += 2;
+            } else if y1 > m {
+                // ran off the bottom of the graph
+                k1_start += 2;
+            } else if front {
+                let k2_offset = offset + delta - k1;
+                if (0..len).contains(&k2_offset) && backward[k2_offset as usize] != -1 {
+                    let x2 = n - backward[k2_offset as usize];
+                    if x1 >= x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let k2_offset = (offset + k2) as usize;
+            let mut x2 =
+                match k2 == -d || (k2 != d && backward[k2_offset - 1] < backward[k2_offset + 1]) {
+                    true => backward[k2_offset + 1],
+                    false => backward[k2_offset - 1] + 1,
+                };
+            let mut y2 = x2 - k2;
+            while x2 < n && y2 < m && old[(n - x2 - 1) as usize] == new[(m - y2 - 1) as usize] {
+                x2 += 1;
+                y2 += 1;
+                steps += 1;
+            }
+            backward[k2_offset] = x2;
+            if x2 > n {
+                k2_end += 2;
+            } else if y2 > m {
+                k2_start += 2;
+            } else if !front {
+                let k1_offset = offset + delta - k2;
+                if (0..len).contains(&k1_offset) && forward[k1_offset as usize] != -1 {
+                    let x1 = forward[k1_offset as usize];
+                    let y1 = offset + x1 - k1_offset;
+                    if x1 >= n - x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::diff;
+
+    fn apply(old: &str, new: &str) -> (String, usize) {
+        let operations = diff(old, new, 0);
+        let mut text = String::from(old);
+        for operation in &operations {
+            assert!(operation.can_apply(&text), "{operation:?} on {text:?}");
+            operation.apply(&mut text);
+        }
+        let edited = operations.iter().map(|operation| operation.size()).sum();
+        (text, edited)
+    }
+
+    #[test]
+    fn shortest_script() {
+        let cases = [
+            ("", "", 0),
+            ("same", "same", 0),
+            ("", "new", 3),
+            ("old", "", 3),
+            ("abcabba", "cbabac", 5),
+            ("the cat sat", "the dog sat", 6),
+            ("héllo wörld", "hello world", 6),
+            ("abc", "xyz", 6),
+        ];
+
+        for (old, new, edited) in cases {
+            assert_eq!(
+                apply(old, new),
+                (String::from(new), edited),
+                "{old:?} -> {new:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn unrelated_rewrite_is_replaced() {
+        let old: String = (0..100_000).map(|i| ['a', 'b'][i * 7 % 3 % 2]).collect();
+        let new: String = (0..100_000).map(|i| ['b', 'c'][i * 5 % 3 % 2]).collect();
+        assert_eq!(apply(&old, &new).0, new);
+    }
+
+    #[test]
+    fn operations_follow_each_other() {
+        let operations = diff("abcdef", "abXdefY", 4);
+        let kinds: Vec<_> = operations
+            .iter()
+            .map(|operation| (operation.kind(), operation.position(), operation.revision()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [("delete", 2, 4), ("insert", 2, 4), ("insert", 6, 4)]
+        );
+    }
+
+    /// Length of the longest common subsequence
+    fn common(old: &[u8], new: &[u8]) -> usize {
+        let mut row = vec![0; new.len() + 1];
+        for a in old {
+            let mut diagonal = 0;
+            for (j, b) in new.iter().enumerate() {
+                let up = row[j + 1];
+                row[j + 1] = match a == b {
+                    true => diagonal + 1,
+                    false => up.max(row[j]),
+                };
+                diagonal = up;
+            }
+        }
+        row[new.len()]
+    }
+
+    proptest! {
+        #[test]
+        fn diff_turns_old_into_new(old in "[abcé]{0,40}", new in "[abcé]{0,40}") {
+            let (text, _) = apply(&old, &new);
+            prop_assert_eq!(text, new);
+        }
+
+        #[test]
+        fn diff_is_shortest(old in "[abc]{0,30}", new in "[abc]{0,30}") {
+            let (_, edited) = apply(&old, &new);
+            let shortest = old.len() + new.len() - 2 * common(old.as_bytes(), new.as_bytes());
+            prop_assert_eq!(edited, shortest);
+        }
+    }
+}
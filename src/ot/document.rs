@@ -1,48 +1,288 @@
 use core::fmt::Debug;
 use std::sync::Arc;
 
-use super::operations::{ArcOperation, Operation};
+use serde::{Deserialize, Serialize};
 
-pub trait DocumentTrait: Debug {
-    fn apply(&mut self, operation: Operation) -> ArcOperation;
+use super::{
+    diff::diff,
+    json::JsonDocument,
+    operations::{ArcOperation, Operation, TransactionOperation},
+    rich_text::RichText,
+    sequence::ListDocument,
+};
+
+pub trait DocumentTrait: Debug + Send {
+    fn kind(&self) -> DocumentKind;
+
+    /// Transforms the operation relative to the operations it doesn't know and applies it.
+    ///
+    /// Returns `None` if the revision of the operation is in the future
+    /// or the transformed operation doesn't fit the text.
+    fn apply(&mut self, operation: Operation) -> Option<ArcOperation>;
     fn revision(&self) -> usize;
+
+    /// Operations starting from revision `since`, empty if `since` is in the future
+    fn operations(&self, since: usize) -> &[ArcOperation];
+
+    /// Text of the document at the current revision
+    fn text(&self) -> String;
+
+    /// Text of the document after the operations before `revision`,
+    /// `None` if the revision is in the future
+    fn text_at(&self, revision: usize) -> Option<String>;
+
+    /// Operation based on the current revision which brings back the content at `revision`.
+    ///
+    /// Returns `None` if the revision is in the future or the document can't restore its content.
+    fn restore(&self, _revision: usize) -> Option<Operation> {
+        None
+    }
+
+    /// Document with the history of this one before `revision`, the operations are shared.
+    ///
+    /// Returns `None` if the revision is in the future.
+    fn fork(&self, revision: usize) -> Option<Box<dyn DocumentTrait>>;
+}
+
+/// Type of the content of a document, it is chosen when the document is created
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentKind {
+    /// Text with formatting, see [`DocumentMem`]
+    #[default]
+    Text,
+    /// JSON value, see [`JsonDocument`]
+    Json,
+    /// Sequence of JSON values, see [`ListDocument`]
+    List,
+}
+
+impl DocumentKind {
+    /// Creates an empty document of the kind
+    pub fn create(self) -> Box<dyn DocumentTrait> {
+        match self {
+            DocumentKind::Text => Box::new(DocumentMem::new()),
+            DocumentKind::Json => Box::new(JsonDocument::new()),
+            DocumentKind::List => Box::new(ListDocument::<serde_json::Value>::new()),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct DocumentMem {
     operations: Vec<ArcOperation>,
+    content: RichText,
+}
+
+impl Default for DocumentMem {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DocumentMem {
     pub fn new() -> DocumentMem {
         DocumentMem {
             operations: Vec::new(),
+            content: RichText::new(),
         }
     }
 
-    #[cfg(debug_assertions)]
-    pub fn print(&self) {
-        for i in &self.operations {
-            i.print();
-        }
-        println!("");
+    /// Text of the document with its formatting
+    pub fn content(&self) -> &RichText {
+        &self.content
     }
 }
 
 impl DocumentTrait for DocumentMem {
-    fn apply(&mut self, operation: Operation) -> ArcOperation {
-        // TODO add a check that revision is valid
+    fn kind(&self) -> DocumentKind {
+        DocumentKind::Text
+    }
+
+    fn apply(&mut self, operation: Operation) -> Option<ArcOperation> {
         let mut op = operation;
-        for i in &self.operations[op.revision()..] {
-            op.transform_relative_to(Box::as_ref(&i));
+        for i in self.operations.get(op.revision()..)? {
+            op.transform_relative_to(Box::as_ref(i));
+        }
+        if !op.can_apply(self.content.text()) {
+            return None;
         }
+        self.content.apply(op.as_ref());
         op.set_revision(self.operations.len());
         let op = Arc::new(op);
         self.operations.push(op);
-        Arc::clone(self.operations.last().unwrap())
+        Some(Arc::clone(self.operations.last().unwrap()))
     }
 
     fn revision(&self) -> usize {
         self.operations.len()
     }
+
+    fn operations(&self, since: usize) -> &[ArcOperation] {
+        self.operations.get(since..).unwrap_or(&[])
+    }
+
+    fn text(&self) -> String {
+        self.content.text().to_string()
+    }
+
+    fn fork(&self, revision: usize) -> Option<Box<dyn DocumentTrait>> {
+        let operations = self.operations.get(..revision)?.to_vec();
+        let mut content = RichText::new();
+        for operation in &operations {
+            content.apply(Box::as_ref(operation));
+        }
+        Some(Box::new(DocumentMem {
+            operations,
+            content,
+        }))
+    }
+
+    fn text_at(&self, revision: usize) -> Option<String> {
+        let mut text = String::new();
+        for operation in self.operations.get(..revision)? {
+            operation.apply(&mut text);
+        }
+        Some(text)
+    }
+
+    /// The difference of the texts as one transaction, the formatting is not restored
+    fn restore(&self, revision: usize) -> Option<Operation> {
+        let text = self.text_at(revision)?;
+        let operations = diff(self.content.text(), &text, self.revision());
+        Some(Box::new(TransactionOperation::new(
+            self.revision(),
+            operations,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{DocumentMem, DocumentTrait};
+    use crate::ot::{
+        operations::{DeleteOperation, InsertOperation, ReplaceOperation, TransactionOperation},
+        rich_text::{Attributes, FormatOperation},
+    };
+
+    #[test]
+    fn invalid_operations_are_rejected() {
+        let mut document = DocumentMem::new();
+        assert!(document
+            .apply(Box::new(InsertOperation::new(0, 0, String::from("abc"))))
+            .is_some());
+
+        // the revision is in the future
+        assert!(document
+            .apply(Box::new(InsertOperation::new(0, 2, String::from("x"))))
+            .is_none());
+        // the range is out of the text
+        assert!(document
+            .apply(Box::new(DeleteOperation::new(1, 1, 5)))
+            .is_none());
+        assert!(document
+            .apply(Box::new(InsertOperation::new(4, 1, String::from("x"))))
+            .is_none());
+
+        assert_eq!(document.revision(), 1);
+        assert_eq!(document.text(), "abc");
+    }
+
+    #[test]
+    fn concurrent_format_is_transformed() {
+        let mut document = DocumentMem::new();
+        document.apply(Box::new(InsertOperation::new(0, 0, String::from("hello"))));
+        let bold = Attributes::from([(String::from("bold"), serde_json::Value::Bool(true))]);
+        document.apply(Box::new(InsertOperation::new(0, 1, String::from(">"))));
+        // formats "ell" without knowing about the insertion
+        document.apply(Box::new(FormatOperation::new(1, 1, 3, bold.clone())));
+
+        assert_eq!(document.text(), ">hello");
+        assert_eq!(
+            document.content().runs(),
+            [(2, Attributes::new()), (3, bold), (1, Attributes::new())]
+        );
+    }
+
+    #[test]
+    fn transaction_takes_one_revision() {
+        let mut document = DocumentMem::new();
+        document.apply(Box::new(InsertOperation::new(
+            0,
+            0,
+            String::from("a cat, a cat"),
+        )));
+        document.apply(Box::new(InsertOperation::new(0, 1, String::from(">"))));
+        // replaces both words without knowing about the insertion
+        let transaction = TransactionOperation::new(
+            1,
+            vec![
+                Box::new(ReplaceOperation::new(2, 1, 3, String::from("dog"))),
+                Box::new(ReplaceOperation::new(9, 1, 3, String::from("dog"))),
+            ],
+        );
+        let applied = document.apply(Box::new(transaction)).unwrap();
+
+        assert_eq!(applied.revision(), 2);
+        assert_eq!(document.revision(), 3);
+        assert_eq!(document.text(), ">a dog, a dog");
+
+        // nothing is applied if a part doesn't fit the text
+        let transaction = TransactionOperation::new(
+            3,
+            vec![
+                Box::new(DeleteOperation::new(0, 3, 1)),
+                Box::new(DeleteOperation::new(12, 3, 1)),
+            ],
+        );
+        assert!(document.apply(Box::new(transaction)).is_none());
+        assert_eq!(document.text(), ">a dog, a dog");
+    }
+
+    #[test]
+    fn restore_keeps_concurrent_edits() {
+        let mut document = DocumentMem::new();
+        document.apply(Box::new(InsertOperation::new(
+            0,
+            0,
+            String::from("first draft"),
+        )));
+        document.apply(Box::new(ReplaceOperation::new(
+            0,
+            1,
+            5,
+            String::from("second"),
+        )));
+        assert_eq!(document.text_at(1).as_deref(), Some("first draft"));
+        assert!(document.text_at(3).is_none());
+
+        let restore = document.restore(1).unwrap();
+        // a client appends without knowing about the restore
+        document.apply(Box::new(InsertOperation::new(12, 2, String::from("!"))));
+        let restored = document.apply(restore).unwrap();
+
+        assert_eq!(restored.revision(), 3);
+        assert_eq!(document.text(), "first draft!");
+        assert_eq!(document.text_at(2).as_deref(), Some("second draft"));
+    }
+
+    #[test]
+    fn fork_shares_history() {
+        let mut document = DocumentMem::new();
+        document.apply(Box::new(InsertOperation::new(0, 0, String::from("hello"))));
+        document.apply(Box::new(InsertOperation::new(5, 1, String::from(" world"))));
+
+        let mut branch = document.fork(1).unwrap();
+        assert!(document.fork(3).is_none());
+        assert_eq!(branch.text(), "hello");
+        assert!(Arc::ptr_eq(
+            &branch.operations(0)[0],
+            &document.operations(0)[0]
+        ));
+        branch.apply(Box::new(InsertOperation::new(0, 1, String::from(">"))));
+        assert_eq!(branch.text(), ">hello");
+        assert_eq!(document.text(), "hello world");
+    }
 }
@@ -0,0 +1,819 @@
+//! Structured documents: configs, forms and other JSON values edited together.
+//!
+//! A [`JsonOperation`] changes the value at a path: it sets or removes a key of an object,
+//! inserts, deletes or moves an element of a list, adds to a number or edits a string.
+//! The operations are transformed against each other like the text operations,
+//! and are applied by [`JsonDocument`] instead of a text.
+
+use std::{any::Any, ops::Range};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+use super::{
+    document::{DocumentKind, DocumentTrait},
+    operations::{ArcOperation, DeleteOperation, InsertOperation, Operation, OperationTrait},
+};
+
+type Revision = usize;
+
+/// Step of a path: a key of an object or an index of a list
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Edit of a string inside the document, byte offsets like in the text operations
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TextEdit {
+    Insert {
+        position: usize,
+        text: String,
+    },
+    /// A concurrent insertion into the deleted range splits it into `ranges`,
+    /// then the position and the length are the start and the length of all of them
+    Delete {
+        position: usize,
+        length: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ranges: Option<Vec<Range<usize>>>,
+    },
+}
+
+impl TextEdit {
+    fn operation(&self) -> Operation {
+        match self {
+            TextEdit::Insert { position, text } => {
+                Box::new(InsertOperation::new(*position, 0, text.clone()))
+            }
+            TextEdit::Delete {
+                ranges: Some(ranges),
+                ..
+            } => Box::new(DeleteOperation::with_ranges(0, ranges.clone())),
+            TextEdit::Delete {
+                position, length, ..
+            } => Box::new(DeleteOperation::new(*position, 0, *length)),
+        }
+    }
+
+    /// Transforms the edit with the rules of the text operations
+    fn transform(&mut self, other: &TextEdit, wins_ties: bool) {
+        let mut operation = self.operation();
+        match wins_ties {
+            true => operation.transform_relative_to_later(other.operation().as_ref()),
+            false => operation.transform_relative_to(other.operation().as_ref()),
+        }
+        *self = if let Some(insert) = operation.downcast::<InsertOperation>() {
+            TextEdit::Insert {
+                position: insert.position(),
+                text: insert.text().clone(),
+            }
+        } else if let Some(delete) = operation.downcast::<DeleteOperation>() {
+            TextEdit::Delete {
+                position: delete.position(),
+                length: delete.len(),
+                ranges: (delete.ranges().len() > 1).then(|| delete.ranges().to_vec()),
+            }
+        } else {
+            unreachable!();
+        };
+    }
+}
+
+/// What an operation does with the value at its path
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum JsonAction {
+    /// Sets the key of an object, replaces the element of a list or the whole document
+    Set { value: Value },
+    /// Removes the key of an object
+    Remove,
+    /// Inserts the value into a list in front of the element with the index
+    Insert { value: Value },
+    /// Deletes the element of a list
+    Delete,
+    /// Moves the element of a list in front of the element which is at index `to`
+    /// before the move, `to` equal to the length of the list moves it to the end
+    Move { to: usize },
+    /// Adds the amount to a number
+    Add { amount: Number },
+    /// Edits a string
+    Text { edit: TextEdit },
+    /// Does nothing, a concurrent operation has removed the target or overwritten it
+    Noop,
+}
+
+/// Change of the value at `path`.
+///
+/// The last segment of the path is the target of the action: the key which is set or removed,
+/// the index in front of which a value is inserted, the element which is deleted or moved.
+#[derive(Debug, Clone)]
+pub struct JsonOperation {
+    path: Vec<PathSegment>,
+    revision: Revision,
+    action: JsonAction,
+}
+
+impl JsonOperation {
+    pub fn new(path: Vec<PathSegment>, revision: Revision, action: JsonAction) -> Self {
+        Self {
+            path,
+            revision,
+            action,
+        }
+    }
+
+    pub fn path(&self) -> &[PathSegment] {
+        &self.path
+    }
+
+    pub fn action(&self) -> &JsonAction {
+        &self.action
+    }
+
+    /// Checks that the target of the operation exists and has the right type,
+    /// [`JsonOperation::apply_to`] panics otherwise
+    pub fn can_apply_to(&self, document: &Value) -> bool {
+        match &self.action {
+            JsonAction::Noop => return true,
+            JsonAction::Add { amount } => {
+                return matches!(
+                    resolve(document, &self.path),
+                    Some(Value::Number(number)) if add(number, amount).is_some()
+                );
+            }
+            JsonAction::Text { edit } => {
+                return matches!(
+                    resolve(document, &self.path),
+                    Some(Value::String(text)) if edit.operation().can_apply(text)
+                );
+            }
+            _ => {}
+        }
+        let Some((last, parent)) = self.path.split_last() else {
+            return matches!(self.action, JsonAction::Set { .. });
+        };
+        match (&self.action, last, resolve(document, parent)) {
+            (JsonAction::Set { .. }, PathSegment::Key(_), Some(Value::Object(_))) => true,
+            (JsonAction::Remove, PathSegment::Key(key), Some(Value::Object(object))) => {
+                object.contains_key(key)
+            }
+            (JsonAction::Insert { .. }, PathSegment::Index(index), Some(Value::Array(list))) => {
+                *index <= list.len()
+            }
+            (
+                JsonAction::Set { .. } | JsonAction::Delete,
+                PathSegment::Index(index),
+                Some(Value::Array(list)),
+            ) => *index < list.len(),
+            (JsonAction::Move { to }, PathSegment::Index(index), Some(Value::Array(list))) => {
+                *index < list.len() && *to <= list.len()
+            }
+            _ => false,
+        }
+    }
+
+    /// Applies the operation to the document, it must fit the document,
+    /// see [`JsonOperation::can_apply_to`]
+    pub fn apply_to(&self, document: &mut Value) {
+        match &self.action {
+            JsonAction::Noop => return,
+            JsonAction::Add { amount } => {
+                let Some(Value::Number(number)) = resolve_mut(document, &self.path) else {
+                    unreachable!();
+                };
+                *number = add(number, amount).unwrap();
+                return;
+            }
+            JsonAction::Text { edit } => {
+                let Some(Value::String(text)) = resolve_mut(document, &self.path) else {
+                    unreachable!();
+                };
+                edit.operation().apply(text);
+                return;
+            }
+            _ => {}
+        }
+        let Some((last, parent)) = self.path.split_last() else {
+            if let JsonAction::Set { value } = &self.action {
+                *document = value.clone();
+            }
+            return;
+        };
+        let parent = resolve_mut(document, parent).unwrap();
+        match (&self.action, last) {
+            (JsonAction::Set { value }, PathSegment::Key(key)) => {
+                parent
+                    .as_object_mut()
+                    .unwrap()
+                    .insert(key.clone(), value.clone());
+            }
+            (JsonAction::Set { value }, PathSegment::Index(index)) => {
+                parent.as_array_mut().unwrap()[*index] = value.clone();
+            }
+            (JsonAction::Remove, PathSegment::Key(key)) => {
+                parent.as_object_mut().unwrap().remove(key);
+            }
+            (JsonAction::Insert { value }, PathSegment::Index(index)) => {
+                parent.as_array_mut().unwrap().insert(*index, value.clone());
+            }
+            (JsonAction::Delete, PathSegment::Index(index)) => {
+                parent.as_array_mut().unwrap().remove(*index);
+            }
+            (JsonAction::Move { to }, PathSegment::Index(index)) => {
+                let list = parent.as_array_mut().unwrap();
+                let value = list.remove(*index);
+                list.insert(to - usize::from(to > index), value);
+            }
+            _ => {}
+        }
+    }
+
+    /// `wins_ties` is true if `other` is ordered after this operation,
+    /// then `other` wins when both operations overwrite the same value
+    fn transform(&mut self, other: &JsonOperation, wins_ties: bool) {
+        if self.moves_nothing() {
+            self.action = JsonAction::Noop;
+        }
+        if self.action == JsonAction::Noop || other.moves_nothing() {
+            return;
+        }
+        match &other.action {
+            JsonAction::Set { .. } | JsonAction::Remove => {
+                if !self.path.starts_with(&other.path) {
+                    return;
+                }
+                let overwritten = match &self.action {
+                    _ if self.path.len() > other.path.len() => true,
+                    // the key is already removed
+                    JsonAction::Remove if other.action == JsonAction::Remove => true,
+                    // the later of two writes wins
+                    JsonAction::Set { .. } | JsonAction::Remove => wins_ties,
+                    JsonAction::Add { .. } | JsonAction::Text { .. } => true,
+                    // the list operations target the slot or the element, not the new value
+                    _ => false,
+                };
+                if overwritten {
+                    self.action = JsonAction::Noop;
+                }
+            }
+            JsonAction::Insert { .. } => {
+                let Some((depth, PathSegment::Index(index))) = self.list_index(other) else {
+                    return;
+                };
+                let inserted = other.index();
+                let slot = |slot: usize| match slot > inserted || (slot == inserted && !wins_ties) {
+                    true => slot + 1,
+                    false => slot,
+                };
+                let element = |element: usize| match element >= inserted {
+                    true => element + 1,
+                    false => element,
+                };
+                let target = depth + 1 == self.path.len();
+                match &mut self.action {
+                    JsonAction::Insert { .. } if target => {
+                        self.path[depth] = PathSegment::Index(slot(index))
+                    }
+                    JsonAction::Move { to } if target => {
+                        *to = slot(*to);
+                        self.path[depth] = PathSegment::Index(element(index));
+                    }
+                    _ => self.path[depth] = PathSegment::Index(element(index)),
+                }
+            }
+            JsonAction::Delete => {
+                let Some((depth, PathSegment::Index(index))) = self.list_index(other) else {
+                    return;
+                };
+                let deleted = other.index();
+                let shift = |index: usize| match index > deleted {
+                    true => index - 1,
+                    false => index,
+                };
+                let target = depth + 1 == self.path.len();
+                match &mut self.action {
+                    JsonAction::Insert { .. } if target => {
+                        self.path[depth] = PathSegment::Index(shift(index))
+                    }
+                    _ if index == deleted => self.action = JsonAction::Noop,
+                    JsonAction::Move { to } if target => {
+                        *to = shift(*to);
+                        self.path[depth] = PathSegment::Index(shift(index));
+                    }
+                    _ => self.path[depth] = PathSegment::Index(shift(index)),
+                }
+            }
+            JsonAction::Move { to: moved_to } => {
+                let Some((depth, PathSegment::Index(index))) = self.list_index(other) else {
+                    return;
+                };
+                let moved = other.index();
+                // the slot of the moved element in the list without it
+                let moved_to = moved_to - usize::from(*moved_to > moved);
+                let slot = |slot: usize| {
+                    let slot = slot - usize::from(slot > moved);
+                    match slot > moved_to || (slot == moved_to && !wins_ties) {
+                        true => slot + 1,
+                        false => slot,
+                    }
+                };
+                let element = |element: usize| {
+                    if element == moved {
+                        return moved_to;
+                    }
+                    let element = element - usize::from(element > moved);
+                    element + usize::from(element >= moved_to)
+                };
+                let target = depth + 1 == self.path.len();
+                match &mut self.action {
+                    JsonAction::Insert { .. } if target => {
+                        self.path[depth] = PathSegment::Index(slot(index))
+                    }
+                    // the later of two moves of the element wins
+                    JsonAction::Move { .. } if target && index == moved && wins_ties => {
+                        self.action = JsonAction::Noop
+                    }
+                    JsonAction::Move { to } if target => {
+                        *to = slot(*to);
+                        self.path[depth] = PathSegment::Index(element(index));
+                    }
+                    _ => self.path[depth] = PathSegment::Index(element(index)),
+                }
+            }
+            JsonAction::Text { edit } => {
+                if let JsonAction::Text { edit: own } = &mut self.action {
+                    if self.path == other.path {
+                        own.transform(edit, wins_ties);
+                    }
+                }
+            }
+            JsonAction::Add { .. } | JsonAction::Noop => {}
+        }
+    }
+
+    /// Finds the segment of the path of this operation which indexes the list changed by `other`
+    fn list_index(&self, other: &JsonOperation) -> Option<(usize, PathSegment)> {
+        let depth = other.path.len().checked_sub(1)?;
+        match self.path.get(depth) {
+            Some(segment) if self.path[..depth] == other.path[..depth] => {
+                Some((depth, segment.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the operation moves an element to its own place
+    fn moves_nothing(&self) -> bool {
+        match (&self.action, self.path.last()) {
+            (JsonAction::Move { to }, Some(PathSegment::Index(index))) => {
+                to == index || *to == index + 1
+            }
+            _ => false,
+        }
+    }
+
+    /// Index of the element changed by a list operation
+    fn index(&self) -> usize {
+        match self.path.last() {
+            Some(PathSegment::Index(index)) => *index,
+            _ => unreachable!("list operations end with an index"),
+        }
+    }
+}
+
+impl OperationTrait for JsonOperation {
+    /// The JSON operations don't change texts, see [`JsonDocument`]
+    fn apply(&self, _text: &mut String) {}
+
+    fn apply_return(&self, text: &mut String) -> String {
+        text.clone()
+    }
+
+    /// The JSON operations only fit JSON documents
+    fn can_apply(&self, _text: &str) -> bool {
+        false
+    }
+
+    fn transform_relative_to(&mut self, operation: &dyn OperationTrait) {
+        if let Some(other) = operation.downcast::<JsonOperation>() {
+            self.transform(other, false);
+        }
+    }
+
+    fn transform_relative_to_later(&mut self, operation: &dyn OperationTrait) {
+        if let Some(other) = operation.downcast::<JsonOperation>() {
+            self.transform(other, true);
+        }
+    }
+
+    fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    fn set_revision(&mut self, revision: Revision) {
+        self.revision = revision
+    }
+
+    /// The JSON operations have a path instead of a position
+    fn position(&self) -> usize {
+        0
+    }
+
+    fn kind(&self) -> &'static str {
+        "json"
+    }
+
+    fn size(&self) -> usize {
+        match &self.action {
+            JsonAction::Set { value } | JsonAction::Insert { value } => value.to_string().len(),
+            JsonAction::Text {
+                edit: TextEdit::Insert { text, .. },
+            } => text.len(),
+            JsonAction::Text {
+                edit: TextEdit::Delete { length, .. },
+            } => *length,
+            _ => 0,
+        }
+    }
+
+    fn boxed_clone(&self) -> Operation {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Document which holds a JSON value, it starts as an empty object
+#[derive(Debug)]
+pub struct JsonDocument {
+    operations: Vec<ArcOperation>,
+    value: Value,
+}
+
+impl Default for JsonDocument {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JsonDocument {
+    pub fn new() -> JsonDocument {
+        JsonDocument {
+            operations: Vec::new(),
+            value: Value::Object(Default::default()),
+        }
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    /// Value after the operations before `revision`, `None` if the revision is in the future
+    pub fn value_at(&self, revision: usize) -> Option<Value> {
+        let mut value = Value::Object(Default::default());
+        for operation in self.operations.get(..revision)? {
+            if let Some(json) = operation.downcast::<JsonOperation>() {
+                json.apply_to(&mut value);
+            }
+        }
+        Some(value)
+    }
+}
+
+impl DocumentTrait for JsonDocument {
+    fn kind(&self) -> DocumentKind {
+        DocumentKind::Json
+    }
+
+    fn apply(&mut self, mut op: Operation) -> Option<ArcOperation> {
+        for i in self.operations.get(op.revision()..)? {
+            op.transform_relative_to(Box::as_ref(i));
+        }
+        let json = op.downcast::<JsonOperation>()?;
+        if !json.can_apply_to(&self.value) {
+            return None;
+        }
+        json.apply_to(&mut self.value);
+        op.set_revision(self.operations.len());
+        let op = std::sync::Arc::new(op);
+        self.operations.push(std::sync::Arc::clone(&op));
+        Some(op)
+    }
+
+    fn revision(&self) -> usize {
+        self.operations.len()
+    }
+
+    fn operations(&self, since: usize) -> &[ArcOperation] {
+        self.operations.get(since..).unwrap_or_default()
+    }
+
+    /// The value serialized to JSON
+    fn text(&self) -> String {
+        self.value.to_string()
+    }
+
+    fn text_at(&self, revision: usize) -> Option<String> {
+        Some(self.value_at(revision)?.to_string())
+    }
+
+    fn fork(&self, revision: usize) -> Option<Box<dyn DocumentTrait>> {
+        Some(Box::new(JsonDocument {
+            value: self.value_at(revision)?,
+            operations: self.operations[..revision].to_vec(),
+        }))
+    }
+
+    /// Sets the whole document to the value at the revision
+    fn restore(&self, revision: usize) -> Option<Operation> {
+        let value = self.value_at(revision)?;
+        let action = JsonAction::Set { value };
+        Some(Box::new(JsonOperation::new(
+            vec![],
+            self.revision(),
+            action,
+        )))
+    }
+}
+
+fn resolve<'a>(value: &'a Value, path: &[PathSegment]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, segment| match segment {
+        PathSegment::Key(key) => value.as_object()?.get(key),
+        PathSegment::Index(index) => value.as_array()?.get(*index),
+    })
+}
+
+fn resolve_mut<'a>(value: &'a mut Value, path: &[PathSegment]) -> Option<&'a mut Value> {
+    path.iter().try_fold(value, |value, segment| match segment {
+        PathSegment::Key(key) => value.as_object_mut()?.get_mut(key),
+        PathSegment::Index(index) => value.as_array_mut()?.get_mut(*index),
+    })
+}
+
+/// Sum of the numbers, integers stay integers while they don't overflow
+fn add(number: &Number, amount: &Number) -> Option<Number> {
+    if let (Some(number), Some(amount)) = (number.as_i64(), amount.as_i64()) {
+        if let Some(sum) = number.checked_add(amount) {
+            return Some(sum.into());
+        }
+    }
+    Number::from_f64(number.as_f64()? + amount.as_f64()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use serde_json::{json, Value};
+
+    use super::{JsonAction, JsonDocument, JsonOperation, PathSegment, TextEdit};
+    use crate::ot::{
+        document::DocumentTrait,
+        operations::{InsertOperation, OperationTrait},
+    };
+
+    fn path(value: Value) -> Vec<PathSegment> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn operation(path_value: Value, action: Value) -> JsonOperation {
+        JsonOperation::new(path(path_value), 0, serde_json::from_value(action).unwrap())
+    }
+
+    #[test]
+    fn operations_are_applied_at_paths() {
+        let mut document = json!({"form": {"title": "Hello", "fields": ["a", "b", "c"]}, "n": 1});
+        let operations = [
+            operation(
+                json!(["form", "title"]),
+                json!({"type": "text", "edit": {"kind": "insert", "position": 5, "text": "!"}}),
+            ),
+            operation(
+                json!(["form", "fields", 1]),
+                json!({"type": "insert", "value": "x"}),
+            ),
+            operation(
+                json!(["form", "fields", 0]),
+                json!({"type": "move", "to": 4}),
+            ),
+            operation(json!(["form", "fields", 1]), json!({"type": "delete"})),
+            operation(json!(["n"]), json!({"type": "add", "amount": 2})),
+            operation(
+                json!(["form", "done"]),
+                json!({"type": "set", "value": true}),
+            ),
+            operation(json!(["form", "title"]), json!({"type": "remove"})),
+        ];
+        for operation in &operations {
+            assert!(operation.can_apply_to(&document), "{operation:?}");
+            operation.apply_to(&mut document);
+        }
+
+        assert_eq!(
+            document,
+            json!({"form": {"fields": ["x", "c", "a"], "done": true}, "n": 3})
+        );
+    }
+
+    #[test]
+    fn invalid_operations_are_rejected() {
+        let document = json!({"list": [1], "text": "abc"});
+
+        assert!(
+            !operation(json!(["list", 2]), json!({"type": "insert", "value": 0}))
+                .can_apply_to(&document)
+        );
+        assert!(!operation(
+            json!(["list", 0, "key"]),
+            json!({"type": "set", "value": 0})
+        )
+        .can_apply_to(&document));
+        assert!(
+            !operation(json!(["text"]), json!({"type": "add", "amount": 1}))
+                .can_apply_to(&document)
+        );
+        assert!(!operation(json!(["missing"]), json!({"type": "remove"})).can_apply_to(&document));
+        assert!(
+            !operation(json!(["list", 0]), json!({"type": "move", "to": 2}))
+                .can_apply_to(&document)
+        );
+    }
+
+    #[test]
+    fn paths_follow_list_changes() {
+        // the edit of the third element follows it when an element is inserted in front of it
+        let mut edit = operation(json!(["rows", 2, "n"]), json!({"type": "add", "amount": 1}));
+        edit.transform_relative_to(&operation(
+            json!(["rows", 0]),
+            json!({"type": "insert", "value": {}}),
+        ));
+        assert_eq!(edit.path(), path(json!(["rows", 3, "n"])));
+
+        // and is dropped when the element is deleted
+        edit.transform_relative_to(&operation(json!(["rows", 3]), json!({"type": "delete"})));
+        assert_eq!(*edit.action(), JsonAction::Noop);
+
+        let mut edit = operation(json!(["rows", 2, "n"]), json!({"type": "add", "amount": 1}));
+        edit.transform_relative_to(&operation(
+            json!(["rows", 2]),
+            json!({"type": "move", "to": 0}),
+        ));
+        assert_eq!(edit.path(), path(json!(["rows", 0, "n"])));
+    }
+
+    #[test]
+    fn later_write_wins() {
+        let first = operation(json!(["title"]), json!({"type": "set", "value": "a"}));
+        let second = operation(json!(["title"]), json!({"type": "remove"}));
+
+        let mut first_after_second = first.clone();
+        first_after_second.transform_relative_to_later(&second);
+        assert_eq!(*first_after_second.action(), JsonAction::Noop);
+        let mut second_after_first = second.clone();
+        second_after_first.transform_relative_to(&first);
+        assert_eq!(*second_after_first.action(), JsonAction::Remove);
+
+        // a replaced value isn't edited
+        let mut edit = operation(
+            json!(["title"]),
+            json!({"type": "text", "edit": {"kind": "delete", "position": 0, "length": 1}}),
+        );
+        edit.transform_relative_to(&first);
+        assert_eq!(*edit.action(), JsonAction::Noop);
+    }
+
+    #[test]
+    fn concurrent_text_edits_are_transformed() {
+        let mut edit = operation(
+            json!(["s"]),
+            json!({"type": "text", "edit": {"kind": "insert", "position": 3, "text": "x"}}),
+        );
+        edit.transform_relative_to(&operation(
+            json!(["s"]),
+            json!({"type": "text", "edit": {"kind": "delete", "position": 0, "length": 2}}),
+        ));
+        assert_eq!(
+            *edit.action(),
+            JsonAction::Text {
+                edit: TextEdit::Insert {
+                    position: 1,
+                    text: String::from("x")
+                }
+            }
+        );
+
+        // the deletion is split around the text inserted into it
+        let mut edit = operation(
+            json!(["s"]),
+            json!({"type": "text", "edit": {"kind": "delete", "position": 1, "length": 3}}),
+        );
+        edit.transform_relative_to(&operation(
+            json!(["s"]),
+            json!({"type": "text", "edit": {"kind": "insert", "position": 2, "text": "xy"}}),
+        ));
+        assert_eq!(
+            serde_json::to_value(edit.action()).unwrap(),
+            json!({"type": "text", "edit": {"kind": "delete", "position": 1, "length": 3,
+                "ranges": [{"start": 1, "end": 2}, {"start": 4, "end": 6}]}})
+        );
+    }
+
+    #[test]
+    fn document_takes_only_json_operations() {
+        let mut document = JsonDocument::new();
+        assert!(document
+            .apply(Box::new(InsertOperation::new(0, 0, String::from("a"))))
+            .is_none());
+        assert!(document
+            .apply(Box::new(operation(
+                json!(["a"]),
+                json!({"type": "set", "value": [1]})
+            )))
+            .is_some());
+        // the insertion is based on the empty document and follows the concurrent one
+        let insert = JsonOperation::new(
+            path(json!(["a", 0])),
+            1,
+            JsonAction::Insert { value: json!(2) },
+        );
+        assert!(document.apply(Box::new(insert.clone())).is_some());
+        assert!(document.apply(Box::new(insert)).is_some());
+
+        assert_eq!(document.value(), &json!({"a": [2, 2, 1]}));
+        assert_eq!(document.text(), r#"{"a":[2,2,1]}"#);
+    }
+
+    #[test]
+    fn old_value_is_restored() {
+        let mut document = JsonDocument::new();
+        let set = |value| operation(json!(["a"]), json!({"type": "set", "value": value}));
+        document.apply(Box::new(set(json!(1))));
+        document.apply(Box::new(set(json!(2))));
+
+        assert_eq!(document.text_at(1).as_deref(), Some(r#"{"a":1}"#));
+        assert!(document.text_at(3).is_none());
+        let restore = document.restore(1).unwrap();
+        assert_eq!(restore.revision(), 2);
+        assert!(document.apply(restore).is_some());
+        assert_eq!(document.value(), &json!({"a": 1}));
+    }
+
+    const LIST_LEN: usize = 4;
+
+    /// `{"l": [{"v": 0}, ...], "o": {"x": 1, "s": "abc"}, "n": 5}`
+    fn document() -> Value {
+        let list: Vec<_> = (0..LIST_LEN).map(|v| json!({"v": v})).collect();
+        json!({"l": list, "o": {"x": 1, "s": "abc"}, "n": 5})
+    }
+
+    /// Operation which fits [`document`]
+    fn json_operation() -> impl Strategy<Value = JsonOperation> {
+        let element = 0..LIST_LEN;
+        let slot = 0..=LIST_LEN;
+        prop_oneof![
+            slot.clone().prop_map(|i| operation(json!(["l", i]), json!({"type": "insert", "value": {"v": 10}}))),
+            element.clone().prop_map(|i| operation(json!(["l", i]), json!({"type": "delete"}))),
+            (element.clone(), slot.clone()).prop_map(|(i, to)| operation(json!(["l", i]), json!({"type": "move", "to": to}))),
+            element.clone().prop_map(|i| operation(json!(["l", i]), json!({"type": "set", "value": {"v": 20}}))),
+            (element.clone(), 1..3).prop_map(|(i, amount)| operation(json!(["l", i, "v"]), json!({"type": "add", "amount": amount}))),
+            element.prop_map(|i| operation(json!(["l", i, "v"]), json!({"type": "set", "value": 30}))),
+            Just(operation(json!(["l"]), json!({"type": "remove"}))),
+            prop_oneof![Just("x"), Just("y")].prop_map(|key| operation(json!(["o", key]), json!({"type": "set", "value": 40}))),
+            Just(operation(json!(["o", "x"]), json!({"type": "remove"}))),
+            Just(operation(json!(["o"]), json!({"type": "set", "value": {"s": ""}}))),
+            Just(operation(json!(["n"]), json!({"type": "add", "amount": 1}))),
+            (0..=3usize, "[a-z]{1,2}").prop_map(|(position, text)| operation(json!(["o", "s"]), json!({"type": "text", "edit": {"kind": "insert", "position": position, "text": text}}))),
+            (0..3usize).prop_map(|position| operation(json!(["o", "s"]), json!({"type": "text", "edit": {"kind": "delete", "position": position, "length": 3 - position}}))),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn concurrent_operations_converge(first in json_operation(), second in json_operation()) {
+            let mut second_after_first = second.clone();
+            second_after_first.transform_relative_to(&first);
+            let mut first_after_second = first.clone();
+            first_after_second.transform_relative_to_later(&second);
+
+            let mut left = document();
+            first.apply_to(&mut left);
+            prop_assert!(second_after_first.can_apply_to(&left), "{:?}", second_after_first);
+            second_after_first.apply_to(&mut left);
+            let mut right = document();
+            second.apply_to(&mut right);
+            prop_assert!(first_after_second.can_apply_to(&right), "{:?}", first_after_second);
+            first_after_second.apply_to(&mut right);
+
+            prop_assert_eq!(left, right, "first: {:?}, second: {:?}", first, second);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Merging the operations of a branch back into its parent document.
+//!
+//! A branch starts with the history of its parent up to the fork point, after it the parent
+//! and the branch have their own operations. The operations of the branch are transformed
+//! through the later operations of the parent as if they came from a client which was
+//! offline since the fork point.
+
+use super::operations::{ArcOperation, DeleteOperation, Operation, OperationTrait};
+
+/// Text deleted both in the branch and in the parent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Revision of the deletion in the parent
+    pub revision: usize,
+    /// Revision of the deletion in the branch
+    pub branch_revision: usize,
+    /// Number of bytes deleted by both operations
+    pub length: usize,
+}
+
+/// Transforms `operations` of the branch relative to `concurrent` operations of the parent.
+///
+/// Both sequences start at the fork point and their operations follow each other.
+/// The returned operations follow each other after the operations of the parent,
+/// their revisions are left as they were in the branch.
+pub fn rebase(
+    operations: &[ArcOperation],
+    concurrent: &[ArcOperation],
+) -> (Vec<Operation>, Vec<Conflict>) {
+    let mut concurrent: Vec<Operation> = (concurrent.iter())
+        .map(|operation| operation.boxed_clone())
+        .collect();
+    let mut rebased = vec![];
+    let mut conflicts = vec![];
+    for operation in operations {
+        let mut operation = operation.boxed_clone();
+        for other in concurrent.iter_mut() {
+            let previous = operation.boxed_clone();
+            let deleted = deleted_len(operation.as_ref());
+            operation.transform_relative_to(other.as_ref());
+            // only the text deleted by the other operation is lost
+            let length = deleted - deleted_len(operation.as_ref());
+            if length > 0 {
+                conflicts.push(Conflict {
+                    revision: other.revision(),
+                    branch_revision: operation.revision(),
+                    length,
+                });
+            }
+            other.transform_relative_to_later(previous.as_ref());
+        }
+        rebased.push(operation);
+    }
+    (rebased, conflicts)
+}
+
+/// Number of bytes deleted by the operation and its parts
+fn deleted_len(operation: &dyn OperationTrait) -> usize {
+    match operation.parts() {
+        Some(parts) => parts.into_iter().map(deleted_len).sum(),
+        None => operation
+            .downcast::<DeleteOperation>()
+            .map_or(0, DeleteOperation::len),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{rebase, Conflict};
+    use crate::ot::operations::{
+        ArcOperation, DeleteOperation, InsertOperation, Operation, ReplaceOperation,
+    };
+
+    fn history(operations: Vec<Operation>) -> Vec<ArcOperation> {
+        operations.into_iter().map(Arc::new).collect()
+    }
+
+    #[test]
+    fn branch_follows_parent() {
+        // "hello world" is forked, the branch capitalizes both words, the parent appends
+        let branch = history(vec![
+            Box::new(ReplaceOperation::new(0, 1, 1, String::from("H"))),
+            Box::new(ReplaceOperation::new(6, 2, 1, String::from("W"))),
+        ]);
+        let parent = history(vec![
+            Box::new(InsertOperation::new(0, 1, String::from("> "))),
+            Box::new(InsertOperation::new(13, 2, String::from("!"))),
+        ]);
+
+        let (rebased, conflicts) = rebase(&branch, &parent);
+        let mut text = String::from("hello world");
+        for operation in parent.iter().map(|operation| operation.as_ref()).chain(&rebased) {
+            operation.apply(&mut text);
+        }
+        assert_eq!(text, "> Hello World!");
+        assert!(conflicts.is_empty());
+        assert_eq!(rebased[1].revision(), 2);
+    }
+
+    #[test]
+    fn overlapping_deletions_are_conflicts() {
+        // "abcdefgh": the branch deletes "cdef" and then "h", the parent deletes "efg"
+        let branch = history(vec![
+            Box::new(DeleteOperation::new(2, 1, 4)),
+            Box::new(DeleteOperation::new(3, 2, 1)),
+        ]);
+        let parent = history(vec![Box::new(DeleteOperation::new(4, 1, 3))]);
+
+        let (rebased, conflicts) = rebase(&branch, &parent);
+        let mut text = String::from("abcdefgh");
+        for operation in parent.iter().map(|operation| operation.as_ref()).chain(&rebased) {
+            operation.apply(&mut text);
+        }
+        assert_eq!(text, "ab");
+        assert_eq!(
+            conflicts,
+            [Conflict {
+                revision: 1,
+                branch_revision: 1,
+                length: 2,
+            }]
+        );
+    }
+}
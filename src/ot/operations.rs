@@ -1,6 +1,6 @@
 use core::fmt::Debug;
 use derive_getters::Getters;
-use std::{any::Any, cmp::min, sync::Arc};
+use std::{any::Any, cmp::min, mem, ops::Range, sync::Arc};
 
 pub type Operation = Box<dyn OperationTrait>;
 pub type ArcOperation = Arc<Operation>;
@@ -19,9 +19,21 @@ pub trait OperationTrait: Send + Sync + Debug {
 
     fn apply_return(&self, text: &mut String) -> String;
 
+    /// Checks that the operation fits `text`: its range is inside the text
+    /// and doesn't split a character. `apply` panics otherwise.
+    fn can_apply(&self, text: &str) -> bool;
+
     /// Transforms the operation relative to transmitted `operation`
     fn transform_relative_to(&mut self, operation: &dyn OperationTrait);
 
+    /// Transforms the operation relative to the concurrent `operation`
+    /// which is ordered after this one.
+    ///
+    /// It is the mirror of `transform_relative_to`: when both operations
+    /// insert at the same position, this operation stays in front.
+    /// Clients need it to transform the operations of the server relative to their own ones.
+    fn transform_relative_to_later(&mut self, operation: &dyn OperationTrait);
+
     /// Getter of the last known revision
     fn revision(&self) -> Revision;
 
@@ -31,6 +43,22 @@ pub trait OperationTrait: Send + Sync + Debug {
     /// Position getter
     fn position(&self) -> Position;
 
+    /// Name of the kind of the operation, e.g. `"insert"`
+    fn kind(&self) -> &'static str;
+
+    /// Number of bytes the operation inserts or deletes
+    fn size(&self) -> usize;
+
+    /// Operations which this one applies one after another, `None` for a single operation.
+    ///
+    /// The other operations are transformed relative to the parts in turn.
+    fn parts(&self) -> Option<Vec<&dyn OperationTrait>> {
+        None
+    }
+
+    /// Clones the operation into a new box
+    fn boxed_clone(&self) -> Operation;
+
     /// Casts your object in &dyn Any.
     ///
     /// Needed for downcasting.
@@ -40,10 +68,6 @@ pub trait OperationTrait: Send + Sync + Debug {
     ///
     /// Needed for downcasting.
     fn as_any_mut(&mut self) -> &mut dyn Any;
-
-    /// Printing, only for debug
-    #[cfg(debug_assertions)]
-    fn print(&self);
 }
 impl dyn OperationTrait + '_ {
     /// Downcasts your object to `&T`.
@@ -84,7 +108,7 @@ impl dyn OperationTrait + '_ {
 type Position = usize;
 type Revision = usize;
 
-#[derive(Debug, Getters)]
+#[derive(Debug, Clone, Getters)]
 pub struct InsertOperation {
     #[getter(skip)]
     position: Position,
@@ -96,30 +120,462 @@ pub struct InsertOperation {
 impl InsertOperation {
     pub fn new(position: Position, revision: Revision, text: String) -> Self {
         Self {
-            position: position,
-            revision: revision,
-            text: text,
+            position,
+            revision,
+            text,
+        }
+    }
+
+    /// `wins_ties` is true if the insertion stays in front of an insertion at the same position
+    fn transform(&mut self, operation: &dyn OperationTrait, wins_ties: bool) {
+        if let Some(parts) = operation.parts() {
+            parts
+                .into_iter()
+                .for_each(|part| self.transform(part, wins_ties));
+            return;
+        }
+        if let Some(other) = operation.downcast::<InsertOperation>() {
+            if self.position > other.position || (self.position == other.position && !wins_ties) {
+                self.position += other.text.len()
+            }
+        }
+        if let Some(other) = operation.downcast::<DeleteOperation>() {
+            // the text inserted inside the deleted range stays where the range was
+            self.position -= deleted_before(&other.ranges, self.position);
+        }
+        if let Some(other) = operation.downcast::<MoveOperation>() {
+            self.position = other.shift_position(self.position, wins_ties);
         }
     }
 }
 
-#[derive(Debug, Getters)]
+/// Deletes ranges of the text.
+///
+/// A client deletes one range, the transformation splits it when a concurrent move
+/// takes a part of the range away or a concurrent insertion goes into it.
+#[derive(Debug, Clone)]
 pub struct DeleteOperation {
-    #[getter(skip)]
-    position: Position,
-    #[getter(skip)]
     revision: Revision,
-    len: usize,
+    /// Sorted ranges which don't touch each other,
+    /// a deletion of nothing keeps one empty range at its position
+    ranges: Vec<Range<Position>>,
 }
 
 impl DeleteOperation {
     pub fn new(position: Position, revision: Revision, len: usize) -> Self {
+        let range = position..position.saturating_add(len);
+        Self::with_ranges(revision, vec![range])
+    }
+
+    /// Deletes the ranges, they may overlap and go in any order
+    pub fn with_ranges(revision: Revision, ranges: Vec<Range<Position>>) -> Self {
+        Self {
+            revision,
+            ranges: normalize_ranges(ranges),
+        }
+    }
+
+    pub fn ranges(&self) -> &[Range<Position>] {
+        &self.ranges
+    }
+
+    /// Number of the deleted bytes
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|range| range.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Number of the bytes of `ranges` in front of `position`
+fn deleted_before(ranges: &[Range<Position>], position: Position) -> usize {
+    ranges
+        .iter()
+        .map(|range| range.end.min(position).saturating_sub(range.start))
+        .sum()
+}
+
+/// Sorts the ranges and merges the ones which overlap or touch.
+/// If all of them are empty, the first one is kept as the position of the deletion.
+fn normalize_ranges(ranges: Vec<Range<Position>>) -> Vec<Range<Position>> {
+    let position = ranges.first().map_or(0, |range| range.start);
+    let mut ranges: Vec<_> = ranges
+        .into_iter()
+        .filter(|range| !range.is_empty())
+        .collect();
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<Position>> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    if merged.is_empty() {
+        merged.push(position..position);
+    }
+    merged
+}
+
+/// Moves ranges of the text to another position.
+///
+/// A client moves one range, the transformation splits it when a concurrent operation
+/// moves a part of the range away. The text inserted into a moved range moves with it,
+/// the deleted parts of the range stay deleted.
+#[derive(Debug, Clone)]
+pub struct MoveOperation {
+    revision: Revision,
+    /// Moved ranges in the order they are put at `to`, they don't overlap
+    ranges: Vec<Range<Position>>,
+    /// Position before the move where the ranges are put, it isn't inside a range
+    to: Position,
+}
+
+impl MoveOperation {
+    /// Moves `len` bytes starting from `position` to `to`
+    pub fn new(position: Position, revision: Revision, len: usize, to: Position) -> Self {
+        let range = position..position.saturating_add(len);
+        Self::with_ranges(revision, vec![range], to)
+    }
+
+    pub fn with_ranges(revision: Revision, ranges: Vec<Range<Position>>, to: Position) -> Self {
+        let mut operation = Self {
+            revision,
+            ranges,
+            to,
+        };
+        operation.normalize();
+        operation
+    }
+
+    pub fn ranges(&self) -> &[Range<Position>] {
+        &self.ranges
+    }
+
+    pub fn to(&self) -> Position {
+        self.to
+    }
+
+    /// Number of the moved bytes
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|range| range.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Position of the moved text in the text without the moved ranges
+    pub(crate) fn block_start(&self) -> Position {
+        self.to - deleted_before(&self.ranges, self.to)
+    }
+
+    /// Drops the empty ranges and merges the neighbours, a move to its own place moves nothing
+    fn normalize(&mut self) {
+        let mut ranges: Vec<Range<Position>> = vec![];
+        for range in mem::take(&mut self.ranges) {
+            match ranges.last_mut() {
+                _ if range.is_empty() => {}
+                Some(last) if last.end == range.start => {
+                    // the moved text is put where the merged range is
+                    if self.to == range.start {
+                        self.to = last.start;
+                    }
+                    last.end = range.end;
+                }
+                _ => ranges.push(range),
+            }
+        }
+        if let [range] = ranges.as_slice() {
+            if range.start == self.to || range.end == self.to {
+                ranges.clear();
+            }
+        }
+        self.ranges = ranges;
+    }
+
+    /// Whether `position` is strictly inside a moved range
+    fn splits(&self, position: Position) -> bool {
+        self.ranges
+            .iter()
+            .any(|range| range.start < position && position < range.end)
+    }
+
+    /// Offset in the moved text of the byte at `position` and the end of its range,
+    /// `None` if the byte isn't moved
+    fn moved_byte(&self, position: Position) -> Option<(usize, Position)> {
+        let mut offset = 0;
+        for range in &self.ranges {
+            if range.contains(&position) {
+                return Some((offset + position - range.start, range.end));
+            }
+            offset += range.len();
+        }
+        None
+    }
+
+    /// New position of `position`, the positions inside the moved ranges move with them.
+    /// `wins_ties` keeps `position` in front of the moved text put at the same place.
+    pub(super) fn shift_position(&self, position: Position, wins_ties: bool) -> Position {
+        if self.splits(position) {
+            let (offset, _) = self.moved_byte(position).unwrap();
+            return self.block_start() + offset;
+        }
+        self.shift_outside(position, wins_ties)
+    }
+
+    /// New position of `position` as if the moved ranges didn't take it with them
+    fn shift_outside(&self, position: Position, wins_ties: bool) -> Position {
+        let block = self.block_start();
+        let rest = position - deleted_before(&self.ranges, position);
+        let after = rest > block
+            || (rest == block && (position > self.to || (position == self.to && !wins_ties)));
+        match after {
+            true => rest + self.len(),
+            false => rest,
+        }
+    }
+
+    /// Appends the new ranges of the bytes of `range` in the order of the bytes.
+    ///
+    /// The moved bytes are skipped unless `moved` is true. When the moved text is put
+    /// strictly inside `range`, the ranges of `block` are appended in its place.
+    pub(super) fn shift_range(
+        &self,
+        range: Range<Position>,
+        moved: bool,
+        block: &[Range<Position>],
+        shifted: &mut Vec<Range<Position>>,
+    ) {
+        let (start, len) = (self.block_start(), self.len());
+        let mut position = range.start;
+        while position < range.end {
+            if position == self.to && range.start < self.to {
+                shifted.extend_from_slice(block);
+            }
+            position = match self.moved_byte(position) {
+                Some((offset, end)) => {
+                    let end = end.min(range.end);
+                    if moved {
+                        shifted.push(start + offset..start + offset + end - position);
+                    }
+                    end
+                }
+                None => {
+                    // the bytes up to the next moved range or `to` stay together
+                    let end = self
+                        .ranges
+                        .iter()
+                        .map(|range| range.start)
+                        .chain([self.to])
+                        .filter(|&boundary| boundary > position)
+                        .fold(range.end, min);
+                    let rest = position - deleted_before(&self.ranges, position);
+                    let rest = rest..end - deleted_before(&self.ranges, end);
+                    match rest.start >= start {
+                        true => shifted.push(rest.start + len..rest.end + len),
+                        false => shifted.push(rest),
+                    }
+                    end
+                }
+            };
+        }
+    }
+
+    /// Ranges of the moved text after the move without the bytes of `ranges`
+    fn block_without(&self, ranges: &[Range<Position>]) -> Vec<Range<Position>> {
+        let mut ranges = ranges.to_vec();
+        ranges.sort_by_key(|range| range.start);
+        let mut block = vec![];
+        let mut start = self.block_start();
+        for range in &self.ranges {
+            let mut position = range.start;
+            for own in ranges
+                .iter()
+                .filter(|own| own.start < range.end && range.start < own.end)
+            {
+                if own.start > position {
+                    block.push(start + position - range.start..start + own.start - range.start);
+                }
+                position = position.max(own.end);
+            }
+            if position < range.end {
+                block.push(start + position - range.start..start + range.len());
+            }
+            start += range.len();
+        }
+        block
+    }
+
+    /// `wins_ties` is true if `operation` is ordered after this one, then this move
+    /// stays in front at the same position and leaves the bytes which both operations move
+    fn transform(&mut self, operation: &dyn OperationTrait, wins_ties: bool) {
+        if let Some(parts) = operation.parts() {
+            parts
+                .into_iter()
+                .for_each(|part| self.transform(part, wins_ties));
+            return;
+        }
+        let mut ranges = vec![];
+        if let Some(other) = operation.downcast::<InsertOperation>() {
+            let (position, len) = (other.position, other.text.len());
+            for range in &self.ranges {
+                ranges.push(if position <= range.start {
+                    range.start + len..range.end + len
+                } else if position < range.end {
+                    // the inserted text moves with the range
+                    range.start..range.end + len
+                } else {
+                    range.clone()
+                });
+            }
+            if self.to > position || (self.to == position && !wins_ties) {
+                self.to += len;
+            }
+        } else if let Some(other) = operation.downcast::<DeleteOperation>() {
+            let shift = |position| position - deleted_before(&other.ranges, position);
+            ranges = (self.ranges.iter())
+                .map(|range| shift(range.start)..shift(range.end))
+                .collect();
+            self.to = shift(self.to);
+        } else if let Some(other) = operation.downcast::<MoveOperation>() {
+            // the text of the other move put inside a moved range moves with it,
+            // except the bytes which this move takes itself
+            let whole = other.block_start()..other.block_start() + other.len();
+            let block = match wins_ties {
+                true => vec![whole],
+                false => other.block_without(&self.ranges),
+            };
+            // each move puts its text inside the range of the other one: the earlier move
+            // takes the text of the later one to the place of that text
+            let cycle = other.splits(self.to) && self.splits(other.to);
+            let block = match cycle && !wins_ties {
+                true => &[][..],
+                false => &block[..],
+            };
+            for range in &self.ranges {
+                other.shift_range(range.clone(), !wins_ties, block, &mut ranges);
+            }
+            self.to = match cycle && wins_ties {
+                true => other.shift_outside(self.to, wins_ties),
+                false => other.shift_position(self.to, wins_ties),
+            };
+        } else {
+            return;
+        }
+        self.ranges = ranges;
+        self.normalize();
+    }
+}
+
+/// Replaces a range of the text with another text.
+///
+/// It is a deletion followed by an insertion at the same position, the transformation
+/// may split the deleted range or take the insertion away from it like for the parts alone.
+#[derive(Debug, Clone)]
+pub struct ReplaceOperation {
+    delete: DeleteOperation,
+    /// Position of the insertion is in the text without the deleted ranges
+    insert: InsertOperation,
+}
+
+impl ReplaceOperation {
+    /// Replaces `len` bytes starting from `position` with `text`
+    pub fn new(position: Position, revision: Revision, len: usize, text: String) -> Self {
+        Self::with_parts(
+            DeleteOperation::new(position, revision, len),
+            InsertOperation::new(position, revision, text),
+        )
+    }
+
+    /// Deletion and the insertion into the text left by it
+    pub fn with_parts(delete: DeleteOperation, mut insert: InsertOperation) -> Self {
+        insert.set_revision(delete.revision());
+        Self { delete, insert }
+    }
+
+    pub fn delete(&self) -> &DeleteOperation {
+        &self.delete
+    }
+
+    pub fn insert(&self) -> &InsertOperation {
+        &self.insert
+    }
+}
+
+/// Operations applied atomically under one revision.
+///
+/// Each operation is based on the text left by the previous ones, the transaction is
+/// transformed like its operations one after another and takes one place in the history.
+#[derive(Debug)]
+pub struct TransactionOperation {
+    revision: Revision,
+    operations: Vec<Operation>,
+}
+
+impl TransactionOperation {
+    pub fn new(revision: Revision, mut operations: Vec<Operation>) -> Self {
+        for operation in &mut operations {
+            operation.set_revision(revision);
+        }
         Self {
-            position: position,
-            revision: revision,
-            len: len,
+            revision,
+            operations,
+        }
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+}
+
+impl Clone for TransactionOperation {
+    fn clone(&self) -> Self {
+        Self {
+            revision: self.revision,
+            operations: self.operations.iter().map(|op| op.boxed_clone()).collect(),
+        }
+    }
+}
+
+/// Transforms the parts applied one after another relative to `operation`,
+/// each next part is transformed relative to `operation` moved over the previous parts
+fn transform_parts<'a>(
+    parts: impl IntoIterator<Item = &'a mut (dyn OperationTrait + 'static)>,
+    operation: &dyn OperationTrait,
+    wins_ties: bool,
+) {
+    let mut operation = operation.boxed_clone();
+    for part in parts {
+        let previous = part.boxed_clone();
+        match wins_ties {
+            true => {
+                part.transform_relative_to_later(operation.as_ref());
+                operation.transform_relative_to(previous.as_ref());
+            }
+            false => {
+                part.transform_relative_to(operation.as_ref());
+                operation.transform_relative_to_later(previous.as_ref());
+            }
+        }
+    }
+}
+
+/// Checks that the parts fit the text one after another
+fn parts_can_apply<'a>(
+    parts: impl IntoIterator<Item = &'a dyn OperationTrait>,
+    text: &str,
+) -> bool {
+    let mut text = String::from(text);
+    for part in parts {
+        if !part.can_apply(&text) {
+            return false;
         }
+        part.apply(&mut text);
     }
+    true
 }
 
 /// Calculates the intersection of two segments. Accepts the ends of the segments,
@@ -141,7 +597,7 @@ impl DeleteOperation {
 /// ```ignore
 /// assert_eq!(intersection(5, 5+6-1, 0, 0+3-1), None)
 /// ```
-fn intersection(
+pub(super) fn intersection(
     first_start: usize,
     first_end: usize,
     second_start: usize,
@@ -170,7 +626,7 @@ impl OperationTrait for InsertOperation {
     fn apply(&self, text: &mut String) {
         let mut a = String::with_capacity(text.len() + self.text.len());
         a.push_str(&text[..self.position]);
-        a.push_str(text);
+        a.push_str(&self.text);
         a.push_str(&text[self.position..]);
         *text = a; // very bed, idk why
 
@@ -180,21 +636,21 @@ impl OperationTrait for InsertOperation {
     fn apply_return(&self, text: &mut String) -> String {
         let mut a = String::with_capacity(text.len() + self.text.len());
         a.push_str(&text[..self.position]);
-        a.push_str(text);
+        a.push_str(&self.text);
         a.push_str(&text[self.position..]);
         a
     }
+
+    fn can_apply(&self, text: &str) -> bool {
+        text.is_char_boundary(self.position)
+    }
+
     fn transform_relative_to(&mut self, operation: &dyn OperationTrait) {
-        if let Some(other) = operation.downcast::<InsertOperation>() {
-            if self.position >= other.position {
-                self.position += other.text.len()
-            }
-        }
-        if let Some(other) = operation.downcast::<DeleteOperation>() {
-            if self.position > other.position {
-                self.position -= min(other.len, self.position - other.position)
-            }
-        }
+        self.transform(operation, false)
+    }
+
+    fn transform_relative_to_later(&mut self, operation: &dyn OperationTrait) {
+        self.transform(operation, true)
     }
 
     fn revision(&self) -> Revision {
@@ -209,6 +665,18 @@ impl OperationTrait for InsertOperation {
         self.position
     }
 
+    fn kind(&self) -> &'static str {
+        "insert"
+    }
+
+    fn size(&self) -> usize {
+        self.text.len()
+    }
+
+    fn boxed_clone(&self) -> Operation {
+        Box::new(self.clone())
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -216,56 +684,153 @@ impl OperationTrait for InsertOperation {
     fn as_any_mut(&mut self) -> &mut dyn Any {
         self
     }
-
-    #[cfg(debug_assertions)]
-    fn print(&self) {
-        print!("{:?}, ", self);
-    }
 }
 
 impl OperationTrait for DeleteOperation {
     fn apply(&self, text: &mut String) {
-        let mut tmp = String::with_capacity(text.len() - self.len);
-        tmp.push_str(&text[..self.position]);
-        tmp.push_str(&text[self.position + self.len..]);
-        *text = tmp; // TODO  Need to find a faster way to work with strings !!!
+        *text = self.apply_return(text); // TODO  Need to find a faster way to work with strings !!!
     }
 
     fn apply_return(&self, text: &mut String) -> String {
-        let mut tmp = String::with_capacity(text.len() - self.len);
-        tmp.push_str(&text[..self.position]);
-        tmp.push_str(&text[self.position + self.len..]);
+        let mut tmp = String::with_capacity(text.len().saturating_sub(self.len()));
+        let mut start = 0;
+        for range in &self.ranges {
+            tmp.push_str(&text[start..range.start]);
+            start = range.end;
+        }
+        tmp.push_str(&text[start..]);
 
         tmp
     }
 
+    fn can_apply(&self, text: &str) -> bool {
+        self.ranges
+            .iter()
+            .all(|range| text.is_char_boundary(range.start) && text.is_char_boundary(range.end))
+    }
+
     fn transform_relative_to(&mut self, operation: &dyn OperationTrait) {
+        if let Some(parts) = operation.parts() {
+            parts
+                .into_iter()
+                .for_each(|part| self.transform_relative_to(part));
+            return;
+        }
+        let start = self.ranges[0].start;
+        let mut ranges = vec![];
         if let Some(other) = operation.downcast::<InsertOperation>() {
-            if self.position >= other.position {
-                self.position += other.text.len();
+            let (position, len) = (other.position, other.text.len());
+            for range in &self.ranges {
+                if position <= range.start {
+                    ranges.push(range.start + len..range.end + len);
+                } else if position < range.end {
+                    // the text inserted inside the deleted range is kept
+                    ranges.push(range.start..position);
+                    ranges.push(position + len..range.end + len);
+                } else {
+                    ranges.push(range.clone());
+                }
             }
+        } else if let Some(other) = operation.downcast::<DeleteOperation>() {
+            // the parts deleted by the other deletion are gone
+            let shift = |position| position - deleted_before(&other.ranges, position);
+            ranges = (self.ranges.iter())
+                .map(|range| shift(range.start)..shift(range.end))
+                .collect();
+        } else if let Some(other) = operation.downcast::<MoveOperation>() {
+            // the same bytes are deleted wherever they are moved
+            ranges.push(other.shift_position(start, true)..other.shift_position(start, true));
+            for range in &self.ranges {
+                other.shift_range(range.clone(), true, &[], &mut ranges);
+            }
+        } else {
+            return;
         }
-        if let Some(other) = operation.downcast::<DeleteOperation>() {
-            let intersection_len = if let Some(el) = intersection(
-                self.position,
-                self.position + self.len - 1,
-                other.position,
-                other.position + other.len - 1,
-            ) {
-                el.1 - el.0
-            } else {
-                0
-            };
+        self.ranges = normalize_ranges(ranges);
+    }
 
-            if intersection_len == self.len {
-                self.len = 0;
-            } else {
-                self.len -= intersection_len;
-                if self.position >= other.position {
-                    self.position -= other.len - intersection_len;
-                }
-            }
+    fn transform_relative_to_later(&mut self, operation: &dyn OperationTrait) {
+        // deletions have no ties
+        self.transform_relative_to(operation)
+    }
+
+    fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    fn set_revision(&mut self, revision: Revision) {
+        self.revision = revision
+    }
+
+    fn position(&self) -> Position {
+        self.ranges[0].start
+    }
+
+    fn kind(&self) -> &'static str {
+        "delete"
+    }
+
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn boxed_clone(&self) -> Operation {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl OperationTrait for MoveOperation {
+    fn apply(&self, text: &mut String) {
+        *text = self.apply_return(text);
+    }
+
+    fn apply_return(&self, text: &mut String) -> String {
+        let moved: String = self
+            .ranges
+            .iter()
+            .map(|range| &text[range.clone()])
+            .collect();
+        let mut ranges = self.ranges.clone();
+        ranges.sort_by_key(|range| range.start);
+
+        let mut tmp = String::with_capacity(text.len());
+        let mut start = 0;
+        for range in ranges {
+            tmp.push_str(&text[start..range.start]);
+            start = range.end;
         }
+        tmp.push_str(&text[start..]);
+        tmp.insert_str(self.block_start(), &moved);
+
+        tmp
+    }
+
+    fn can_apply(&self, text: &str) -> bool {
+        let mut ranges = self.ranges.clone();
+        ranges.sort_by_key(|range| range.start);
+        ranges.iter().all(|range| {
+            range.start < range.end
+                && text.is_char_boundary(range.start)
+                && text.is_char_boundary(range.end)
+        }) && ranges.windows(2).all(|pair| pair[0].end <= pair[1].start)
+            && text.is_char_boundary(self.to)
+            && !self.splits(self.to)
+    }
+
+    fn transform_relative_to(&mut self, operation: &dyn OperationTrait) {
+        self.transform(operation, false)
+    }
+
+    fn transform_relative_to_later(&mut self, operation: &dyn OperationTrait) {
+        self.transform(operation, true)
     }
 
     fn revision(&self) -> Revision {
@@ -276,8 +841,91 @@ impl OperationTrait for DeleteOperation {
         self.revision = revision
     }
 
+    /// Start of the first moved range, `to` if nothing is moved
     fn position(&self) -> Position {
-        self.position
+        self.ranges.first().map_or(self.to, |range| range.start)
+    }
+
+    fn kind(&self) -> &'static str {
+        "move"
+    }
+
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn boxed_clone(&self) -> Operation {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl OperationTrait for ReplaceOperation {
+    fn apply(&self, text: &mut String) {
+        self.delete.apply(text);
+        self.insert.apply(text);
+    }
+
+    fn apply_return(&self, text: &mut String) -> String {
+        let mut tmp = self.delete.apply_return(text);
+        self.insert.apply(&mut tmp);
+        tmp
+    }
+
+    fn can_apply(&self, text: &str) -> bool {
+        parts_can_apply([&self.delete as &dyn OperationTrait, &self.insert], text)
+    }
+
+    fn transform_relative_to(&mut self, operation: &dyn OperationTrait) {
+        transform_parts(
+            [&mut self.delete as _, &mut self.insert as _],
+            operation,
+            false,
+        )
+    }
+
+    fn transform_relative_to_later(&mut self, operation: &dyn OperationTrait) {
+        transform_parts(
+            [&mut self.delete as _, &mut self.insert as _],
+            operation,
+            true,
+        )
+    }
+
+    fn revision(&self) -> Revision {
+        self.delete.revision()
+    }
+
+    fn set_revision(&mut self, revision: Revision) {
+        self.delete.set_revision(revision);
+        self.insert.set_revision(revision);
+    }
+
+    fn position(&self) -> Position {
+        self.delete.position()
+    }
+
+    fn kind(&self) -> &'static str {
+        "replace"
+    }
+
+    fn size(&self) -> usize {
+        self.delete.size() + self.insert.size()
+    }
+
+    fn parts(&self) -> Option<Vec<&dyn OperationTrait>> {
+        Some(vec![&self.delete, &self.insert])
+    }
+
+    fn boxed_clone(&self) -> Operation {
+        Box::new(self.clone())
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -287,10 +935,79 @@ impl OperationTrait for DeleteOperation {
     fn as_any_mut(&mut self) -> &mut dyn Any {
         self
     }
+}
+
+impl OperationTrait for TransactionOperation {
+    fn apply(&self, text: &mut String) {
+        for operation in &self.operations {
+            operation.apply(text);
+        }
+    }
+
+    fn apply_return(&self, text: &mut String) -> String {
+        let mut tmp = text.clone();
+        self.apply(&mut tmp);
+        tmp
+    }
+
+    fn can_apply(&self, text: &str) -> bool {
+        parts_can_apply(self.operations.iter().map(|op| op.as_ref()), text)
+    }
+
+    fn transform_relative_to(&mut self, operation: &dyn OperationTrait) {
+        transform_parts(
+            self.operations.iter_mut().map(|op| op.as_mut()),
+            operation,
+            false,
+        )
+    }
 
-    #[cfg(debug_assertions)]
-    fn print(&self) {
-        print!("{:?}, ", self);
+    fn transform_relative_to_later(&mut self, operation: &dyn OperationTrait) {
+        transform_parts(
+            self.operations.iter_mut().map(|op| op.as_mut()),
+            operation,
+            true,
+        )
+    }
+
+    fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    fn set_revision(&mut self, revision: Revision) {
+        self.revision = revision;
+        for operation in &mut self.operations {
+            operation.set_revision(revision);
+        }
+    }
+
+    /// Position of the first operation, 0 if there are none
+    fn position(&self) -> Position {
+        self.operations.first().map_or(0, |op| op.position())
+    }
+
+    fn kind(&self) -> &'static str {
+        "transaction"
+    }
+
+    fn size(&self) -> usize {
+        self.operations.iter().map(|op| op.size()).sum()
+    }
+
+    fn parts(&self) -> Option<Vec<&dyn OperationTrait>> {
+        Some(self.operations.iter().map(|op| op.as_ref()).collect())
+    }
+
+    fn boxed_clone(&self) -> Operation {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
     }
 }
 
@@ -301,7 +1018,12 @@ mod tests {
             String::from($elem)
         };
     }
-    use super::{intersection, DeleteOperation, InsertOperation, OperationTrait};
+    use proptest::prelude::*;
+
+    use super::{
+        intersection, DeleteOperation, InsertOperation, MoveOperation, Operation, OperationTrait,
+        ReplaceOperation, TransactionOperation,
+    };
 
     #[test]
     fn intersection_test() {
@@ -328,6 +1050,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn can_apply() {
+        let cases: [(Operation, bool); 12] = [
+            (Box::new(InsertOperation::new(0, 0, newStr!("x"))), true),
+            (Box::new(InsertOperation::new(6, 0, newStr!("x"))), true),
+            (Box::new(InsertOperation::new(7, 0, newStr!("x"))), false),
+            (Box::new(InsertOperation::new(2, 0, newStr!("x"))), false),
+            (Box::new(DeleteOperation::new(0, 0, 6)), true),
+            (Box::new(DeleteOperation::new(3, 0, 4)), false),
+            (Box::new(DeleteOperation::new(0, 0, 2)), false),
+            (Box::new(DeleteOperation::new(1, 0, usize::MAX)), false),
+            (Box::new(MoveOperation::new(0, 0, 1, 6)), true),
+            (Box::new(MoveOperation::new(1, 0, 2, 0)), true),
+            (Box::new(MoveOperation::new(0, 0, 1, 2)), false),
+            (Box::new(MoveOperation::new(0, 0, 4, 2)), false),
+        ];
+
+        // 'é' takes the bytes 1 and 2
+        for (operation, ans) in cases {
+            assert_eq!(operation.can_apply("héllo"), ans, "{operation:?}");
+        }
+    }
+
     #[test]
     fn insert_after_insert() {
         let cases = [
@@ -457,7 +1202,7 @@ mod tests {
 
         for ((old_op, mut new_op), new_pos) in cases {
             new_op.transform_relative_to(&old_op);
-            assert_eq!(new_op.position, new_pos);
+            assert_eq!(new_op.position(), new_pos);
         }
     }
 
@@ -478,7 +1223,7 @@ mod tests {
             ), // 3
             (
                 (DeleteOperation::new(0, 0, 5), DeleteOperation::new(1, 0, 4)),
-                (1, 0),
+                (0, 0),
             ), // 4
             (
                 (DeleteOperation::new(4, 0, 3), DeleteOperation::new(0, 0, 5)),
@@ -494,9 +1239,8 @@ mod tests {
             ), // 7
             (
                 (DeleteOperation::new(0, 0, 5), DeleteOperation::new(1, 0, 3)),
-                (1, 0),
+                (0, 0),
             ), // 8
-            //TODO: Check 8 case in go-serve-test
             (
                 (DeleteOperation::new(5, 0, 5), DeleteOperation::new(1, 0, 3)),
                 (1, 3),
@@ -510,17 +1254,388 @@ mod tests {
         for (idx, ((old_op, mut new_op), (new_pos, new_len))) in cases.into_iter().enumerate() {
             new_op.transform_relative_to(&old_op);
             assert_eq!(
-                new_op.position,
+                new_op.position(),
                 new_pos,
                 "Wrong position in {} case! Old: {old_op:?}, new: {new_op:?}",
                 idx + 1
             );
             assert_eq!(
-                new_op.len,
+                new_op.len(),
                 new_len,
                 "Wrong length in {} case! Old: {old_op:?}, new: {new_op:?}",
                 idx + 1
             );
         }
     }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn move_after_insert() {
+        let cases = [
+            (
+                (
+                    InsertOperation::new(0, 0, newStr!("123")),
+                    MoveOperation::new(2, 0, 2, 6),
+                ),
+                (vec![5..7], 9),
+            ),
+            (
+                (
+                    InsertOperation::new(3, 0, newStr!("ab")),
+                    MoveOperation::new(2, 0, 3, 0),
+                ),
+                (vec![2..7], 0),
+            ),
+            (
+                (
+                    InsertOperation::new(6, 0, newStr!("x")),
+                    MoveOperation::new(2, 0, 2, 6),
+                ),
+                (vec![2..4], 7),
+            ),
+            (
+                (
+                    InsertOperation::new(2, 0, newStr!("x")),
+                    MoveOperation::new(2, 0, 2, 6),
+                ),
+                (vec![3..5], 7),
+            ),
+        ];
+
+        for ((old_op, mut new_op), (ranges, to)) in cases {
+            new_op.transform_relative_to(&old_op);
+            assert_eq!(new_op.ranges(), ranges, "Old: {old_op:?}, new: {new_op:?}");
+            assert_eq!(new_op.to(), to, "Old: {old_op:?}, new: {new_op:?}");
+        }
+    }
+
+    #[test]
+    fn insert_after_move() {
+        // "abcdef" becomes "adebcf"
+        let cases = [(0, 0), (1, 1), (2, 4), (3, 1), (4, 2), (5, 5), (6, 6)];
+
+        for (position, new_pos) in cases {
+            let old_op = MoveOperation::new(1, 0, 2, 5);
+            let mut new_op = InsertOperation::new(position, 0, newStr!("x"));
+            new_op.transform_relative_to(&old_op);
+            assert_eq!(new_op.position, new_pos, "Old: {old_op:?}, new: {new_op:?}");
+        }
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn move_after_delete() {
+        let cases = [
+            (
+                (
+                    DeleteOperation::new(0, 0, 2),
+                    MoveOperation::new(2, 0, 2, 5),
+                ),
+                (vec![0..2], 3),
+            ),
+            (
+                (
+                    DeleteOperation::new(3, 0, 2),
+                    MoveOperation::new(2, 0, 2, 6),
+                ),
+                (vec![2..3], 4),
+            ),
+            (
+                (
+                    DeleteOperation::new(1, 0, 4),
+                    MoveOperation::new(2, 0, 2, 6),
+                ),
+                (vec![], 2),
+            ),
+        ];
+
+        for ((old_op, mut new_op), (ranges, to)) in cases {
+            new_op.transform_relative_to(&old_op);
+            assert_eq!(new_op.ranges(), ranges, "Old: {old_op:?}, new: {new_op:?}");
+            assert_eq!(new_op.to(), to, "Old: {old_op:?}, new: {new_op:?}");
+        }
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn delete_after_move() {
+        // "abcdef" becomes "adebcf"
+        let cases = [
+            (DeleteOperation::new(0, 0, 3), vec![0..1, 3..5]),
+            (DeleteOperation::new(1, 0, 2), vec![3..5]),
+            (DeleteOperation::new(3, 0, 3), vec![1..3, 5..6]),
+            (DeleteOperation::new(6, 0, 0), vec![6..6]),
+        ];
+
+        for (mut new_op, ranges) in cases {
+            let old_op = MoveOperation::new(1, 0, 2, 5);
+            new_op.transform_relative_to(&old_op);
+            assert_eq!(new_op.ranges(), ranges, "Old: {old_op:?}, new: {new_op:?}");
+        }
+    }
+
+    #[test]
+    fn move_normalization() {
+        assert!(MoveOperation::new(1, 0, 2, 1).is_empty());
+        assert!(MoveOperation::new(1, 0, 2, 3).is_empty());
+        assert!(MoveOperation::new(1, 0, 0, 4).is_empty());
+
+        let merged = MoveOperation::with_ranges(0, vec![1..2, 2..4, 4..6, 7..7], 8);
+        assert_eq!(merged.ranges().len(), 1);
+        assert_eq!(merged.position()..merged.position() + merged.len(), 1..6);
+        assert_eq!(merged.len(), 5);
+
+        // the moved text keeps the order of the ranges
+        let swapped = MoveOperation::with_ranges(0, vec![4..6, 1..2], 0);
+        assert_eq!(swapped.ranges(), [4..6, 1..2]);
+        assert_eq!(swapped.apply_return(&mut newStr!("ABCDEF")), "EFBACD");
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn replace_after_insert() {
+        let cases = [
+            // the text inserted into the replaced range is kept in front of the replacement
+            (
+                InsertOperation::new(3, 0, newStr!("xy")),
+                (vec![2..3, 5..7], 4),
+            ),
+            (InsertOperation::new(2, 0, newStr!("xy")), (vec![4..7], 4)),
+            // the replacement goes after the insertion like a later insertion
+            (InsertOperation::new(5, 0, newStr!("xy")), (vec![2..5], 4)),
+        ];
+
+        for (old_op, (ranges, position)) in cases {
+            let mut new_op = ReplaceOperation::new(2, 0, 3, newStr!("abc"));
+            new_op.transform_relative_to(&old_op);
+            assert_eq!(new_op.delete().ranges(), ranges, "Old: {old_op:?}");
+            assert_eq!(new_op.insert().position, position, "Old: {old_op:?}");
+        }
+        let mut new_op = ReplaceOperation::new(2, 0, 3, newStr!("abc"));
+        new_op.transform_relative_to(&InsertOperation::new(3, 0, newStr!("xy")));
+        assert_eq!(new_op.apply_return(&mut newStr!("012xy3456")), "01xyabc56");
+    }
+
+    #[test]
+    fn insert_after_replace() {
+        // "0123456" becomes "01abc56", the replacement goes first like an earlier insertion
+        let cases = [(1, 1), (2, 5), (3, 5), (5, 5), (6, 6)];
+
+        for (position, new_pos) in cases {
+            let old_op = ReplaceOperation::new(2, 0, 3, newStr!("abc"));
+            let mut new_op = InsertOperation::new(position, 0, newStr!("x"));
+            new_op.transform_relative_to(&old_op);
+            assert_eq!(new_op.position, new_pos, "Old: {old_op:?}, new: {new_op:?}");
+        }
+    }
+
+    #[test]
+    fn transaction_parts_follow_each_other() {
+        let transaction = TransactionOperation::new(
+            0,
+            vec![
+                Box::new(DeleteOperation::new(0, 0, 2)),
+                Box::new(InsertOperation::new(3, 0, newStr!("x"))),
+            ],
+        );
+        assert!(transaction.can_apply("abcde"));
+        assert!(!transaction.can_apply("abcd"));
+        assert_eq!(transaction.apply_return(&mut newStr!("abcde")), "cdex");
+
+        // the insertion is shifted by the concurrent one and by the deletion before it
+        let mut insert = InsertOperation::new(4, 0, newStr!("y"));
+        insert.transform_relative_to(&transaction);
+        assert_eq!(insert.position, 2);
+    }
+
+    /// Byte offsets of the chars of the text and of its end
+    fn char_offsets(text: &str) -> Vec<usize> {
+        (text.char_indices())
+            .map(|(idx, _)| idx)
+            .chain([text.len()])
+            .collect()
+    }
+
+    /// Text and a valid single operation on it, the positions are at char boundaries
+    fn single_operation(text: &str) -> BoxedStrategy<Operation> {
+        let len = text.chars().count();
+        let offsets = char_offsets(text);
+        let insert = (0..=len, "[a-zé🙂]{1,4}")
+            .prop_map(move |(position, text)| -> Operation {
+                Box::new(InsertOperation::new(offsets[position], 0, text))
+            })
+            .boxed();
+        if len == 0 {
+            return insert;
+        }
+        let offsets = char_offsets(text);
+        let delete = (0..len)
+            .prop_flat_map(move |position| (Just(position), 1..=len - position))
+            .prop_map(move |(position, len)| -> Operation {
+                let (start, end) = (offsets[position], offsets[position + len]);
+                Box::new(DeleteOperation::new(start, 0, end - start))
+            })
+            .boxed();
+        let offsets = char_offsets(text);
+        let moves = (0..len)
+            .prop_flat_map(move |position| (Just(position), 1..=len - position))
+            .prop_flat_map(move |(position, moved)| (Just(position), Just(moved), 0..=len - moved))
+            .prop_map(move |(position, len, to)| -> Operation {
+                // `to` isn't inside the moved range
+                let to = if to <= position { to } else { to + len };
+                let (start, end) = (offsets[position], offsets[position + len]);
+                Box::new(MoveOperation::new(start, 0, end - start, offsets[to]))
+            })
+            .boxed();
+        let offsets = char_offsets(text);
+        let replace = (0..len)
+            .prop_flat_map(move |position| (Just(position), 0..=len - position, "[a-zé🙂]{0,3}"))
+            .prop_map(move |(position, len, text)| -> Operation {
+                let (start, end) = (offsets[position], offsets[position + len]);
+                Box::new(ReplaceOperation::new(start, 0, end - start, text))
+            })
+            .boxed();
+        prop_oneof![insert, delete, moves, replace].boxed()
+    }
+
+    /// Text and a valid operation on it, a transaction makes the second operation
+    /// follow the first one
+    fn operation(text: &str) -> BoxedStrategy<Operation> {
+        let transaction = (single_operation(text), single_operation(text))
+            .prop_map(|(first, mut second)| -> Operation {
+                second.transform_relative_to(first.as_ref());
+                Box::new(TransactionOperation::new(0, vec![first, second]))
+            })
+            .boxed();
+        prop_oneof![3 => single_operation(text), 1 => transaction].boxed()
+    }
+
+    fn concurrent_operations() -> impl Strategy<Value = (String, Operation, Operation)> {
+        "[A-ZÄЖ中]{0,12}".prop_flat_map(|text| {
+            let (first, second) = (operation(&text), operation(&text));
+            (Just(text), first, second)
+        })
+    }
+
+    /// Checks TP1: `first` then transformed `second` gives the same text
+    /// as `second` then transformed `first`, where `first` is ordered before `second`.
+    fn check_convergence(text: &str, first: &Operation, second: &Operation) -> (String, String) {
+        let mut second_after_first = second.boxed_clone();
+        second_after_first.transform_relative_to(first.as_ref());
+        let mut first_after_second = first.boxed_clone();
+        first_after_second.transform_relative_to_later(second.as_ref());
+
+        let mut left = String::from(text);
+        first.apply(&mut left);
+        second_after_first.apply(&mut left);
+
+        let mut right = String::from(text);
+        second.apply(&mut right);
+        first_after_second.apply(&mut right);
+
+        (left, right)
+    }
+
+    /// Minimal reproducers found by `concurrent_operations_converge` and the cases of the moves
+    #[test]
+    fn convergence_regressions() {
+        let cases: [(&str, Operation, Operation, &str); 12] = [
+            (
+                "",
+                Box::new(InsertOperation::new(0, 0, newStr!("a"))),
+                Box::new(InsertOperation::new(0, 0, newStr!("b"))),
+                "ab",
+            ),
+            (
+                "XUY",
+                Box::new(DeleteOperation::new(0, 0, 3)),
+                Box::new(DeleteOperation::new(1, 0, 1)),
+                "",
+            ),
+            (
+                "BY",
+                Box::new(DeleteOperation::new(0, 0, 2)),
+                Box::new(InsertOperation::new(1, 0, newStr!("a"))),
+                "a",
+            ),
+            (
+                "BY",
+                Box::new(InsertOperation::new(1, 0, newStr!("a"))),
+                Box::new(DeleteOperation::new(0, 0, 2)),
+                "a",
+            ),
+            (
+                "ABCDEF",
+                Box::new(MoveOperation::new(1, 0, 2, 5)),
+                Box::new(InsertOperation::new(2, 0, newStr!("x"))),
+                "ADEBxCF",
+            ),
+            (
+                "ABCDEF",
+                Box::new(MoveOperation::new(1, 0, 2, 5)),
+                Box::new(DeleteOperation::new(2, 0, 3)),
+                "ABF",
+            ),
+            (
+                "ABCDEF",
+                Box::new(MoveOperation::new(1, 0, 2, 0)),
+                Box::new(MoveOperation::new(1, 0, 2, 6)),
+                "ADEFBC",
+            ),
+            (
+                "ABCDEF",
+                Box::new(MoveOperation::new(1, 0, 2, 5)),
+                Box::new(MoveOperation::new(2, 0, 3, 0)),
+                "CDEABF",
+            ),
+            (
+                "ABCDEF",
+                Box::new(MoveOperation::new(1, 0, 4, 6)),
+                Box::new(MoveOperation::new(2, 0, 2, 0)),
+                "CDAFBE",
+            ),
+            (
+                "ABCDEF",
+                Box::new(MoveOperation::new(0, 0, 2, 4)),
+                Box::new(MoveOperation::new(3, 0, 2, 1)),
+                "CADEBF",
+            ),
+            (
+                "ABCDEF",
+                Box::new(ReplaceOperation::new(1, 0, 3, newStr!("x"))),
+                Box::new(ReplaceOperation::new(2, 0, 3, newStr!("y"))),
+                "AxyF",
+            ),
+            (
+                "ABCDEF",
+                Box::new(MoveOperation::new(2, 0, 2, 6)),
+                Box::new(ReplaceOperation::new(1, 0, 2, newStr!("y"))),
+                "AyEFD",
+            ),
+        ];
+
+        for (idx, (text, first, second, ans)) in cases.into_iter().enumerate() {
+            let (left, right) = check_convergence(text, &first, &second);
+            assert_eq!(
+                left,
+                ans,
+                "Wrong text in {} case! First: {first:?}, second: {second:?}",
+                idx + 1
+            );
+            assert_eq!(
+                right,
+                ans,
+                "Wrong text in {} case! First: {first:?}, second: {second:?}",
+                idx + 1
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn concurrent_operations_converge((text, first, second) in concurrent_operations()) {
+            let (left, right) = check_convergence(&text, &first, &second);
+            prop_assert_eq!(left, right, "text: {:?}, first: {:?}, second: {:?}", text, first, second);
+        }
+    }
 }
@@ -0,0 +1,510 @@
+//! Formatting of the text: attributes like `bold`, `italic` or `link` on ranges of the text.
+//!
+//! The formatting is changed by [`FormatOperation`], the text itself only by the insertions,
+//! deletions and moves, the moved text keeps its attributes. Inserted text is plain: the attributes of its neighbours may be deleted
+//! or changed concurrently, so an editor which continues a bold word sends the insertion
+//! and a format of the inserted text.
+
+use std::{any::Any, cmp::min, collections::BTreeMap};
+
+use super::operations::{
+    DeleteOperation, InsertOperation, MoveOperation, Operation, OperationTrait,
+};
+
+/// Attributes by name, `null` removes the attribute when it is applied
+pub type Attributes = BTreeMap<String, serde_json::Value>;
+
+type Position = usize;
+type Revision = usize;
+
+/// Sets attributes on a range of the text.
+///
+/// The range is split into spans with their own attributes: a client usually sends one span,
+/// the transformation splits it when a concurrent operation changes a part of the range.
+/// A span with no attributes leaves its part of the range as it is.
+#[derive(Debug, Clone)]
+pub struct FormatOperation {
+    position: Position,
+    revision: Revision,
+    /// Lengths of the parts of the range and their attributes
+    spans: Vec<(usize, Attributes)>,
+}
+
+impl FormatOperation {
+    /// Sets `attributes` on `len` bytes starting from `position`
+    pub fn new(position: Position, revision: Revision, len: usize, attributes: Attributes) -> Self {
+        Self::with_spans(position, revision, vec![(len, attributes)])
+    }
+
+    pub fn with_spans(
+        position: Position,
+        revision: Revision,
+        spans: Vec<(usize, Attributes)>,
+    ) -> Self {
+        Self {
+            position,
+            revision,
+            spans,
+        }
+    }
+
+    pub fn spans(&self) -> &[(usize, Attributes)] {
+        &self.spans
+    }
+
+    /// Length of the formatted range
+    pub fn len(&self) -> usize {
+        self.spans.iter().map(|(len, _)| len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `wins_ties` is true if this operation is ordered before `operation`,
+    /// then the attributes of `operation` win on the common part of the ranges
+    fn transform(&mut self, operation: &dyn OperationTrait, wins_ties: bool) {
+        if let Some(parts) = operation.parts() {
+            parts
+                .into_iter()
+                .for_each(|part| self.transform(part, wins_ties));
+            return;
+        }
+        if let Some(other) = operation.downcast::<InsertOperation>() {
+            let end = self.position + self.len();
+            if other.position() <= self.position {
+                self.position += other.text().len();
+            } else if other.position() < end {
+                // the inserted text stays plain
+                let idx = split_runs(&mut self.spans, other.position() - self.position);
+                self.spans
+                    .insert(idx, (other.text().len(), Attributes::new()));
+            }
+        }
+        if let Some(other) = operation.downcast::<DeleteOperation>() {
+            let start = self.position;
+            for deleted in other.ranges().iter().rev() {
+                let end = start + self.len();
+                if deleted.start < end && start < deleted.end {
+                    let first = split_runs(&mut self.spans, deleted.start.max(start) - start);
+                    let last = split_runs(&mut self.spans, deleted.end.min(end) - start);
+                    self.spans.drain(first..last);
+                }
+                self.position -= min(deleted.len(), start.saturating_sub(deleted.start));
+            }
+        }
+        if let Some(other) = operation.downcast::<MoveOperation>() {
+            // the moved parts of the range are formatted where they are put,
+            // the text between the parts is left as it is
+            let mut parts = vec![];
+            let mut start = self.position;
+            for (len, attributes) in &self.spans {
+                let mut ranges = vec![];
+                other.shift_range(start..start + len, true, &[], &mut ranges);
+                parts.extend(ranges.into_iter().map(|range| (range, attributes.clone())));
+                start += len;
+            }
+            parts.sort_by_key(|(range, _)| range.start);
+
+            self.position = match parts.first() {
+                Some((range, _)) => range.start,
+                None => other.shift_position(self.position, wins_ties),
+            };
+            self.spans.clear();
+            let mut end = self.position;
+            for (range, attributes) in parts {
+                self.spans.push((range.start - end, Attributes::new()));
+                end = range.end;
+                self.spans.push((range.len(), attributes));
+            }
+        }
+        if let Some(other) = operation.downcast::<FormatOperation>() {
+            if !wins_ties {
+                return;
+            }
+            let mut other_start = other.position;
+            for (len, attributes) in &other.spans {
+                let (start, end) = (
+                    other_start.max(self.position),
+                    (other_start + len).min(self.position + self.len()),
+                );
+                other_start += len;
+                if start >= end || attributes.is_empty() {
+                    continue;
+                }
+                let first = split_runs(&mut self.spans, start - self.position);
+                let last = split_runs(&mut self.spans, end - self.position);
+                for (_, own) in &mut self.spans[first..last] {
+                    own.retain(|name, _| !attributes.contains_key(name));
+                }
+            }
+        }
+        merge_runs(&mut self.spans);
+    }
+}
+
+impl OperationTrait for FormatOperation {
+    /// The text doesn't change, see [`RichText::apply`]
+    fn apply(&self, _text: &mut String) {}
+
+    fn apply_return(&self, text: &mut String) -> String {
+        text.clone()
+    }
+
+    fn can_apply(&self, text: &str) -> bool {
+        match self.position.checked_add(self.len()) {
+            Some(end) => text.is_char_boundary(self.position) && text.is_char_boundary(end),
+            None => false,
+        }
+    }
+
+    fn transform_relative_to(&mut self, operation: &dyn OperationTrait) {
+        self.transform(operation, false)
+    }
+
+    fn transform_relative_to_later(&mut self, operation: &dyn OperationTrait) {
+        self.transform(operation, true)
+    }
+
+    fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    fn set_revision(&mut self, revision: Revision) {
+        self.revision = revision
+    }
+
+    fn position(&self) -> Position {
+        self.position
+    }
+
+    fn kind(&self) -> &'static str {
+        "format"
+    }
+
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn boxed_clone(&self) -> Operation {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Text with attributed runs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RichText {
+    text: String,
+    /// Lengths of the runs with the same attributes, they cover the whole text
+    runs: Vec<(usize, Attributes)>,
+}
+
+impl RichText {
+    pub fn new() -> Self {
+        RichText::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Runs of the text with the same attributes, plain text has no attributes
+    pub fn runs(&self) -> &[(usize, Attributes)] {
+        &self.runs
+    }
+
+    /// Applies the operation to the text and its attributes,
+    /// the operation must fit the text, see [`OperationTrait::can_apply`]
+    pub fn apply(&mut self, operation: &dyn OperationTrait) {
+        if let Some(parts) = operation.parts() {
+            parts.into_iter().for_each(|part| self.apply(part));
+            return;
+        }
+        if let Some(insert) = operation.downcast::<InsertOperation>() {
+            let idx = split_runs(&mut self.runs, insert.position());
+            self.runs
+                .insert(idx, (insert.text().len(), Attributes::new()));
+        } else if let Some(delete) = operation.downcast::<DeleteOperation>() {
+            for range in delete.ranges().iter().rev() {
+                let first = split_runs(&mut self.runs, range.start);
+                let last = split_runs(&mut self.runs, range.end);
+                self.runs.drain(first..last);
+            }
+        } else if let Some(operation) = operation.downcast::<MoveOperation>() {
+            move_runs(&mut self.runs, operation);
+        } else if let Some(format) = operation.downcast::<FormatOperation>() {
+            let mut start = format.position;
+            for (len, attributes) in &format.spans {
+                let first = split_runs(&mut self.runs, start);
+                let last = split_runs(&mut self.runs, start + len);
+                for (_, own) in &mut self.runs[first..last] {
+                    for (name, value) in attributes {
+                        match value.is_null() {
+                            true => own.remove(name),
+                            false => own.insert(name.clone(), value.clone()),
+                        };
+                    }
+                }
+                start += len;
+            }
+        }
+        operation.apply(&mut self.text);
+        merge_runs(&mut self.runs);
+    }
+}
+
+/// Splits the run containing byte `position`, so a run starts there.
+///
+/// Returns the index of that run, the length of `runs` if `position` is the end of the runs.
+pub(crate) fn split_runs<T: Clone>(runs: &mut Vec<(usize, T)>, position: usize) -> usize {
+    let mut start = 0;
+    for idx in 0..runs.len() {
+        let len = runs[idx].0;
+        if position == start {
+            return idx;
+        }
+        if position < start + len {
+            let tail = (start + len - position, runs[idx].1.clone());
+            runs[idx].0 = position - start;
+            runs.insert(idx + 1, tail);
+            return idx + 1;
+        }
+        start += len;
+    }
+    runs.len()
+}
+
+/// Moves the runs of the ranges of `operation` to its position
+pub(crate) fn move_runs<T: Clone>(runs: &mut Vec<(usize, T)>, operation: &MoveOperation) {
+    let mut moved = vec![];
+    for range in operation.ranges() {
+        let first = split_runs(runs, range.start);
+        let last = split_runs(runs, range.end);
+        moved.extend_from_slice(&runs[first..last]);
+    }
+    let mut ranges = operation.ranges().to_vec();
+    ranges.sort_by_key(|range| range.start);
+    for range in ranges.iter().rev() {
+        let first = split_runs(runs, range.start);
+        let last = split_runs(runs, range.end);
+        runs.drain(first..last);
+    }
+    let idx = split_runs(runs, operation.block_start());
+    runs.splice(idx..idx, moved);
+}
+
+/// Removes the empty runs and merges the neighbours with the same value
+fn merge_runs<T: PartialEq>(runs: &mut Vec<(usize, T)>) {
+    runs.retain(|(len, _)| *len > 0);
+    runs.dedup_by(|next, previous| {
+        let same = next.1 == previous.1;
+        if same {
+            previous.0 += next.0;
+        }
+        same
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    use super::{Attributes, FormatOperation, RichText};
+    use crate::ot::operations::{
+        DeleteOperation, InsertOperation, MoveOperation, Operation, OperationTrait,
+        ReplaceOperation, TransactionOperation,
+    };
+
+    fn attributes(value: serde_json::Value) -> Attributes {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn rich_text(text: &str) -> RichText {
+        let mut rich_text = RichText::new();
+        rich_text.apply(&InsertOperation::new(0, 0, text.to_string()));
+        rich_text
+    }
+
+    #[test]
+    fn formatting_is_applied_to_runs() {
+        let mut text = rich_text("hello world");
+        text.apply(&FormatOperation::new(
+            0,
+            0,
+            5,
+            attributes(json!({"bold": true})),
+        ));
+        text.apply(&FormatOperation::new(
+            3,
+            0,
+            5,
+            attributes(json!({"bold": null, "link": "x"})),
+        ));
+
+        assert_eq!(text.text(), "hello world");
+        assert_eq!(
+            text.runs(),
+            [
+                (3, attributes(json!({"bold": true}))),
+                (5, attributes(json!({"link": "x"}))),
+                (3, Attributes::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn inserted_text_is_plain() {
+        let mut text = rich_text("abc");
+        text.apply(&FormatOperation::new(
+            0,
+            0,
+            3,
+            attributes(json!({"bold": true})),
+        ));
+        text.apply(&InsertOperation::new(1, 0, String::from("x")));
+
+        assert_eq!(text.text(), "axbc");
+        assert_eq!(
+            text.runs(),
+            [
+                (1, attributes(json!({"bold": true}))),
+                (1, Attributes::new()),
+                (2, attributes(json!({"bold": true}))),
+            ]
+        );
+    }
+
+    #[test]
+    fn format_is_transformed_relative_to_edits() {
+        let bold = || attributes(json!({"bold": true}));
+
+        // the inserted text isn't formatted
+        let mut format = FormatOperation::new(2, 0, 3, bold());
+        format.transform_relative_to(&InsertOperation::new(5, 0, String::from("xy")));
+        format.transform_relative_to(&InsertOperation::new(2, 0, String::from("z")));
+        format.transform_relative_to(&InsertOperation::new(4, 0, String::from("w")));
+        assert_eq!(format.position(), 3);
+        assert_eq!(
+            format.spans(),
+            [(1, bold()), (1, Attributes::new()), (2, bold())]
+        );
+
+        // the deleted part of the range isn't formatted
+        let mut format = FormatOperation::new(2, 0, 3, bold());
+        format.transform_relative_to(&DeleteOperation::new(0, 0, 3));
+        assert_eq!((format.position(), format.len()), (0, 2));
+    }
+
+    #[test]
+    fn later_format_wins() {
+        let mut first = FormatOperation::new(0, 0, 4, attributes(json!({"bold": true})));
+        let second = FormatOperation::new(2, 0, 4, attributes(json!({"bold": false, "i": 1})));
+        first.transform_relative_to_later(&second);
+
+        assert_eq!(
+            first.spans(),
+            [
+                (2, attributes(json!({"bold": true}))),
+                (2, Attributes::new())
+            ]
+        );
+    }
+
+    /// Text and a valid single operation on it
+    fn single_operation(len: usize) -> BoxedStrategy<Operation> {
+        let insert = (0..=len, "[a-z]{1,3}")
+            .prop_map(|(position, text)| -> Operation {
+                Box::new(InsertOperation::new(position, 0, text))
+            })
+            .boxed();
+        if len == 0 {
+            return insert;
+        }
+        let range = (0..len).prop_flat_map(move |position| (Just(position), 1..=len - position));
+        let delete = range
+            .clone()
+            .prop_map(|(position, len)| -> Operation {
+                Box::new(DeleteOperation::new(position, 0, len))
+            })
+            .boxed();
+        let format = (range, "[ab]", prop::option::of(0..2))
+            .prop_map(|((position, len), name, value)| -> Operation {
+                let value = value.map_or(serde_json::Value::Null, serde_json::Value::from);
+                let attributes = Attributes::from([(name, value)]);
+                Box::new(FormatOperation::new(position, 0, len, attributes))
+            })
+            .boxed();
+        let moves = (0..len)
+            .prop_flat_map(move |position| (Just(position), 1..=len - position))
+            .prop_flat_map(move |(position, moved)| (Just(position), Just(moved), 0..=len - moved))
+            .prop_map(|(position, len, to)| -> Operation {
+                let to = if to <= position { to } else { to + len };
+                Box::new(MoveOperation::new(position, 0, len, to))
+            })
+            .boxed();
+        let replace = (0..len)
+            .prop_flat_map(move |position| (Just(position), 0..=len - position, "[a-z]{0,3}"))
+            .prop_map(|(position, len, text)| -> Operation {
+                Box::new(ReplaceOperation::new(position, 0, len, text))
+            })
+            .boxed();
+        prop_oneof![insert, delete, format, moves, replace].boxed()
+    }
+
+    /// Text and a valid operation on it, a transaction makes the second operation
+    /// follow the first one
+    fn operation(len: usize) -> BoxedStrategy<Operation> {
+        let transaction = (single_operation(len), single_operation(len))
+            .prop_map(|(first, mut second)| -> Operation {
+                second.transform_relative_to(first.as_ref());
+                Box::new(TransactionOperation::new(0, vec![first, second]))
+            })
+            .boxed();
+        prop_oneof![3 => single_operation(len), 1 => transaction].boxed()
+    }
+
+    /// Formatted text and two concurrent operations on it
+    fn concurrent_operations() -> impl Strategy<Value = (RichText, Operation, Operation)> {
+        ("[A-Z]{1,8}", 0..8usize, 1..4usize).prop_flat_map(|(text, start, len)| {
+            let mut rich_text = rich_text(&text);
+            let start = start.min(text.len() - 1);
+            let len = len.min(text.len() - start);
+            rich_text.apply(&FormatOperation::new(
+                start,
+                0,
+                len,
+                attributes(json!({"a": 1})),
+            ));
+            let (first, second) = (operation(text.len()), operation(text.len()));
+            (Just(rich_text), first, second)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn concurrent_operations_converge((text, first, second) in concurrent_operations()) {
+            let mut second_after_first = second.boxed_clone();
+            second_after_first.transform_relative_to(first.as_ref());
+            let mut first_after_second = first.boxed_clone();
+            first_after_second.transform_relative_to_later(second.as_ref());
+
+            let mut left = text.clone();
+            left.apply(first.as_ref());
+            left.apply(second_after_first.as_ref());
+            let mut right = text.clone();
+            right.apply(second.as_ref());
+            right.apply(first_after_second.as_ref());
+
+            prop_assert_eq!(left, right, "first: {:?}, second: {:?}", first, second);
+        }
+    }
+}
@@ -0,0 +1,598 @@
+//! Sequences of arbitrary elements: rows of a table, cards on a board.
+//!
+//! A [`SequenceOperation`] inserts, deletes and moves elements like the text operations
+//! change characters, [`ListDocument`] holds the elements.
+//!
+//! Unlike a text deletion, a deletion of elements never loses the elements which are
+//! concurrently inserted into its range or moved into it: it deletes the same elements
+//! wherever they are moved, and the inserted ones are kept.
+
+use core::fmt::Debug;
+use std::{any::Any, ops::Range, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    document::{DocumentKind, DocumentTrait},
+    operations::{intersection, ArcOperation, Operation, OperationTrait},
+};
+
+type Position = usize;
+type Revision = usize;
+
+/// What an operation does with the sequence
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SequenceAction<T> {
+    /// Inserts the items in front of the element at `position`
+    Insert { position: Position, items: Vec<T> },
+    /// Deletes the ranges of elements, they are sorted and don't overlap.
+    /// No ranges is an operation which does nothing.
+    Delete { ranges: Vec<Range<Position>> },
+    /// Moves the element at `from` in front of the element which is at index `to`
+    /// before the move, `to` equal to the length of the sequence moves it to the end
+    Move { from: Position, to: Position },
+}
+
+#[derive(Debug, Clone)]
+pub struct SequenceOperation<T> {
+    revision: Revision,
+    action: SequenceAction<T>,
+}
+
+impl<T: Clone> SequenceOperation<T> {
+    pub fn new(revision: Revision, action: SequenceAction<T>) -> Self {
+        Self { revision, action }
+    }
+
+    pub fn insert(position: Position, revision: Revision, items: Vec<T>) -> Self {
+        Self::new(revision, SequenceAction::Insert { position, items })
+    }
+
+    /// Deletes `len` elements starting from `position`
+    pub fn delete(position: Position, revision: Revision, len: usize) -> Self {
+        let ranges = std::iter::once(position..position + len).collect();
+        Self::new(revision, SequenceAction::Delete { ranges })
+    }
+
+    pub fn move_element(from: Position, revision: Revision, to: Position) -> Self {
+        Self::new(revision, SequenceAction::Move { from, to })
+    }
+
+    pub fn action(&self) -> &SequenceAction<T> {
+        &self.action
+    }
+
+    /// Checks that the operation fits a sequence of `len` elements,
+    /// [`SequenceOperation::apply_to`] panics otherwise
+    pub fn can_apply_to(&self, len: usize) -> bool {
+        match &self.action {
+            SequenceAction::Insert { position, .. } => *position <= len,
+            SequenceAction::Delete { ranges } => {
+                ranges.iter().all(|range| range.start <= range.end)
+                    && ranges.windows(2).all(|pair| pair[0].end <= pair[1].start)
+                    && ranges.last().is_none_or(|range| range.end <= len)
+            }
+            SequenceAction::Move { from, to } => *from < len && *to <= len,
+        }
+    }
+
+    /// Applies the operation to the sequence, it must fit the sequence,
+    /// see [`SequenceOperation::can_apply_to`]
+    pub fn apply_to(&self, sequence: &mut Vec<T>) {
+        match &self.action {
+            SequenceAction::Insert { position, items } => {
+                sequence.splice(*position..*position, items.iter().cloned());
+            }
+            SequenceAction::Delete { ranges } => {
+                for range in ranges.iter().rev() {
+                    sequence.drain(range.clone());
+                }
+            }
+            SequenceAction::Move { from, to } => {
+                let item = sequence.remove(*from);
+                sequence.insert(to - usize::from(to > from), item);
+            }
+        }
+    }
+
+    /// `wins_ties` is true if `other` is ordered after this operation,
+    /// then this operation inserts in front of `other` at the same position
+    /// and `other` wins when both operations move the same element
+    fn transform(&mut self, other: &SequenceOperation<T>, wins_ties: bool) {
+        if self.moves_nothing() {
+            self.action = SequenceAction::Delete { ranges: vec![] };
+        }
+        if other.moves_nothing() {
+            return;
+        }
+        let shift = Shift::of(&other.action);
+        match &mut self.action {
+            SequenceAction::Insert { position, .. } => *position = shift.slot(*position, wins_ties),
+            SequenceAction::Delete { ranges } => *ranges = shift.ranges(ranges),
+            SequenceAction::Move { from, to } => {
+                let moved_by_other = matches!(
+                    other.action,
+                    SequenceAction::Move { from: other_from, .. } if other_from == *from
+                );
+                match shift.element(*from) {
+                    Some(element) if !(moved_by_other && wins_ties) => {
+                        *from = element;
+                        *to = shift.slot(*to, wins_ties);
+                    }
+                    // the element is deleted or the later move wins
+                    _ => self.action = SequenceAction::Delete { ranges: vec![] },
+                }
+            }
+        }
+    }
+
+    /// Whether the operation moves an element to its own place
+    fn moves_nothing(&self) -> bool {
+        match self.action {
+            SequenceAction::Move { from, to } => to == from || to == from + 1,
+            _ => false,
+        }
+    }
+}
+
+/// How an applied operation shifts the elements and the slots between them
+enum Shift<'a> {
+    Insert {
+        position: Position,
+        len: usize,
+    },
+    Delete {
+        ranges: &'a [Range<Position>],
+    },
+    /// `to` is the slot of the moved element in the sequence without it
+    Move {
+        from: Position,
+        to: Position,
+    },
+}
+
+impl<'a> Shift<'a> {
+    fn of<T>(action: &'a SequenceAction<T>) -> Self {
+        match action {
+            SequenceAction::Insert { position, items } => Shift::Insert {
+                position: *position,
+                len: items.len(),
+            },
+            SequenceAction::Delete { ranges } => Shift::Delete { ranges },
+            SequenceAction::Move { from, to } => Shift::Move {
+                from: *from,
+                to: to - usize::from(to > from),
+            },
+        }
+    }
+
+    /// New index of the element, `None` if the element is deleted
+    fn element(&self, element: Position) -> Option<Position> {
+        match *self {
+            Shift::Insert { position, len } => match element >= position {
+                true => Some(element + len),
+                false => Some(element),
+            },
+            Shift::Delete { ranges } => match ranges.iter().any(|range| range.contains(&element)) {
+                true => None,
+                false => Some(element - Self::deleted_before(ranges, element)),
+            },
+            Shift::Move { from, to } => {
+                if element == from {
+                    return Some(to);
+                }
+                let element = element - usize::from(element > from);
+                Some(element + usize::from(element >= to))
+            }
+        }
+    }
+
+    /// New index of the slot in front of the element `slot`,
+    /// `wins_ties` keeps the slot in front of the elements inserted into it
+    fn slot(&self, slot: Position, wins_ties: bool) -> Position {
+        let after = |slot: Position, position: Position| {
+            slot > position || (slot == position && !wins_ties)
+        };
+        match *self {
+            Shift::Insert { position, len } => match after(slot, position) {
+                true => slot + len,
+                false => slot,
+            },
+            Shift::Delete { ranges } => slot - Self::deleted_before(ranges, slot),
+            Shift::Move { from, to } => {
+                let slot = slot - usize::from(slot > from);
+                slot + usize::from(after(slot, to))
+            }
+        }
+    }
+
+    /// New ranges of the same elements, the elements inserted between them aren't included
+    fn ranges(&self, ranges: &[Range<Position>]) -> Vec<Range<Position>> {
+        let mut shifted = vec![];
+        for range in ranges.iter().filter(|range| !range.is_empty()) {
+            match *self {
+                Shift::Insert { position, len } => {
+                    if position <= range.start {
+                        shifted.push(range.start + len..range.end + len);
+                    } else if position < range.end {
+                        shifted.push(range.start..position);
+                        shifted.push(position + len..range.end + len);
+                    } else {
+                        shifted.push(range.clone());
+                    }
+                }
+                Shift::Delete { ranges: deleted } => {
+                    let mut pieces = vec![range.clone()];
+                    for deleted in deleted.iter().filter(|range| !range.is_empty()) {
+                        pieces = pieces
+                            .into_iter()
+                            .flat_map(|piece| {
+                                match intersection(
+                                    piece.start,
+                                    piece.end - 1,
+                                    deleted.start,
+                                    deleted.end - 1,
+                                ) {
+                                    Some((start, end)) => vec![piece.start..start, end..piece.end],
+                                    None => vec![piece],
+                                }
+                            })
+                            .filter(|piece| !piece.is_empty())
+                            .collect();
+                    }
+                    shifted.extend(pieces.into_iter().map(|piece| {
+                        let start = piece.start - Self::deleted_before(deleted, piece.start);
+                        start..start + piece.len()
+                    }));
+                }
+                Shift::Move { from, to } => {
+                    let mut pieces = vec![range.clone()];
+                    if range.contains(&from) {
+                        shifted.push(to..to + 1);
+                        pieces = vec![range.start..from, from + 1..range.end];
+                    }
+                    for piece in pieces.into_iter().filter(|piece| !piece.is_empty()) {
+                        let start = piece.start - usize::from(piece.start > from);
+                        let end = start + piece.len();
+                        if to <= start {
+                            shifted.push(start + 1..end + 1);
+                        } else if to < end {
+                            shifted.push(start..to);
+                            shifted.push(to + 1..end + 1);
+                        } else {
+                            shifted.push(start..end);
+                        }
+                    }
+                }
+            }
+        }
+        normalize(shifted)
+    }
+
+    /// Number of the deleted elements in front of the element `position`
+    fn deleted_before(ranges: &[Range<Position>], position: Position) -> usize {
+        ranges
+            .iter()
+            .map(|range| range.end.min(position).saturating_sub(range.start))
+            .sum()
+    }
+}
+
+/// Sorts the ranges and merges the ones which overlap or touch
+fn normalize(mut ranges: Vec<Range<Position>>) -> Vec<Range<Position>> {
+    ranges.retain(|range| !range.is_empty());
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<Position>> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+impl<T> OperationTrait for SequenceOperation<T>
+where
+    T: Clone + Debug + Send + Sync + 'static,
+{
+    /// The sequence operations don't change texts, see [`ListDocument`]
+    fn apply(&self, _text: &mut String) {}
+
+    fn apply_return(&self, text: &mut String) -> String {
+        text.clone()
+    }
+
+    /// The sequence operations only fit list documents
+    fn can_apply(&self, _text: &str) -> bool {
+        false
+    }
+
+    fn transform_relative_to(&mut self, operation: &dyn OperationTrait) {
+        if let Some(other) = operation.downcast::<SequenceOperation<T>>() {
+            self.transform(other, false);
+        }
+    }
+
+    fn transform_relative_to_later(&mut self, operation: &dyn OperationTrait) {
+        if let Some(other) = operation.downcast::<SequenceOperation<T>>() {
+            self.transform(other, true);
+        }
+    }
+
+    fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    fn set_revision(&mut self, revision: Revision) {
+        self.revision = revision
+    }
+
+    fn position(&self) -> Position {
+        match &self.action {
+            SequenceAction::Insert { position, .. } => *position,
+            SequenceAction::Delete { ranges } => ranges.first().map_or(0, |range| range.start),
+            SequenceAction::Move { from, .. } => *from,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "list"
+    }
+
+    /// Number of the elements the operation inserts, deletes or moves
+    fn size(&self) -> usize {
+        match &self.action {
+            SequenceAction::Insert { items, .. } => items.len(),
+            SequenceAction::Delete { ranges } => ranges.iter().map(|range| range.len()).sum(),
+            SequenceAction::Move { .. } => 1,
+        }
+    }
+
+    fn boxed_clone(&self) -> Operation {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Document which holds a sequence of elements
+#[derive(Debug)]
+pub struct ListDocument<T> {
+    operations: Vec<ArcOperation>,
+    items: Vec<T>,
+}
+
+impl<T> Default for ListDocument<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ListDocument<T> {
+    pub fn new() -> ListDocument<T> {
+        ListDocument {
+            operations: Vec::new(),
+            items: Vec::new(),
+        }
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+}
+
+impl<T> ListDocument<T>
+where
+    T: Clone + Serialize + Debug + Send + Sync + 'static,
+{
+    /// Elements after the operations before `revision`, `None` if the revision is in the future
+    pub fn items_at(&self, revision: usize) -> Option<Vec<T>> {
+        let mut items = vec![];
+        for operation in self.operations.get(..revision)? {
+            if let Some(sequence) = operation.downcast::<SequenceOperation<T>>() {
+                sequence.apply_to(&mut items);
+            }
+        }
+        Some(items)
+    }
+}
+
+impl<T> DocumentTrait for ListDocument<T>
+where
+    T: Clone + Serialize + Debug + Send + Sync + 'static,
+{
+    fn kind(&self) -> DocumentKind {
+        DocumentKind::List
+    }
+
+    fn apply(&mut self, mut op: Operation) -> Option<ArcOperation> {
+        for i in self.operations.get(op.revision()..)? {
+            op.transform_relative_to(Box::as_ref(i));
+        }
+        let sequence = op.downcast::<SequenceOperation<T>>()?;
+        if !sequence.can_apply_to(self.items.len()) {
+            return None;
+        }
+        sequence.apply_to(&mut self.items);
+        op.set_revision(self.operations.len());
+        let op = Arc::new(op);
+        self.operations.push(Arc::clone(&op));
+        Some(op)
+    }
+
+    fn revision(&self) -> usize {
+        self.operations.len()
+    }
+
+    fn operations(&self, since: usize) -> &[ArcOperation] {
+        self.operations.get(since..).unwrap_or_default()
+    }
+
+    /// The elements serialized to a JSON array
+    fn text(&self) -> String {
+        serde_json::to_string(&self.items).unwrap()
+    }
+
+    fn text_at(&self, revision: usize) -> Option<String> {
+        Some(serde_json::to_string(&self.items_at(revision)?).unwrap())
+    }
+
+    fn fork(&self, revision: usize) -> Option<Box<dyn DocumentTrait>> {
+        Some(Box::new(ListDocument {
+            items: self.items_at(revision)?,
+            operations: self.operations[..revision].to_vec(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::{ListDocument, SequenceAction, SequenceOperation};
+    use crate::ot::{document::DocumentTrait, operations::OperationTrait};
+
+    type Operation = SequenceOperation<char>;
+
+    fn apply(items: &str, operations: &[Operation]) -> String {
+        let mut items: Vec<char> = items.chars().collect();
+        for operation in operations {
+            assert!(operation.can_apply_to(items.len()), "{operation:?}");
+            operation.apply_to(&mut items);
+        }
+        items.into_iter().collect()
+    }
+
+    #[test]
+    fn operations_are_applied() {
+        let operations = [
+            Operation::insert(1, 0, vec!['x', 'y']),
+            Operation::delete(3, 0, 2),
+            Operation::move_element(0, 0, 4),
+            Operation::move_element(3, 0, 1),
+        ];
+        assert_eq!(apply("abcde", &operations), "xayde");
+    }
+
+    #[test]
+    fn deletion_keeps_concurrent_insertions() {
+        let mut delete = Operation::delete(1, 0, 3);
+        delete.transform_relative_to(&Operation::insert(2, 0, vec!['x']));
+        assert_eq!(
+            *delete.action(),
+            SequenceAction::Delete {
+                ranges: vec![1..2, 3..5]
+            }
+        );
+        assert_eq!(apply("abxcde", &[delete]), "axe");
+    }
+
+    #[test]
+    fn deletion_follows_moved_elements() {
+        // "c" is moved out of the deleted range, "e" into it
+        let mut delete = Operation::delete(1, 0, 3);
+        delete.transform_relative_to(&Operation::move_element(2, 0, 6));
+        delete.transform_relative_to(&Operation::move_element(3, 0, 2));
+        assert_eq!(apply("abedfc", &[delete]), "aef");
+    }
+
+    #[test]
+    fn moves_of_deleted_elements_are_dropped() {
+        let mut move_element = Operation::move_element(2, 0, 0);
+        move_element.transform_relative_to(&Operation::delete(1, 0, 2));
+        assert_eq!(
+            *move_element.action(),
+            SequenceAction::Delete { ranges: vec![] }
+        );
+
+        // the later of two moves of an element wins
+        let first = Operation::move_element(0, 0, 3);
+        let second = Operation::move_element(0, 0, 2);
+        let mut first_after_second = first.clone();
+        first_after_second.transform_relative_to_later(&second);
+        let mut second_after_first = second.clone();
+        second_after_first.transform_relative_to(&first);
+        assert_eq!(apply("abc", &[first, second_after_first]), "bac");
+        assert_eq!(apply("abc", &[second, first_after_second]), "bac");
+    }
+
+    #[test]
+    fn list_document_serializes_items() {
+        let mut document = ListDocument::<u32>::new();
+        assert!(document
+            .apply(Box::new(SequenceOperation::<u32>::insert(
+                0,
+                0,
+                vec![1, 2, 3]
+            )))
+            .is_some());
+        assert!(document
+            .apply(Box::new(SequenceOperation::<u32>::delete(2, 0, 1)))
+            .is_none());
+        assert!(document
+            .apply(Box::new(SequenceOperation::<u32>::move_element(0, 1, 3)))
+            .is_some());
+
+        assert_eq!(document.items(), [2, 3, 1]);
+        assert_eq!(document.text(), "[2,3,1]");
+    }
+
+    const LEN: usize = 6;
+
+    /// Operation on a sequence of [`LEN`] elements
+    fn sequence_operation() -> impl Strategy<Value = Operation> {
+        let range = (0..LEN).prop_flat_map(|start| (Just(start), 0..=LEN - start));
+        prop_oneof![
+            (0..=LEN, "[x-z]{1,2}").prop_map(|(position, items)| Operation::insert(
+                position,
+                0,
+                items.chars().collect()
+            )),
+            range.prop_map(|(position, len)| Operation::delete(position, 0, len)),
+            (0..LEN, 0..=LEN).prop_map(|(from, to)| Operation::move_element(from, 0, to)),
+        ]
+    }
+
+    /// Operation on a sequence of [`LEN`] elements and a pair of concurrent ones,
+    /// the transformed operations can delete several ranges
+    fn operations() -> impl Strategy<Value = (Operation, Operation, Operation)> {
+        (
+            sequence_operation(),
+            sequence_operation(),
+            sequence_operation(),
+        )
+            .prop_map(|(base, first, mut second)| {
+                second.transform_relative_to(&base);
+                (base, first, second)
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn concurrent_operations_converge((base, first, second) in operations()) {
+            // the first operation is based on the same sequence as the transformed second one
+            let mut first = first;
+            first.transform_relative_to_later(&base);
+            let start = apply("abcdef", &[base]);
+
+            let mut second_after_first = second.clone();
+            second_after_first.transform_relative_to(&first);
+            let mut first_after_second = first.clone();
+            first_after_second.transform_relative_to_later(&second);
+
+            prop_assert_eq!(
+                apply(&start, &[first.clone(), second_after_first]),
+                apply(&start, &[second.clone(), first_after_second]),
+                "first: {:?}, second: {:?}", first, second
+            );
+        }
+    }
+}
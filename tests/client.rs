@@ -0,0 +1,249 @@
+//! The client library against a real server on a random port
+
+use std::{
+    net::TcpListener,
+    time::{Duration, Instant},
+};
+
+use futures_util::{SinkExt, StreamExt};
+use rust_live_server::{
+    api::{get_server_future_on, start_on},
+    client::{Client, ClientError, ClientEvent, Config},
+};
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+type Connection = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+fn config(port: u16, client: &str) -> Config {
+    let mut config = Config::new(
+        format!("ws://127.0.0.1:{port}/ws"),
+        client.to_string(),
+        String::from("doc"),
+    );
+    config.reconnect_delay = Duration::from_millis(50);
+    config
+}
+
+/// Waits until the text of the client satisfies `condition`
+async fn wait_for(client: &Client, condition: impl Fn(&str) -> bool) {
+    let mut text = client.watch_text();
+    let wait = text.wait_for(|text| condition(text));
+    tokio::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .unwrap_or_else(|_| panic!("text {:?} doesn't change", client.text()))
+        .unwrap();
+}
+
+/// Connects without the client library in the fourth protocol version
+/// and subscribes to the document
+async fn connect_raw(port: u16, client: &str) -> Connection {
+    let url = format!("ws://127.0.0.1:{port}/ws?client={client}");
+    let (mut connection, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    send_raw(&mut connection, json!({"type": "hello", "version": 4})).await;
+    receive_raw(&mut connection, "hello").await;
+    send_raw(
+        &mut connection,
+        json!({"type": "subscribe", "document": "doc", "since": 0}),
+    )
+    .await;
+    receive_raw(&mut connection, "subscribed").await;
+    connection
+}
+
+async fn send_raw(connection: &mut Connection, message: Value) {
+    let message = Message::Text(message.to_string());
+    connection.send(message).await.unwrap();
+}
+
+/// Waits for the message of the type, the other messages are skipped
+async fn receive_raw(connection: &mut Connection, kind: &str) -> Value {
+    let receive = async {
+        loop {
+            let text = match connection.next().await {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                message => panic!("unexpected message {message:?}"),
+            };
+            let message: Value = serde_json::from_str(&text).unwrap();
+            if message["type"] == kind {
+                return message;
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), receive)
+        .await
+        .unwrap_or_else(|_| panic!("{kind} isn't received"))
+}
+
+fn start_server() -> u16 {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(get_server_future_on(listener));
+    port
+}
+
+#[actix_web::test]
+async fn concurrent_edits_converge() {
+    let port = start_server();
+    let alice = Client::connect(config(port, "alice"));
+    alice.insert(0, String::from("hello")).await.unwrap();
+
+    let bob = Client::connect(config(port, "bob"));
+    wait_for(&bob, |text| text == "hello").await;
+
+    // both edit before they see each other: alice only after "hello" and bob only before it,
+    // so the positions are right whichever edits of the other one have arrived
+    alice.insert(5, String::from(" world")).await.unwrap();
+    alice.insert(11, String::from("!")).await.unwrap();
+    bob.insert(0, String::from(">> ")).await.unwrap();
+    bob.delete(3, 1).await.unwrap();
+
+    wait_for(&alice, |text| text == ">> ello world!").await;
+    wait_for(&bob, |text| text == ">> ello world!").await;
+}
+
+#[actix_web::test]
+async fn only_the_submitting_connection_is_acknowledged() {
+    let port = start_server();
+    let alice = Client::connect(config(port, "alice"));
+    alice.insert(0, String::from("hello")).await.unwrap();
+    alice.synchronized().await;
+
+    // e.g. alice in another tab
+    let mut tab = connect_raw(port, "alice").await;
+    send_raw(
+        &mut tab,
+        json!({"type": "operation", "document": "doc", "id": "tab-1",
+            "kind": "INSERT", "position": 5, "revision": 1, "content": "!"}),
+    )
+    .await;
+    assert_eq!(receive_raw(&mut tab, "ack").await["revision"], 1);
+
+    wait_for(&alice, |text| text == "hello!").await;
+    alice.insert(0, String::from(">")).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), alice.synchronized())
+        .await
+        .expect("the edit isn't acknowledged");
+    let operation = receive_raw(&mut tab, "operation").await;
+    assert_eq!(operation["revision"], 2);
+    assert!(operation["id"].as_str().unwrap().starts_with("alice-"));
+}
+
+#[actix_web::test]
+async fn rejected_operations_are_reported() {
+    let port = start_server();
+    let alice = Client::connect(config(port, "alice"));
+    alice.insert(0, String::from("hello")).await.unwrap();
+    alice.synchronized().await;
+    let mut bob = connect_raw(port, "bob").await;
+
+    let operations = [
+        // out of the text
+        json!({"kind": "DELETE", "position": 3, "revision": 1, "length": 5}),
+        // based on a revision the server doesn't have
+        json!({"kind": "INSERT", "position": 0, "revision": 7, "content": "!"}),
+    ];
+    for (idx, mut operation) in operations.into_iter().enumerate() {
+        let id = format!("bob-{idx}");
+        operation["type"] = json!("operation");
+        operation["document"] = json!("doc");
+        operation["id"] = json!(id);
+        send_raw(&mut bob, operation).await;
+
+        let error = receive_raw(&mut bob, "error").await;
+        assert_eq!(error["document"], "doc");
+        assert_eq!(error["id"], id);
+    }
+    assert_eq!(alice.text(), "hello");
+}
+
+#[actix_web::test]
+async fn invalid_edits_are_refused() {
+    let port = start_server();
+    let alice = Client::connect(config(port, "alice"));
+
+    assert_eq!(alice.delete(0, 1).await, Err(ClientError::InvalidOperation));
+    assert_eq!(alice.text(), "");
+}
+
+#[actix_web::test]
+async fn edits_made_offline_are_sent_after_connecting() {
+    // the port is free until the server starts
+    let port = TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let alice = Client::connect(config(port, "alice"));
+    alice.insert(0, String::from("offline")).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+    tokio::spawn(get_server_future_on(listener));
+
+    let bob = Client::connect(config(port, "bob"));
+    wait_for(&bob, |text| text == "offline").await;
+    assert_eq!(alice.text(), "offline");
+}
+
+#[actix_web::test]
+async fn cursors_are_shared() {
+    let port = start_server();
+    let alice = Client::connect(config(port, "alice"));
+    alice.insert(0, String::from("hello")).await.unwrap();
+    alice.set_cursor(5).await.unwrap();
+
+    let bob = Client::connect(config(port, "bob"));
+    let mut events = bob.subscribe();
+    let presence = async {
+        loop {
+            if let ClientEvent::Presence(client, cursor) = events.recv().await.unwrap() {
+                return (client, cursor);
+            }
+        }
+    };
+    let presence = tokio::time::timeout(Duration::from_secs(5), presence)
+        .await
+        .expect("the cursor of alice isn't received");
+    assert_eq!(presence, (String::from("alice"), Some(5)));
+
+    drop(alice);
+    let left = async {
+        loop {
+            if let ClientEvent::Presence(_, None) = events.recv().await.unwrap() {
+                return;
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), left)
+        .await
+        .expect("alice doesn't leave");
+}
+
+#[actix_web::test]
+async fn shutdown_closes_connections() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (server, shutdown) = start_on(listener);
+    let server = tokio::spawn(server);
+
+    let alice = Client::connect(config(port, "alice"));
+    alice.insert(0, String::from("hello")).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), alice.synchronized())
+        .await
+        .expect("the edit isn't acknowledged");
+    let mut events = alice.subscribe();
+
+    let start = Instant::now();
+    shutdown.shutdown(Duration::from_secs(5)).await;
+    assert!(start.elapsed() < Duration::from_secs(5));
+    server.await.unwrap().unwrap();
+
+    let disconnected =
+        async { while !matches!(events.recv().await.unwrap(), ClientEvent::Disconnected) {} };
+    tokio::time::timeout(Duration::from_secs(1), disconnected)
+        .await
+        .expect("the client isn't disconnected");
+}
@@ -0,0 +1,329 @@
+//! Deterministic simulation of clients editing one document through [`Manager`].
+//!
+//! Every client keeps its own [`ClientDocument`], the messages between the clients
+//! and the server go through a simulated network with random delays, so messages
+//! of different clients are reordered. Clients also lose their connection, keep editing
+//! offline and catch up after reconnecting. When the network is quiet, every client
+//! must have the text of the server.
+//!
+//! The runs depend only on the seed. A failed seed can be replayed with
+//! `SIMULATION_SEED=<seed> cargo test --test simulation`.
+
+use std::collections::{BTreeMap, HashMap};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_live_server::{
+    collaboration::{manager::Manager, sessions::Submission},
+    ot::{
+        client::ClientDocument,
+        operations::{
+            ArcOperation, DeleteOperation, InsertOperation, MoveOperation, Operation,
+            ReplaceOperation, TransactionOperation,
+        },
+    },
+};
+
+const DOCUMENT: &str = "simulation";
+
+struct Config {
+    clients: usize,
+    edits: usize,
+    /// Maximum delay of a message in ticks
+    max_delay: u64,
+    /// Probability that a step is a disconnection or a reconnection
+    disconnect_probability: f64,
+}
+
+enum Message {
+    ToServer(usize, Operation),
+    /// The client has to receive the operation with the revision
+    ToClient(usize, usize),
+}
+
+impl Message {
+    fn client(&self) -> usize {
+        match self {
+            Message::ToServer(client, _) | Message::ToClient(client, _) => *client,
+        }
+    }
+}
+
+struct VirtualClient {
+    name: String,
+    document: ClientDocument,
+    connected: bool,
+}
+
+struct Simulation {
+    seed: u64,
+    rng: StdRng,
+    config: Config,
+    manager: Manager,
+    clients: Vec<VirtualClient>,
+    /// Applied operations and the clients who sent them
+    history: Vec<(ArcOperation, usize)>,
+    now: u64,
+    /// Messages by delivery time, the second key keeps the order of sending
+    queue: BTreeMap<(u64, u64), Message>,
+    sent: u64,
+    /// Delivery time of the last message of a link, links deliver in order
+    links: HashMap<(usize, bool), u64>,
+    log: Vec<String>,
+}
+
+impl Simulation {
+    fn new(seed: u64, config: Config) -> Self {
+        let manager = Manager::new();
+        let clients = (0..config.clients)
+            .map(|idx| {
+                let name = format!("client {idx}");
+                let _connection = manager.connect(name.clone(), DOCUMENT.to_string());
+                VirtualClient {
+                    name,
+                    document: ClientDocument::new(String::new(), 0),
+                    connected: true,
+                }
+            })
+            .collect();
+
+        Simulation {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            config,
+            manager,
+            clients,
+            history: vec![],
+            now: 0,
+            queue: BTreeMap::new(),
+            sent: 0,
+            links: HashMap::new(),
+            log: vec![],
+        }
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        let mut edits = 0;
+        while edits < self.config.edits {
+            let step = self.rng.gen::<f64>();
+            if step < self.config.disconnect_probability {
+                let client = self.rng.gen_range(0..self.clients.len());
+                match self.clients[client].connected {
+                    true => self.disconnect(client),
+                    false => self.reconnect(client),
+                }
+            } else if step < 0.5 || self.queue.is_empty() {
+                let client = self.rng.gen_range(0..self.clients.len());
+                self.edit(client);
+                edits += 1;
+            } else {
+                self.deliver()?;
+            }
+        }
+
+        for client in 0..self.clients.len() {
+            if !self.clients[client].connected {
+                self.reconnect(client);
+            }
+        }
+        while !self.queue.is_empty() {
+            self.deliver()?;
+        }
+
+        let server_text = self.manager.text(DOCUMENT).unwrap();
+        for client in &self.clients {
+            if client.document.text() != server_text {
+                return Err(format!(
+                    "{} has {:?}, but the server has {:?}",
+                    client.name,
+                    client.document.text(),
+                    server_text
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn edit(&mut self, client: usize) {
+        let text_len = self.clients[client].document.text().len();
+        let operation: Operation = if text_len == 0 || self.rng.gen_bool(0.6) {
+            let position = self.rng.gen_range(0..=text_len);
+            let text = (0..self.rng.gen_range(1..=3))
+                .map(|_| self.rng.gen_range(b'a'..=b'z') as char)
+                .collect();
+            Box::new(InsertOperation::new(position, 0, text))
+        } else if self.rng.gen_bool(0.3) {
+            let position = self.rng.gen_range(0..text_len);
+            let len = self.rng.gen_range(1..=(text_len - position).min(3));
+            Box::new(DeleteOperation::new(position, 0, len))
+        } else if self.rng.gen_bool(0.5) {
+            let position = self.rng.gen_range(0..text_len);
+            let len = self.rng.gen_range(1..=(text_len - position).min(4));
+            // a position outside the moved range
+            let to = self.rng.gen_range(0..=text_len - len);
+            let to = if to <= position { to } else { to + len };
+            Box::new(MoveOperation::new(position, 0, len, to))
+        } else if self.rng.gen_bool(0.5) {
+            Box::new(self.replace(text_len))
+        } else {
+            // replaces two places at once like a replace-all
+            let first = self.replace(text_len);
+            let text_len = text_len - first.delete().len() + first.insert().text().len();
+            let second = self.replace(text_len);
+            Box::new(TransactionOperation::new(
+                0,
+                vec![Box::new(first), Box::new(second)],
+            ))
+        };
+        self.log.push(format!(
+            "{}: {} edits {operation:?}",
+            self.now, self.clients[client].name
+        ));
+
+        if let Some(operation) = self.clients[client].document.edit(operation) {
+            self.send(Message::ToServer(client, operation));
+        }
+    }
+
+    fn replace(&mut self, text_len: usize) -> ReplaceOperation {
+        let position = self.rng.gen_range(0..=text_len);
+        let len = self.rng.gen_range(0..=(text_len - position).min(3));
+        let text = (0..self.rng.gen_range(0..=3))
+            .map(|_| self.rng.gen_range(b'a'..=b'z') as char)
+            .collect();
+        ReplaceOperation::new(position, 0, len, text)
+    }
+
+    fn send(&mut self, message: Message) {
+        let client = message.client();
+        if !self.clients[client].connected {
+            return;
+        }
+
+        let link = (client, matches!(message, Message::ToServer(..)));
+        let delay = self.rng.gen_range(0..=self.config.max_delay);
+        let delivery = (self.now + delay).max(*self.links.get(&link).unwrap_or(&0));
+        self.links.insert(link, delivery);
+
+        self.sent += 1;
+        self.queue.insert((delivery, self.sent), message);
+    }
+
+    fn deliver(&mut self) -> Result<(), String> {
+        let Some(((time, _), message)) = self.queue.pop_first() else {
+            return Ok(());
+        };
+        self.now = time;
+
+        match message {
+            Message::ToServer(client, operation) => {
+                let client_name = &self.clients[client].name;
+                let operation = self
+                    .manager
+                    .submit(DOCUMENT, Submission::new(client_name.clone(), operation))
+                    .map_err(|err| err.to_string())?;
+                self.log.push(format!(
+                    "{}: server applies {operation:?} from {client_name}",
+                    self.now
+                ));
+
+                self.history.push((operation, client));
+                let revision = self.history.len() - 1;
+                for receiver in 0..self.clients.len() {
+                    self.send(Message::ToClient(receiver, revision));
+                }
+            }
+            Message::ToClient(client, revision) => {
+                if self.clients[client].document.revision() != revision {
+                    return Err(format!(
+                        "{} expects revision {}, but receives {revision}",
+                        self.clients[client].name,
+                        self.clients[client].document.revision()
+                    ));
+                }
+                self.receive(client, revision);
+            }
+        }
+        Ok(())
+    }
+
+    /// Delivers the operation from the history to the client
+    fn receive(&mut self, client: usize, revision: usize) {
+        let (operation, origin) = &self.history[revision];
+        let document = &mut self.clients[client].document;
+        if *origin != client {
+            document.receive(operation.as_ref().as_ref());
+        } else if let Some(next) = document.acknowledge() {
+            self.send(Message::ToServer(client, next));
+        }
+    }
+
+    /// Drops the connection with all messages on its way
+    fn disconnect(&mut self, client: usize) {
+        self.log.push(format!(
+            "{}: {} disconnects",
+            self.now, self.clients[client].name
+        ));
+        self.clients[client].connected = false;
+        self.queue.retain(|_, message| message.client() != client);
+        self.links
+            .retain(|(link_client, _), _| *link_client != client);
+    }
+
+    /// Catches up with the server history and sends the operation which the server hasn't got
+    fn reconnect(&mut self, client: usize) {
+        self.log.push(format!(
+            "{}: {} reconnects",
+            self.now, self.clients[client].name
+        ));
+
+        // nothing is sent while catching up, the operation to send is known after it
+        self.clients[client].connected = false;
+        for revision in self.clients[client].document.revision()..self.history.len() {
+            self.receive(client, revision);
+        }
+        self.clients[client].connected = true;
+
+        if let Some(operation) = self.clients[client].document.pending() {
+            self.send(Message::ToServer(client, operation));
+        }
+    }
+}
+
+fn run_seeds(seeds: impl Iterator<Item = u64>, config: impl Fn() -> Config) {
+    let seeds: Vec<u64> = match std::env::var("SIMULATION_SEED") {
+        Ok(seed) => vec![seed.parse().expect("SIMULATION_SEED must be a number")],
+        Err(_) => seeds.collect(),
+    };
+
+    for seed in seeds {
+        let mut simulation = Simulation::new(seed, config());
+        if let Err(err) = simulation.run() {
+            let log_start = simulation.log.len().saturating_sub(30);
+            panic!(
+                "Simulation with seed {} failed: {err}\nLast events:\n{}",
+                simulation.seed,
+                simulation.log[log_start..].join("\n")
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn clients_converge() {
+    run_seeds(0..64, || Config {
+        clients: 4,
+        edits: 200,
+        max_delay: 10,
+        disconnect_probability: 0.0,
+    });
+}
+
+#[tokio::test]
+async fn clients_converge_after_disconnects() {
+    run_seeds(0..64, || Config {
+        clients: 5,
+        edits: 300,
+        max_delay: 20,
+        disconnect_probability: 0.05,
+    });
+}